@@ -0,0 +1,3432 @@
+//! VibeHub 的协议转换库：Anthropic <-> OpenAI，以及 OpenAI Responses <-> Chat Completions。
+//! 参考: https://github.com/CassiopeiaCode/b4u2cc
+//!
+//! 纯转换逻辑，只依赖 serde/serde_json/uuid，不依赖 Tauri/axum，可以脱离桌面应用单独使用和测试。
+//! 以 serde_json::Value 为参数的函数是主要 API，字节版本只是解析/序列化的薄封装。
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// 转换后 system 提示使用的角色 (o1/o3 等新模型要求使用 developer)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemRole {
+    #[default]
+    System,
+    Developer,
+}
+
+impl SystemRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SystemRole::System => "system",
+            SystemRole::Developer => "developer",
+        }
+    }
+}
+
+/// 协议转换选项 (来自供应商配置)
+#[derive(Debug, Clone, Default)]
+pub struct ConversionOptions {
+    /// 模型名称映射表，将请求中的模型名映射到目标模型名
+    pub model_mapping: HashMap<String, String>,
+    /// max_tokens 上限，超过时截断
+    pub max_tokens_cap: Option<u64>,
+    /// 客户端未指定 max_tokens 时使用的默认值
+    pub max_tokens_default: Option<u64>,
+    /// system 提示使用的角色
+    pub system_role: SystemRole,
+    /// 上游的推理参数名 (如 "reasoning_effort"、"thinking")，为空时丢弃 Anthropic 的 thinking 配置
+    pub reasoning_param_name: Option<String>,
+    /// 插入到 system 提示开头的全局前缀 (来自网关配置)
+    pub system_prompt_prefix: Option<String>,
+    /// 追加到 system 提示末尾的全局后缀 (来自网关配置)
+    pub system_prompt_suffix: Option<String>,
+    /// 合并相邻的同角色消息 (部分严格的上游不接受连续的同角色消息)
+    pub merge_consecutive_roles: bool,
+    /// 上游支持的 stop 序列最大数量 (None 时为 4)，超出部分截断
+    pub max_stop_sequences: Option<usize>,
+    /// 只有一个 stop 序列时以字符串而不是数组发送
+    pub single_stop_as_string: bool,
+    /// 流式请求不发送 stream_options.include_usage (部分上游见到该参数会返回 400)，输出用量改为按文本估算
+    pub disable_stream_usage: bool,
+}
+
+// OpenAI Chat Completions 的 stop 最多支持 4 个序列
+const DEFAULT_MAX_STOP_SEQUENCES: usize = 4;
+
+/// 协议转换错误
+#[derive(Debug, Clone)]
+pub enum ConversionError {
+    /// 客户端请求本身有误 (格式错误、缺少字段)，换供应商也无法成功
+    InvalidRequest(String),
+    /// 转换过程内部错误
+    Internal(String),
+}
+
+impl std::fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConversionError::InvalidRequest(msg) => write!(f, "Invalid request: {}", msg),
+            ConversionError::Internal(msg) => write!(f, "Conversion error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// 字节版本请求转换的结果
+#[derive(Debug, Clone)]
+pub struct ConvertedRequest {
+    pub body: Vec<u8>,
+    /// 超出上游支持数量而被丢弃的 stop 序列数 (由调用方决定是否记录警告)
+    pub dropped_stop_sequences: usize,
+}
+
+/// 将 Anthropic Messages API 请求转换为 OpenAI Chat Completions 格式 (字节版本)
+pub fn anthropic_to_openai(body: &[u8], options: &ConversionOptions) -> Result<ConvertedRequest, ConversionError> {
+    let anthropic_req: Value = serde_json::from_slice(body)
+        .map_err(|e| ConversionError::InvalidRequest(format!("Failed to parse Anthropic request: {}", e)))?;
+    let openai_req = anthropic_request_to_openai(&anthropic_req, options)?;
+    let body = serde_json::to_vec(&openai_req)
+        .map_err(|e| ConversionError::Internal(format!("Failed to serialize OpenAI request: {}", e)))?;
+    Ok(ConvertedRequest {
+        body,
+        dropped_stop_sequences: dropped_stop_sequences(&anthropic_req, options),
+    })
+}
+
+/// 转换时会被丢弃的 stop 序列数：非空序列超过上游支持的数量 (max_stop_sequences，默认 4) 的部分
+pub fn dropped_stop_sequences(anthropic_req: &Value, options: &ConversionOptions) -> usize {
+    let count = anthropic_req.get("stop_sequences")
+        .and_then(|s| s.as_array())
+        .map(|s| s.iter().filter(|s| s.as_str().is_some_and(|s| !s.is_empty())).count())
+        .unwrap_or(0);
+    count.saturating_sub(options.max_stop_sequences.unwrap_or(DEFAULT_MAX_STOP_SEQUENCES))
+}
+
+/// 将 Anthropic Messages API 请求转换为 OpenAI Responses API 格式 (字节版本)，用于只支持 /v1/responses 的上游
+pub fn anthropic_to_responses(body: &[u8], options: &ConversionOptions) -> Result<ConvertedRequest, ConversionError> {
+    let anthropic_req: Value = serde_json::from_slice(body)
+        .map_err(|e| ConversionError::InvalidRequest(format!("Failed to parse Anthropic request: {}", e)))?;
+    let responses_req = anthropic_request_to_responses(&anthropic_req, options)?;
+    let body = serde_json::to_vec(&responses_req)
+        .map_err(|e| ConversionError::Internal(format!("Failed to serialize Responses request: {}", e)))?;
+    Ok(ConvertedRequest {
+        body,
+        dropped_stop_sequences: dropped_stop_sequences(&anthropic_req, options),
+    })
+}
+
+/// 在 Anthropic 请求的 system 提示前后加入全局前缀/后缀
+///
+/// 同时支持字符串和文本块数组两种 system 格式；已包含前缀/后缀时不再重复添加
+/// (多轮对话中客户端可能原样回传上一轮的 system)。请求没有 system 时直接以前缀/后缀作为 system。
+pub fn apply_system_prompt_affixes(request: &mut Value, prefix: Option<&str>, suffix: Option<&str>) {
+    let prefix = prefix.filter(|p| !p.trim().is_empty());
+    let suffix = suffix.filter(|s| !s.trim().is_empty());
+    if prefix.is_none() && suffix.is_none() {
+        return;
+    }
+    let Some(obj) = request.as_object_mut() else {
+        return;
+    };
+    
+    match obj.get_mut("system") {
+        Some(Value::String(system)) => {
+            if let Some(prefix) = prefix {
+                if !system.starts_with(prefix) {
+                    *system = if system.is_empty() { prefix.to_string() } else { format!("{}\n\n{}", prefix, system) };
+                }
+            }
+            if let Some(suffix) = suffix {
+                if !system.ends_with(suffix) {
+                    *system = if system.is_empty() { suffix.to_string() } else { format!("{}\n\n{}", system, suffix) };
+                }
+            }
+        }
+        Some(Value::Array(blocks)) => {
+            let block_text = |block: Option<&Value>| {
+                block.and_then(|b| b.get("text")).and_then(|t| t.as_str()).unwrap_or("").to_string()
+            };
+            if let Some(prefix) = prefix {
+                if !block_text(blocks.first()).starts_with(prefix) {
+                    blocks.insert(0, json!({"type": "text", "text": prefix}));
+                }
+            }
+            if let Some(suffix) = suffix {
+                if !block_text(blocks.last()).ends_with(suffix) {
+                    blocks.push(json!({"type": "text", "text": suffix}));
+                }
+            }
+        }
+        None | Some(Value::Null) => {
+            let system = [prefix, suffix].into_iter().flatten().collect::<Vec<_>>().join("\n\n");
+            obj.insert("system".to_string(), Value::String(system));
+        }
+        // system 格式无效时保持原样，由上游返回校验错误
+        Some(_) => {}
+    }
+}
+
+/// 将 Anthropic Messages API 请求转换为 OpenAI Chat Completions 格式
+pub fn anthropic_request_to_openai(anthropic_req: &Value, options: &ConversionOptions) -> Result<Value, ConversionError> {
+    // 配置了全局 system 前缀/后缀时，先在 Anthropic 请求上注入，再统一转换
+    let with_affixes;
+    let anthropic_req = if options.system_prompt_prefix.is_some() || options.system_prompt_suffix.is_some() {
+        let mut req = anthropic_req.clone();
+        apply_system_prompt_affixes(&mut req, options.system_prompt_prefix.as_deref(), options.system_prompt_suffix.as_deref());
+        with_affixes = req;
+        &with_affixes
+    } else {
+        anthropic_req
+    };
+    
+    let mut openai_messages = Vec::new();
+    
+    // 处理 system 字段
+    let system_role = options.system_role.as_str();
+    if let Some(system) = anthropic_req.get("system") {
+        if let Some(system_str) = system.as_str() {
+            openai_messages.push(json!({
+                "role": system_role,
+                "content": system_str
+            }));
+        } else if let Some(system_arr) = system.as_array() {
+            // Anthropic 的 system 可以是数组格式
+            let mut system_content = String::new();
+            for item in system_arr {
+                if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
+                    system_content.push_str(text);
+                    system_content.push('\n');
+                }
+            }
+            if !system_content.is_empty() {
+                openai_messages.push(json!({
+                    "role": system_role,
+                    "content": system_content.trim()
+                }));
+            }
+        }
+    }
+    
+    // 转换 messages
+    if let Some(messages) = anthropic_req.get("messages").and_then(|m| m.as_array()) {
+        for msg in messages {
+            let role = msg.get("role").and_then(|r| r.as_str()).unwrap_or("user");
+            let openai_role = match role {
+                "user" => "user",
+                "assistant" => "assistant",
+                // messages 中内联的 system 消息 (非标准，但部分客户端/工具会注入)：保持原位置，按供应商配置的系统角色发送
+                "system" | "developer" => system_role,
+                // 其他未知角色按用户消息处理
+                _ => "user",
+            };
+            
+            // 处理 content
+            if let Some(content) = msg.get("content") {
+                if let Some(content_str) = content.as_str() {
+                    openai_messages.push(json!({
+                        "role": openai_role,
+                        "content": content_str
+                    }));
+                } else if let Some(content_arr) = content.as_array() {
+                    // 多模态内容块
+                    let mut text_parts = Vec::new();
+                    let mut tool_calls = Vec::new();
+                    let mut tool_messages = Vec::new();
+                    for block in content_arr {
+                        if let Some(block_type) = block.get("type").and_then(|t| t.as_str()) {
+                            match block_type {
+                                "text" => {
+                                    if let Some(text) = block.get("text").and_then(|t| t.as_str()) {
+                                        text_parts.push(text.to_string());
+                                    }
+                                }
+                                "tool_use" => {
+                                    // 工具调用转换为 assistant 消息的 tool_calls (arguments 为 JSON 字符串)
+                                    let input = block.get("input").cloned().unwrap_or_else(|| json!({}));
+                                    tool_calls.push(json!({
+                                        "id": block.get("id").cloned().unwrap_or(Value::Null),
+                                        "type": "function",
+                                        "function": {
+                                            "name": block.get("name").cloned().unwrap_or(Value::Null),
+                                            "arguments": input.to_string()
+                                        }
+                                    }));
+                                }
+                                "tool_result" => {
+                                    // 工具结果转换为 role: "tool" 消息，按 tool_call_id 关联对应的调用
+                                    let mut result_text = tool_result_text(block);
+                                    if block.get("is_error").and_then(|e| e.as_bool()).unwrap_or(false) {
+                                        result_text = format!("Error: {}", result_text);
+                                    }
+                                    tool_messages.push(json!({
+                                        "role": "tool",
+                                        "tool_call_id": block.get("tool_use_id").cloned().unwrap_or(Value::Null),
+                                        "content": result_text
+                                    }));
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    
+                    // tool 消息必须紧跟在发起调用的 assistant 消息之后，先于同一轮中的其他文本
+                    openai_messages.extend(tool_messages);
+                    if !tool_calls.is_empty() {
+                        let text = text_parts.join("\n");
+                        openai_messages.push(json!({
+                            "role": "assistant",
+                            "content": if text.is_empty() { Value::Null } else { Value::String(text) },
+                            "tool_calls": tool_calls
+                        }));
+                    } else if !text_parts.is_empty() {
+                        openai_messages.push(json!({
+                            "role": openai_role,
+                            "content": text_parts.join("\n")
+                        }));
+                    }
+                }
+            }
+        }
+    }
+    
+    if options.merge_consecutive_roles {
+        openai_messages = merge_consecutive_roles(openai_messages);
+    }
+    
+    // 构建 OpenAI 请求
+    // 获取原始模型名称，并应用模型映射
+    let original_model = anthropic_req.get("model")
+        .and_then(|m| m.as_str())
+        .ok_or_else(|| ConversionError::InvalidRequest("Missing 'model' field in request".to_string()))?;
+    
+    // 应用模型映射：如果在映射表中找到，则使用映射后的模型名
+    let model = options.model_mapping.get(original_model)
+        .map(|s| s.as_str())
+        .unwrap_or(original_model);
+    
+    let mut max_tokens = anthropic_req.get("max_tokens")
+        .and_then(|m| m.as_u64())
+        .or(options.max_tokens_default)
+        .unwrap_or(4096);
+    
+    // 上游限制的 max_tokens 上限 (静默截断)
+    if let Some(cap) = options.max_tokens_cap {
+        max_tokens = max_tokens.min(cap);
+    }
+    
+    let temperature = anthropic_req.get("temperature")
+        .and_then(|t| t.as_f64())
+        .unwrap_or(1.0);
+    
+    let stream = anthropic_req.get("stream")
+        .and_then(|s| s.as_bool())
+        .unwrap_or(false);
+    
+    let mut openai_req = json!({
+        "model": model,
+        "messages": openai_messages,
+        "max_tokens": max_tokens,
+        "temperature": temperature,
+        "stream": stream
+    });
+    // 流式响应末尾需要 usage 块才能统计真实的输出 token
+    if stream && !options.disable_stream_usage {
+        openai_req["stream_options"] = json!({"include_usage": true});
+    }
+    
+    if let Some(stop) = anthropic_req.get("stop_sequences").and_then(|s| convert_stop_sequences(s, options)) {
+        openai_req["stop"] = stop;
+    }
+    
+    // metadata.user_id 对应 OpenAI 的 user (用于按用户追踪和上游的滥用控制)
+    if let Some(user_id) = anthropic_req.pointer("/metadata/user_id").and_then(|u| u.as_str()).filter(|u| !u.is_empty()) {
+        openai_req["user"] = json!(user_id);
+    }
+    
+    // 工具定义：Anthropic 的 input_schema 对应 OpenAI 的 function.parameters
+    // 没有 input_schema 的服务端工具 (如 web_search) 上游无法执行，直接忽略
+    if let Some(tools) = anthropic_req.get("tools").and_then(|t| t.as_array()) {
+        let openai_tools: Vec<Value> = tools.iter()
+            .filter_map(|tool| {
+                let schema = tool.get("input_schema")?;
+                let mut function = json!({
+                    "name": tool.get("name")?,
+                    "parameters": schema
+                });
+                if let Some(description) = tool.get("description") {
+                    function["description"] = description.clone();
+                }
+                Some(json!({"type": "function", "function": function}))
+            })
+            .collect();
+        if !openai_tools.is_empty() {
+            openai_req["tools"] = Value::Array(openai_tools);
+        }
+    }
+    if let Some(tool_choice) = anthropic_req.get("tool_choice") {
+        if let Some(choice) = convert_tool_choice(tool_choice) {
+            openai_req["tool_choice"] = choice;
+        }
+        if tool_choice.get("disable_parallel_tool_use").and_then(|d| d.as_bool()) == Some(true) {
+            openai_req["parallel_tool_calls"] = json!(false);
+        }
+    }
+    
+    // 透传 thinking 配置 (按供应商配置的推理参数名转换)
+    if let (Some(param), Some(thinking)) = (&options.reasoning_param_name, anthropic_req.get("thinking")) {
+        if let Some(value) = convert_thinking(param, thinking) {
+            openai_req[param.as_str()] = value;
+        }
+    }
+    
+    Ok(openai_req)
+}
+
+/// Anthropic stop_sequences (数组) 转换为 OpenAI stop：
+/// 忽略空字符串，超过上游支持的数量时截断 (丢弃数量见 dropped_stop_sequences)；没有有效序列时返回 None (不发送 stop)
+fn convert_stop_sequences(stop_sequences: &Value, options: &ConversionOptions) -> Option<Value> {
+    let mut sequences: Vec<&str> = stop_sequences.as_array()?
+        .iter()
+        .filter_map(|s| s.as_str())
+        .filter(|s| !s.is_empty())
+        .collect();
+    let limit = options.max_stop_sequences.unwrap_or(DEFAULT_MAX_STOP_SEQUENCES);
+    sequences.truncate(limit);
+    
+    match sequences.as_slice() {
+        [] => None,
+        [single] if options.single_stop_as_string => Some(Value::String(single.to_string())),
+        _ => Some(json!(sequences)),
+    }
+}
+
+/// 合并相邻的同角色消息：按原顺序拼接文本内容 (以空行分隔)，assistant 的 tool_calls 依次合并。
+/// tool 消息各自对应一个工具调用 (tool_call_id)，不参与合并
+fn merge_consecutive_roles(messages: Vec<Value>) -> Vec<Value> {
+    let mut merged: Vec<Value> = Vec::with_capacity(messages.len());
+    for msg in messages {
+        let role = msg.get("role").and_then(|r| r.as_str()).unwrap_or("");
+        let Some(prev) = merged.last_mut()
+            .filter(|prev| role != "tool" && prev.get("role").and_then(|r| r.as_str()) == Some(role))
+        else {
+            merged.push(msg);
+            continue;
+        };
+        
+        let text = msg.get("content").and_then(|c| c.as_str()).unwrap_or("");
+        if !text.is_empty() {
+            let content = match prev.get("content").and_then(|c| c.as_str()) {
+                Some(existing) if !existing.is_empty() => format!("{}\n\n{}", existing, text),
+                _ => text.to_string(),
+            };
+            prev["content"] = Value::String(content);
+        }
+        if let Some(calls) = msg.get("tool_calls").and_then(|c| c.as_array()) {
+            match prev.get_mut("tool_calls").and_then(|c| c.as_array_mut()) {
+                Some(existing) => existing.extend(calls.iter().cloned()),
+                None => prev["tool_calls"] = Value::Array(calls.clone()),
+            }
+        }
+    }
+    merged
+}
+
+/// 提取 tool_result 块的文本内容 (content 可以是字符串或文本块数组)
+fn tool_result_text(block: &Value) -> String {
+    match block.get("content") {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(items)) => items.iter()
+            .filter_map(|item| item.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// 转换 tool_choice：auto -> "auto"，any -> "required"，none -> "none"，
+/// {type: "tool", name} -> {type: "function", function: {name}}
+fn convert_tool_choice(tool_choice: &Value) -> Option<Value> {
+    match tool_choice.get("type").and_then(|t| t.as_str())? {
+        "auto" => Some(json!("auto")),
+        "any" => Some(json!("required")),
+        "none" => Some(json!("none")),
+        "tool" => {
+            let name = tool_choice.get("name")?;
+            Some(json!({"type": "function", "function": {"name": name}}))
+        }
+        _ => None,
+    }
+}
+
+/// Anthropic /v1/messages 请求中允许的内容块类型
+const ANTHROPIC_CONTENT_BLOCK_TYPES: &[&str] = &[
+    "text", "image", "document", "tool_use", "tool_result", "thinking", "redacted_thinking",
+    "server_tool_use", "web_search_tool_result", "search_result",
+];
+
+/// 移除 Anthropic 请求历史中没有签名的 thinking 块，返回移除的数量
+///
+/// 代理转换模式下由 OpenAI 推理内容生成的 thinking 块没有签名 (signature 为空)，
+/// 客户端在后续轮次原样回传给原生 Anthropic 供应商时会被拒绝。移除后内容为空的 assistant 消息整条丢弃
+pub fn strip_unsigned_thinking(request: &mut Value) -> usize {
+    let Some(messages) = request.get_mut("messages").and_then(|m| m.as_array_mut()) else {
+        return 0;
+    };
+    
+    let mut removed = 0;
+    messages.retain_mut(|msg| {
+        if msg.get("role").and_then(|r| r.as_str()) != Some("assistant") {
+            return true;
+        }
+        let Some(blocks) = msg.get_mut("content").and_then(|c| c.as_array_mut()) else {
+            return true;
+        };
+        let before = blocks.len();
+        blocks.retain(|block| {
+            block.get("type").and_then(|t| t.as_str()) != Some("thinking")
+                || block.get("signature").and_then(|s| s.as_str()).is_some_and(|s| !s.is_empty())
+        });
+        removed += before - blocks.len();
+        before == blocks.len() || !blocks.is_empty()
+    });
+    removed
+}
+
+/// 严格校验 Anthropic /v1/messages 请求体，返回第一个不合法字段的路径和原因
+/// (如 `messages[2].content[0].type: unknown content block type 'foo'`)
+pub fn validate_anthropic_request(body: &[u8]) -> Result<(), String> {
+    let req: Value = serde_json::from_slice(body)
+        .map_err(|e| format!("body: invalid JSON: {}", e))?;
+    let obj = req.as_object().ok_or("body: must be a JSON object")?;
+    
+    match obj.get("model") {
+        Some(Value::String(model)) if !model.trim().is_empty() => {}
+        Some(Value::String(_)) => return Err("model: must not be empty".to_string()),
+        Some(_) => return Err("model: must be a string".to_string()),
+        None => return Err("model: field is required".to_string()),
+    }
+    
+    match obj.get("max_tokens") {
+        Some(v) if v.as_u64().is_some_and(|n| n > 0) => {}
+        Some(_) => return Err("max_tokens: must be a positive integer".to_string()),
+        None => return Err("max_tokens: field is required".to_string()),
+    }
+    
+    if let Some(stream) = obj.get("stream") {
+        if !stream.is_boolean() {
+            return Err("stream: must be a boolean".to_string());
+        }
+    }
+    
+    match obj.get("system") {
+        None | Some(Value::String(_)) => {}
+        Some(Value::Array(blocks)) => {
+            for (i, block) in blocks.iter().enumerate() {
+                if block.get("type").and_then(|t| t.as_str()) != Some("text") {
+                    return Err(format!("system[{}].type: must be 'text'", i));
+                }
+                if !block.get("text").is_some_and(|t| t.is_string()) {
+                    return Err(format!("system[{}].text: must be a string", i));
+                }
+            }
+        }
+        Some(_) => return Err("system: must be a string or an array of text blocks".to_string()),
+    }
+    
+    let messages = match obj.get("messages") {
+        Some(Value::Array(messages)) if !messages.is_empty() => messages,
+        Some(Value::Array(_)) => return Err("messages: must contain at least one message".to_string()),
+        Some(_) => return Err("messages: must be an array".to_string()),
+        None => return Err("messages: field is required".to_string()),
+    };
+    for (i, msg) in messages.iter().enumerate() {
+        match msg.get("role").and_then(|r| r.as_str()) {
+            Some("user") | Some("assistant") => {}
+            Some(role) => return Err(format!("messages[{}].role: invalid role '{}' (expected 'user' or 'assistant')", i, role)),
+            None => return Err(format!("messages[{}].role: field is required", i)),
+        }
+        match msg.get("content") {
+            Some(Value::String(_)) => {}
+            Some(Value::Array(blocks)) => {
+                for (j, block) in blocks.iter().enumerate() {
+                    validate_content_block(block).map_err(|e| format!("messages[{}].content[{}].{}", i, j, e))?;
+                }
+            }
+            Some(_) => return Err(format!("messages[{}].content: must be a string or an array of content blocks", i)),
+            None => return Err(format!("messages[{}].content: field is required", i)),
+        }
+    }
+    
+    Ok(())
+}
+
+/// 校验单个内容块，错误信息以块内字段名开头
+fn validate_content_block(block: &Value) -> Result<(), String> {
+    let block_type = match block.get("type") {
+        Some(Value::String(t)) => t.as_str(),
+        Some(_) => return Err("type: must be a string".to_string()),
+        None => return Err("type: field is required".to_string()),
+    };
+    if !ANTHROPIC_CONTENT_BLOCK_TYPES.contains(&block_type) {
+        return Err(format!("type: unknown content block type '{}'", block_type));
+    }
+    
+    let require_string = |field: &str| {
+        if block.get(field).is_some_and(|v| v.is_string()) {
+            Ok(())
+        } else {
+            Err(format!("{}: must be a string for '{}' blocks", field, block_type))
+        }
+    };
+    match block_type {
+        "text" => require_string("text"),
+        "tool_use" => {
+            require_string("id")?;
+            require_string("name")
+        }
+        "tool_result" => require_string("tool_use_id"),
+        "image" | "document" => {
+            if block.get("source").is_some_and(|s| s.is_object()) {
+                Ok(())
+            } else {
+                Err(format!("source: must be an object for '{}' blocks", block_type))
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// 将 Anthropic 的 thinking 配置转换为上游推理参数
+/// - reasoning_effort：按 budget_tokens 映射为 low / medium / high
+/// - 其他参数名：原样透传 thinking 对象
+///
+/// thinking 未启用时返回 None
+fn convert_thinking(param: &str, thinking: &Value) -> Option<Value> {
+    if thinking.get("type").and_then(|t| t.as_str()) != Some("enabled") {
+        return None;
+    }
+    match param {
+        "reasoning_effort" => {
+            let budget = thinking.get("budget_tokens").and_then(|b| b.as_u64()).unwrap_or(0);
+            let effort = if budget < 4096 {
+                "low"
+            } else if budget < 16384 {
+                "medium"
+            } else {
+                "high"
+            };
+            Some(json!(effort))
+        }
+        _ => Some(thinking.clone()),
+    }
+}
+
+/// 流式转换中当前打开的内容块类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StreamBlock {
+    Text,
+    Thinking,
+    // 工具调用，参数为 OpenAI tool_calls 中的 index
+    ToolUse(usize),
+}
+
+/// 尚未打开内容块的工具调用：名称可能分多个分片到达，
+/// 收到第一段参数 (或下一个内容块/结束) 时才以完整的 id 和名称发送 content_block_start
+#[derive(Debug, Default)]
+struct PendingToolCall {
+    index: usize,
+    id: String,
+    name: String,
+}
+
+/// OpenAI SSE -> Anthropic SSE 的流式转换器
+/// 在整个流中维护状态：是否已发送 message_start、当前内容块及其 index，
+/// 内容块类型变化时先关闭上一个块再以递增的 index 打开新块；
+/// tool_calls delta 转换为 tool_use 块 (不同 tool_calls index 对应不同的块)
+///
+/// 收到 finish_reason 时只关闭内容块，message_delta / message_stop 等到 usage 分片、[DONE] 或 finish 时发送，
+/// 以便带上 include_usage 在 finish_reason 之后单独发送的 completion_tokens
+pub struct OpenAISseConverter {
+    message_id: String,
+    model: String,
+    started: bool,
+    finished: bool,
+    // 已收到 finish_reason 时对应的 stop_reason (结束序列尚未发送)
+    stop_reason: Option<&'static str>,
+    // 上游报告的用量 (Anthropic 格式)
+    usage: Option<Value>,
+    // 下一个内容块的 index
+    next_index: usize,
+    // 当前打开的内容块及其 index
+    open_block: Option<(StreamBlock, usize)>,
+    pending_tool: Option<PendingToolCall>,
+    // 当前 tool_use 块已发送的参数，流中断时据此判断参数 JSON 是否完整
+    tool_arguments: String,
+    // 流在工具调用中途中断时已收到的参数字节数
+    interrupted_tool_call: Option<usize>,
+    // 标准 content 为空时读取文本的 JSON Pointer (相对于 delta)
+    content_path: Option<String>,
+}
+
+impl OpenAISseConverter {
+    pub fn new(message_id: &str, model: &str) -> Self {
+        Self {
+            message_id: message_id.to_string(),
+            model: model.to_string(),
+            started: false,
+            finished: false,
+            stop_reason: None,
+            usage: None,
+            next_index: 0,
+            open_block: None,
+            pending_tool: None,
+            tool_arguments: String::new(),
+            interrupted_tool_call: None,
+            content_path: None,
+        }
+    }
+    
+    /// 设置非标准的文本位置：delta.content 为空时按该 JSON Pointer 读取文本
+    pub fn with_content_path(mut self, content_path: Option<&str>) -> Self {
+        self.content_path = content_path.map(|p| p.to_string());
+        self
+    }
+    
+    /// 转换一行 OpenAI SSE
+    /// 输入：OpenAI 的 `data: {...}` 格式
+    /// 输出：Anthropic 的 `event: xxx\ndata: {...}` 格式
+    pub fn convert_line(&mut self, openai_line: &str) -> Vec<String> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        
+        // 跳过空行和非数据行
+        let data = match openai_line.strip_prefix("data: ") {
+            Some(data) => data,
+            None => return events,
+        };
+        
+        // [DONE]：已收到 finish_reason 时发送结束序列 (上游没有发送 usage)；
+        // 未收到 finish_reason 时不产生事件，由调用方通过 finish 补发
+        if data.trim() == "[DONE]" {
+            if self.stop_reason.is_some() {
+                events.extend(self.finish());
+            }
+            return events;
+        }
+        
+        // 解析 OpenAI 响应
+        let openai_resp: Value = match serde_json::from_str(data) {
+            Ok(v) => v,
+            Err(_) => return events,
+        };
+        
+        // 第一个事件前发送 message_start
+        self.ensure_started(&mut events);
+        
+        // include_usage 的 usage 在最后一个 (choices 为空的) 分片中，部分上游直接放在带 finish_reason 的分片里
+        if let Some(usage) = openai_resp.get("usage").filter(|u| u.is_object()) {
+            let token = |key: &str| usage.get(key).and_then(|t| t.as_u64()).unwrap_or(0);
+            self.usage = Some(json!({
+                "input_tokens": token("prompt_tokens"),
+                "output_tokens": token("completion_tokens")
+            }));
+        }
+        
+        // 提取 delta content (部分供应商会发送 choices 为空的首个 role 块或末尾 usage 块)
+        let Some(choice) = openai_resp.get("choices").and_then(|c| c.as_array()).and_then(|c| c.first()) else {
+            if self.stop_reason.is_some() && self.usage.is_some() {
+                events.extend(self.finish());
+            }
+            return events;
+        };
+        
+        if let Some(delta) = choice.get("delta") {
+            let (content, from_path) = message_text(delta, self.content_path.as_deref());
+            
+            // 推理内容 -> thinking 块 (文本本身就取自推理字段时不再重复输出)
+            if let Some(reasoning) = delta.get("reasoning_content")
+                .or_else(|| delta.get("reasoning"))
+                .and_then(|r| r.as_str())
+                .filter(|r| !r.is_empty())
+                .filter(|r| !from_path || *r != content) {
+                let index = self.ensure_block(StreamBlock::Thinking, &mut events);
+                events.push(format!("event: content_block_delta\ndata: {}", json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "thinking_delta", "thinking": reasoning}
+                })));
+            }
+            
+            // 文本 delta
+            if !content.is_empty() {
+                let index = self.ensure_block(StreamBlock::Text, &mut events);
+                events.push(format!("event: content_block_delta\ndata: {}", json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "text_delta", "text": content}
+                })));
+            }
+            
+            // 工具调用 delta -> tool_use 块，参数以 input_json_delta 流式发送
+            if let Some(tool_calls) = delta.get("tool_calls").and_then(|t| t.as_array()) {
+                for tool_call in tool_calls {
+                    self.convert_tool_call_delta(tool_call, &mut events);
+                }
+            }
+        }
+        
+        // 检查是否完成：先关闭内容块，结束序列等待 usage
+        let stop_reason = match choice.get("finish_reason").and_then(|f| f.as_str()) {
+            Some("stop" | "end_turn") => Some("end_turn"),
+            Some("length") => Some("max_tokens"),
+            Some("tool_calls" | "function_call") => Some("tool_use"),
+            _ => None,
+        };
+        if let Some(stop_reason) = stop_reason {
+            self.stop_reason = Some(stop_reason);
+            self.flush_pending_tool(&mut events);
+            if self.open_block.is_none() && self.next_index == 0 {
+                self.ensure_block(StreamBlock::Text, &mut events);
+            }
+            self.close_block(&mut events);
+            if self.usage.is_some() {
+                events.extend(self.finish());
+            }
+        }
+        
+        events
+    }
+    
+    /// 结束序列 (关闭当前内容块 + message_delta + message_stop)，usage 取自上游的 usage 分片 (没有时为 0)
+    /// 流结束时由调用方调用：已收到 finish_reason 时按其发送结束序列；上游未发送 finish_reason 就结束流时
+    /// (连接中断等) 必要时补发 message_start；已结束时返回空
+    ///
+    /// 中断发生在工具调用中途时：已发送的参数是完整 JSON 则关闭 tool_use 块并以 tool_use 结束；
+    /// 否则关闭该块后发送 error 事件，不让客户端把不完整的参数当作正常的工具调用执行
+    pub fn finish(&mut self) -> Vec<String> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        let usage = self.usage.clone().unwrap_or_else(|| json!({"output_tokens": 0}));
+        if let Some(stop_reason) = self.stop_reason {
+            return self.finish_with(stop_reason, usage);
+        }
+        self.ensure_started(&mut events);
+        self.flush_pending_tool(&mut events);
+        
+        if let Some((StreamBlock::ToolUse(_), _)) = self.open_block {
+            if tool_arguments_complete(&self.tool_arguments) {
+                events.extend(self.finish_with("tool_use", usage));
+            } else {
+                self.interrupted_tool_call = Some(self.tool_arguments.len());
+                self.close_block(&mut events);
+                events.push(format!("event: error\ndata: {}", json!({
+                    "type": "error",
+                    "error": {
+                        "type": "api_error",
+                        "message": "Upstream stream was interrupted in the middle of a tool call; the tool arguments are incomplete"
+                    }
+                })));
+                self.finished = true;
+            }
+            return events;
+        }
+        
+        events.extend(self.finish_with("end_turn", usage));
+        events
+    }
+    
+    /// 流是否在工具调用中途中断 (finish 发送了 error 事件)，返回已收到的参数字节数
+    pub fn interrupted_tool_call(&self) -> Option<usize> {
+        self.interrupted_tool_call
+    }
+    
+    /// 按给定的 stop_reason 和 usage 发送结束序列
+    fn finish_with(&mut self, stop_reason: &str, usage: Value) -> Vec<String> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        self.ensure_started(&mut events);
+        
+        self.flush_pending_tool(&mut events);
+        // 没有任何内容时仍发送一个空文本块，保持消息结构完整
+        if self.open_block.is_none() && self.next_index == 0 {
+            self.ensure_block(StreamBlock::Text, &mut events);
+        }
+        self.close_block(&mut events);
+        
+        events.push(format!("event: message_delta\ndata: {}", json!({
+            "type": "message_delta",
+            "delta": {"stop_reason": stop_reason, "stop_sequence": null},
+            "usage": usage
+        })));
+        events.push(r#"event: message_stop
+data: {"type":"message_stop"}"#.to_string());
+        self.finished = true;
+        events
+    }
+    
+    fn ensure_started(&mut self, events: &mut Vec<String>) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        events.push(format!(r#"event: message_start
+data: {{"type":"message_start","message":{{"id":"{}","type":"message","role":"assistant","content":[],"model":"{}","stop_reason":null,"stop_sequence":null,"usage":{{"input_tokens":0,"output_tokens":0}}}}}}"#, 
+            self.message_id, self.model));
+    }
+    
+    /// 处理一个 tool_calls delta 分片 (同一调用的后续分片通常只带 index 和参数片段)
+    fn convert_tool_call_delta(&mut self, tool_call: &Value, events: &mut Vec<String>) {
+        let tool_index = tool_call.get("index").and_then(|i| i.as_u64()).unwrap_or(0) as usize;
+        let id = tool_call.get("id").and_then(|i| i.as_str()).unwrap_or("");
+        let function = tool_call.get("function");
+        let name = function.and_then(|f| f.get("name")).and_then(|n| n.as_str()).unwrap_or("");
+        let arguments = function.and_then(|f| f.get("arguments")).and_then(|a| a.as_str()).unwrap_or("");
+        
+        let block_open = matches!(self.open_block, Some((StreamBlock::ToolUse(open), _)) if open == tool_index);
+        if !block_open {
+            // 新的工具调用：先结束上一个待发送的调用
+            if self.pending_tool.as_ref().is_some_and(|p| p.index != tool_index) {
+                self.flush_pending_tool(events);
+            }
+            let pending = self.pending_tool.get_or_insert_with(|| PendingToolCall { index: tool_index, ..Default::default() });
+            pending.id.push_str(id);
+            pending.name.push_str(name);
+            if arguments.is_empty() {
+                return;
+            }
+            self.flush_pending_tool(events);
+        }
+        
+        if let Some((StreamBlock::ToolUse(_), index)) = self.open_block.filter(|_| !arguments.is_empty()) {
+            self.tool_arguments.push_str(arguments);
+            events.push(format!("event: content_block_delta\ndata: {}", json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": {"type": "input_json_delta", "partial_json": arguments}
+            })));
+        }
+    }
+    
+    /// 为待发送的工具调用打开 tool_use 块 (缺少 id 时生成一个)
+    fn flush_pending_tool(&mut self, events: &mut Vec<String>) {
+        let Some(pending) = self.pending_tool.take() else {
+            return;
+        };
+        self.close_block(events);
+        
+        let id = if pending.id.is_empty() {
+            format!("toolu_{}", uuid::Uuid::new_v4().simple())
+        } else {
+            pending.id
+        };
+        let index = self.next_index;
+        self.next_index += 1;
+        self.tool_arguments.clear();
+        events.push(format!("event: content_block_start\ndata: {}", json!({
+            "type": "content_block_start",
+            "index": index,
+            "content_block": {"type": "tool_use", "id": id, "name": pending.name, "input": {}}
+        })));
+        self.open_block = Some((StreamBlock::ToolUse(pending.index), index));
+    }
+    
+    /// 确保指定类型的文本/thinking 内容块已打开，返回其 index
+    fn ensure_block(&mut self, block: StreamBlock, events: &mut Vec<String>) -> usize {
+        self.flush_pending_tool(events);
+        if let Some((open, index)) = self.open_block {
+            if open == block {
+                return index;
+            }
+        }
+        self.close_block(events);
+        
+        let index = self.next_index;
+        self.next_index += 1;
+        let content_block = match block {
+            StreamBlock::Thinking => json!({"type": "thinking", "thinking": ""}),
+            _ => json!({"type": "text", "text": ""}),
+        };
+        events.push(format!("event: content_block_start\ndata: {}", json!({
+            "type": "content_block_start",
+            "index": index,
+            "content_block": content_block
+        })));
+        self.open_block = Some((block, index));
+        index
+    }
+    
+    fn close_block(&mut self, events: &mut Vec<String>) {
+        if let Some((_, index)) = self.open_block.take() {
+            events.push(format!("event: content_block_stop\ndata: {}", json!({
+                "type": "content_block_stop",
+                "index": index
+            })));
+        }
+    }
+}
+
+/// 流中断时已收到的工具调用参数是否完整：没有参数的工具调用 (空字符串) 视为 {}，否则必须是完整的 JSON
+fn tool_arguments_complete(arguments: &str) -> bool {
+    arguments.trim().is_empty() || serde_json::from_str::<Value>(arguments).is_ok()
+}
+
+/// OpenAI Responses API SSE -> Anthropic SSE 的流式转换器
+/// Responses 流按事件类型 (data 中的 type 字段) 区分，而不是 Chat 的 choices[].delta：
+/// - response.output_text.delta / response.refusal.delta -> 文本块 content_block_delta
+/// - response.reasoning_text.delta / response.reasoning_summary_text.delta -> thinking 块
+/// - response.output_item.added (function_call) + response.function_call_arguments.delta -> tool_use 块
+/// - response.completed / response.incomplete -> 结束序列 (usage 取自完成事件)
+/// - response.failed / error -> error 事件
+///
+/// `event:` 行和其他事件类型忽略；内容块状态与 OpenAISseConverter 共用
+pub struct ResponsesSseConverter {
+    inner: OpenAISseConverter,
+}
+
+impl ResponsesSseConverter {
+    pub fn new(message_id: &str, model: &str) -> Self {
+        Self { inner: OpenAISseConverter::new(message_id, model) }
+    }
+    
+    /// 转换一行 Responses SSE (`data: {...}`)，输出 Anthropic 的 `event: xxx\ndata: {...}`
+    pub fn convert_line(&mut self, line: &str) -> Vec<String> {
+        let mut events = Vec::new();
+        if self.inner.finished {
+            return events;
+        }
+        let Some(data) = line.strip_prefix("data: ") else {
+            return events;
+        };
+        let Ok(event) = serde_json::from_str::<Value>(data) else {
+            return events;
+        };
+        
+        self.inner.ensure_started(&mut events);
+        
+        let event_type = event.get("type").and_then(|t| t.as_str()).unwrap_or("");
+        let delta = event.get("delta").and_then(|d| d.as_str()).filter(|d| !d.is_empty());
+        // 工具调用以 output_index 区分 (对应 OpenAISseConverter 中 tool_calls 的 index)
+        let output_index = event.get("output_index").cloned().unwrap_or(json!(0));
+        match (event_type, delta) {
+            ("response.output_text.delta" | "response.refusal.delta", Some(text)) => {
+                let index = self.inner.ensure_block(StreamBlock::Text, &mut events);
+                events.push(format!("event: content_block_delta\ndata: {}", json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "text_delta", "text": text}
+                })));
+            }
+            ("response.reasoning_text.delta" | "response.reasoning_summary_text.delta", Some(thinking)) => {
+                let index = self.inner.ensure_block(StreamBlock::Thinking, &mut events);
+                events.push(format!("event: content_block_delta\ndata: {}", json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "thinking_delta", "thinking": thinking}
+                })));
+            }
+            ("response.output_item.added", _) if event.pointer("/item/type").and_then(|t| t.as_str()) == Some("function_call") => {
+                let item = &event["item"];
+                self.inner.convert_tool_call_delta(&json!({
+                    "index": output_index,
+                    "id": item.get("call_id").cloned().unwrap_or(Value::Null),
+                    "function": {
+                        "name": item.get("name").cloned().unwrap_or(Value::Null),
+                        "arguments": item.get("arguments").cloned().unwrap_or(Value::Null)
+                    }
+                }), &mut events);
+            }
+            ("response.function_call_arguments.delta", Some(arguments)) => {
+                self.inner.convert_tool_call_delta(&json!({
+                    "index": output_index,
+                    "function": {"arguments": arguments}
+                }), &mut events);
+            }
+            ("response.completed" | "response.incomplete", _) => {
+                let response = event.get("response").cloned().unwrap_or(Value::Null);
+                let has_tool_use = response.get("output").and_then(|o| o.as_array()).into_iter().flatten()
+                    .any(|item| item.get("type").and_then(|t| t.as_str()) == Some("function_call"));
+                let stop_reason = if has_tool_use { "tool_use" } else { responses_stop_reason(&response) };
+                let token = |key: &str| response.pointer(&format!("/usage/{}", key)).and_then(|t| t.as_u64()).unwrap_or(0);
+                events.extend(self.inner.finish_with(stop_reason, json!({
+                    "input_tokens": token("input_tokens"),
+                    "output_tokens": token("output_tokens")
+                })));
+            }
+            ("response.failed" | "error", _) => {
+                let message = event.pointer("/response/error/message")
+                    .or_else(|| event.get("message"))
+                    .and_then(|m| m.as_str())
+                    .unwrap_or("Upstream response failed");
+                self.inner.flush_pending_tool(&mut events);
+                self.inner.close_block(&mut events);
+                events.push(format!("event: error\ndata: {}", json!({
+                    "type": "error",
+                    "error": {"type": "api_error", "message": message}
+                })));
+                self.inner.finished = true;
+            }
+            _ => {}
+        }
+        
+        events
+    }
+    
+    /// 上游未发送完成事件就结束流时补发结束序列 (规则同 OpenAISseConverter::finish)；已结束时返回空
+    pub fn finish(&mut self) -> Vec<String> {
+        self.inner.finish()
+    }
+    
+    /// 流是否在工具调用中途中断 (finish 发送了 error 事件)，返回已收到的参数字节数
+    pub fn interrupted_tool_call(&self) -> Option<usize> {
+        self.inner.interrupted_tool_call()
+    }
+}
+
+/// 将一段完整的 Responses API SSE 文本转换为 Anthropic SSE 事件序列
+/// 逐行交给 ResponsesSseConverter，最后补发结束序列 (上游已发送完成事件时不会重复)
+pub fn responses_sse_to_anthropic(responses_sse: &str, message_id: &str, model: &str) -> Vec<String> {
+    let mut converter = ResponsesSseConverter::new(message_id, model);
+    let mut events: Vec<String> = responses_sse.lines()
+        .flat_map(|line| converter.convert_line(line.trim()))
+        .collect();
+    events.extend(converter.finish());
+    events
+}
+
+/// 提取 SSE 行中的输出文本增量 (用于流式 token 统计)
+/// 支持 OpenAI Chat (含 reasoning_content)、Anthropic (text/thinking delta) 和 Responses (*.delta) 格式
+pub fn sse_delta_text(line: &str) -> Option<String> {
+    let data = line.strip_prefix("data: ")?;
+    let event: Value = serde_json::from_str(data).ok()?;
+    
+    let mut text = String::new();
+    if let Some(delta) = event.get("choices")
+        .and_then(|c| c.as_array())
+        .and_then(|c| c.first())
+        .and_then(|c| c.get("delta")) {
+        // OpenAI Chat
+        for key in ["reasoning_content", "reasoning", "content"] {
+            if let Some(part) = delta.get(key).and_then(|v| v.as_str()) {
+                text.push_str(part);
+            }
+        }
+    } else if let Some(delta) = event.get("delta") {
+        match delta.as_str() {
+            // Responses: response.output_text.delta 等事件的 delta 为字符串
+            Some(part) => text.push_str(part),
+            // Anthropic: content_block_delta
+            None => {
+                for key in ["text", "thinking"] {
+                    if let Some(part) = delta.get(key).and_then(|v| v.as_str()) {
+                        text.push_str(part);
+                    }
+                }
+            }
+        }
+    }
+    
+    if text.is_empty() { None } else { Some(text) }
+}
+
+/// 提取 SSE 行中上游报告的输出 token 数
+/// OpenAI Chat 末尾的 usage 块 (completion_tokens)、Anthropic message_delta (output_tokens)、
+/// Responses response.completed (response.usage.output_tokens)
+pub fn sse_output_tokens(line: &str) -> Option<u32> {
+    let data = line.strip_prefix("data: ")?;
+    let event: Value = serde_json::from_str(data).ok()?;
+    usage_output_tokens(&event)
+}
+
+/// 提取非流式 JSON 响应中上游报告的输出 token 数
+/// (OpenAI Chat usage.completion_tokens，Anthropic / Responses usage.output_tokens)
+pub fn response_output_tokens(body: &[u8]) -> Option<u32> {
+    let resp: Value = serde_json::from_slice(body).ok()?;
+    usage_output_tokens(&resp)
+}
+
+fn usage_output_tokens(value: &Value) -> Option<u32> {
+    let usage = value.get("usage")
+        .or_else(|| value.pointer("/response/usage"))
+        .filter(|u| !u.is_null())?;
+    usage.get("completion_tokens")
+        .or_else(|| usage.get("output_tokens"))
+        .and_then(|t| t.as_u64())
+        .map(|t| t as u32)
+}
+
+/// 将完整的 OpenAI 非流式响应转换为 Anthropic 格式 (字节版本)
+pub fn openai_response_to_anthropic(openai_body: &[u8], model: &str, content_path: Option<&str>) -> Result<Vec<u8>, String> {
+    let openai_resp: Value = serde_json::from_slice(openai_body)
+        .map_err(|e| format!("Failed to parse OpenAI response: {}", e))?;
+    let anthropic_resp = openai_response_value_to_anthropic(&openai_resp, model, content_path);
+    serde_json::to_vec(&anthropic_resp)
+        .map_err(|e| format!("Failed to serialize Anthropic response: {}", e))
+}
+
+/// 将完整的 OpenAI 非流式响应转换为 Anthropic 格式
+///
+/// Anthropic 不支持 `n`：n > 1 时按 choices 的 index 顺序把每个候选依次展开为独立的内容块
+/// (thinking、text、tool_use)，不会丢弃其余候选；只有一个候选时与普通响应一致。
+/// content_path 为非标准的文本位置 (相对于 message 的 JSON Pointer)，message.content 为空时使用
+pub fn openai_response_value_to_anthropic(openai_resp: &Value, model: &str, content_path: Option<&str>) -> Value {
+    let message_id = format!("msg_{}", &uuid::Uuid::new_v4().simple().to_string()[..24]);
+    
+    let mut output_tokens = 0u64;
+    let mut input_tokens = 0u64;
+    
+    // 提取 usage
+    if let Some(usage) = openai_resp.get("usage") {
+        output_tokens = usage.get("completion_tokens").and_then(|c| c.as_u64()).unwrap_or(0);
+        input_tokens = usage.get("prompt_tokens").and_then(|p| p.as_u64()).unwrap_or(0);
+    }
+    
+    // 提取 content (choices 为空或缺失时返回空文本，而不是报错丢弃整轮对话)
+    let mut choices: Vec<&Value> = openai_resp.get("choices")
+        .and_then(|c| c.as_array())
+        .map(|c| c.iter().collect())
+        .unwrap_or_default();
+    choices.sort_by_key(|c| c.get("index").and_then(|i| i.as_u64()).unwrap_or(0));
+    
+    let mut content = Vec::new();
+    let mut has_tool_use = false;
+    for message in choices.iter().filter_map(|c| c.get("message")) {
+        let blocks = openai_message_to_content_blocks(message, content_path);
+        has_tool_use |= blocks.iter().any(|b| b["type"] == "tool_use");
+        content.extend(blocks);
+    }
+    if content.is_empty() {
+        content.push(json!({"type": "text", "text": ""}));
+    }
+    // 输出被截断 (finish_reason = length) 时对应 Anthropic 的 max_tokens
+    let truncated = choices.iter().any(|c| c.get("finish_reason").and_then(|f| f.as_str()) == Some("length"));
+    let stop_reason = if has_tool_use {
+        "tool_use"
+    } else if truncated {
+        "max_tokens"
+    } else {
+        "end_turn"
+    };
+    
+    json!({
+        "id": message_id,
+        "type": "message",
+        "role": "assistant",
+        "content": content,
+        "model": model,
+        "stop_reason": stop_reason,
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": input_tokens,
+            "output_tokens": output_tokens
+        }
+    })
+}
+
+/// 将一个 OpenAI 候选消息转换为 Anthropic 内容块：thinking (如有)、text、tool_use
+/// 没有工具调用时总是包含一个 text 块 (可能为空)
+fn openai_message_to_content_blocks(message: &Value, content_path: Option<&str>) -> Vec<Value> {
+    let mut blocks = Vec::new();
+    let (text, from_path) = message_text(message, content_path);
+    
+    // 推理内容 (reasoning_content / reasoning) 转换为 thinking 块 (文本本身就取自推理字段时不再重复输出)
+    if let Some(reasoning) = message.get("reasoning_content")
+        .or_else(|| message.get("reasoning"))
+        .and_then(|r| r.as_str())
+        .filter(|r| !r.is_empty())
+        .filter(|r| !from_path || *r != text) {
+        blocks.push(json!({
+            "type": "thinking",
+            "thinking": reasoning,
+            "signature": ""
+        }));
+    }
+    
+    // tool_calls -> tool_use 块 (arguments 为 JSON 字符串，解析失败时保留为空对象)
+    let mut tool_uses = Vec::new();
+    for tool_call in message.get("tool_calls").and_then(|t| t.as_array()).into_iter().flatten() {
+        let function = tool_call.get("function");
+        let input = function
+            .and_then(|f| f.get("arguments"))
+            .and_then(|a| a.as_str())
+            .and_then(|a| serde_json::from_str::<Value>(a).ok())
+            .unwrap_or_else(|| json!({}));
+        tool_uses.push(json!({
+            "type": "tool_use",
+            "id": tool_call.get("id").and_then(|i| i.as_str())
+                .map(|i| i.to_string())
+                .unwrap_or_else(|| format!("toolu_{}", uuid::Uuid::new_v4().simple())),
+            "name": function.and_then(|f| f.get("name")).cloned().unwrap_or(Value::Null),
+            "input": input
+        }));
+    }
+    
+    if !text.is_empty() || tool_uses.is_empty() {
+        blocks.push(json!({
+            "type": "text",
+            "text": text
+        }));
+    }
+    blocks.extend(tool_uses);
+    blocks
+}
+
+/// 读取 OpenAI message / delta 的文本：标准的 content 为空时按 content_path (JSON Pointer) 读取
+/// 返回文本以及它是否来自 content_path
+fn message_text<'a>(message: &'a Value, content_path: Option<&str>) -> (&'a str, bool) {
+    let content = message.get("content").and_then(|c| c.as_str()).unwrap_or("");
+    if !content.is_empty() {
+        return (content, false);
+    }
+    match content_path
+        .and_then(|path| message.pointer(path))
+        .and_then(|v| v.as_str())
+        .filter(|t| !t.is_empty())
+    {
+        Some(text) => (text, true),
+        None => (content, false),
+    }
+}
+
+// 回放非流式响应时每个文本增量的字符数
+const SSE_TEXT_CHUNK_CHARS: usize = 64;
+
+/// 将完整的 Anthropic 消息响应拆分为 SSE 事件序列
+/// 用于上游忽略 `stream: true` 返回完整 JSON 时，向流式客户端回放
+pub fn anthropic_response_to_sse(anthropic_body: &[u8]) -> Result<Vec<String>, String> {
+    let resp: Value = serde_json::from_slice(anthropic_body)
+        .map_err(|e| format!("Failed to parse Anthropic response: {}", e))?;
+    Ok(anthropic_response_value_to_sse(&resp))
+}
+
+/// 将完整的 Anthropic 消息响应 (Value) 拆分为 SSE 事件序列
+pub fn anthropic_response_value_to_sse(resp: &Value) -> Vec<String> {
+    let mut events = Vec::new();
+    
+    // message_start：内容为空，output_tokens 在 message_delta 中给出
+    let mut message = resp.clone();
+    message["content"] = json!([]);
+    message["stop_reason"] = Value::Null;
+    message["stop_sequence"] = Value::Null;
+    let input_tokens = resp.pointer("/usage/input_tokens").and_then(|t| t.as_u64()).unwrap_or(0);
+    message["usage"] = json!({"input_tokens": input_tokens, "output_tokens": 0});
+    events.push(format!("event: message_start\ndata: {}", json!({"type": "message_start", "message": message})));
+    
+    let blocks = resp.get("content").and_then(|c| c.as_array()).cloned().unwrap_or_default();
+    for (index, block) in blocks.iter().enumerate() {
+        let block_type = block.get("type").and_then(|t| t.as_str()).unwrap_or("text");
+        match block_type {
+            "text" => {
+                events.push(format!("event: content_block_start\ndata: {}", json!({
+                    "type": "content_block_start",
+                    "index": index,
+                    "content_block": {"type": "text", "text": ""}
+                })));
+                for chunk in text_chunks(block.get("text").and_then(|t| t.as_str()).unwrap_or("")) {
+                    events.push(format!("event: content_block_delta\ndata: {}", json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": {"type": "text_delta", "text": chunk}
+                    })));
+                }
+            }
+            "thinking" => {
+                events.push(format!("event: content_block_start\ndata: {}", json!({
+                    "type": "content_block_start",
+                    "index": index,
+                    "content_block": {"type": "thinking", "thinking": ""}
+                })));
+                for chunk in text_chunks(block.get("thinking").and_then(|t| t.as_str()).unwrap_or("")) {
+                    events.push(format!("event: content_block_delta\ndata: {}", json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": {"type": "thinking_delta", "thinking": chunk}
+                    })));
+                }
+                if let Some(signature) = block.get("signature").and_then(|s| s.as_str()).filter(|s| !s.is_empty()) {
+                    events.push(format!("event: content_block_delta\ndata: {}", json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": {"type": "signature_delta", "signature": signature}
+                    })));
+                }
+            }
+            "tool_use" => {
+                events.push(format!("event: content_block_start\ndata: {}", json!({
+                    "type": "content_block_start",
+                    "index": index,
+                    "content_block": {
+                        "type": "tool_use",
+                        "id": block.get("id").cloned().unwrap_or(Value::Null),
+                        "name": block.get("name").cloned().unwrap_or(Value::Null),
+                        "input": {}
+                    }
+                })));
+                let input = block.get("input").cloned().unwrap_or_else(|| json!({}));
+                events.push(format!("event: content_block_delta\ndata: {}", json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "input_json_delta", "partial_json": input.to_string()}
+                })));
+            }
+            _ => {
+                events.push(format!("event: content_block_start\ndata: {}", json!({
+                    "type": "content_block_start",
+                    "index": index,
+                    "content_block": block
+                })));
+            }
+        }
+        events.push(format!("event: content_block_stop\ndata: {}", json!({
+            "type": "content_block_stop",
+            "index": index
+        })));
+    }
+    
+    let output_tokens = resp.pointer("/usage/output_tokens").and_then(|t| t.as_u64()).unwrap_or(0);
+    events.push(format!("event: message_delta\ndata: {}", json!({
+        "type": "message_delta",
+        "delta": {
+            "stop_reason": resp.get("stop_reason").cloned().unwrap_or_else(|| json!("end_turn")),
+            "stop_sequence": resp.get("stop_sequence").cloned().unwrap_or(Value::Null)
+        },
+        "usage": {"output_tokens": output_tokens}
+    })));
+    events.push(format!("event: message_stop\ndata: {}", json!({"type": "message_stop"})));
+    
+    events
+}
+
+/// 将完整的 Chat Completions 响应拆分为 chat.completion.chunk SSE 事件序列 (以 `data: [DONE]` 结束)
+/// 用于供应商禁用流式时，向请求了流式的 Chat 客户端回放
+pub fn chat_response_to_chat_sse(chat_body: &[u8]) -> Result<Vec<String>, String> {
+    let resp: Value = serde_json::from_slice(chat_body)
+        .map_err(|e| format!("Failed to parse Chat Completions response: {}", e))?;
+    Ok(chat_response_value_to_chat_sse(&resp))
+}
+
+/// 将完整的 Chat Completions 响应 (Value) 拆分为 chat.completion.chunk SSE 事件序列
+///
+/// 每个候选依次发送 role、推理内容、文本 (按 SSE_TEXT_CHUNK_CHARS 拆分) 和 tool_calls 分片，最后是带 finish_reason 的分片；
+/// 响应带 usage 时与 include_usage 一致，在末尾单独发送一个 choices 为空的 usage 分片
+pub fn chat_response_value_to_chat_sse(resp: &Value) -> Vec<String> {
+    let id = resp.get("id").cloned().unwrap_or_else(|| json!(responses_item_id("chatcmpl")));
+    let created = resp.get("created").cloned().unwrap_or_else(|| json!(unix_timestamp()));
+    let chunk = |choices: Value| {
+        let mut chunk = json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": resp.get("model").cloned().unwrap_or(Value::Null),
+            "choices": choices
+        });
+        if let Some(fingerprint) = resp.get("system_fingerprint").filter(|f| !f.is_null()) {
+            chunk["system_fingerprint"] = fingerprint.clone();
+        }
+        chunk
+    };
+    let delta_chunk = |index: &Value, delta: Value| {
+        format!("data: {}", chunk(json!([{"index": index, "delta": delta, "finish_reason": null}])))
+    };
+    
+    let mut events = Vec::new();
+    for (position, choice) in resp.get("choices").and_then(|c| c.as_array()).into_iter().flatten().enumerate() {
+        let index = choice.get("index").cloned().unwrap_or_else(|| json!(position));
+        let message = choice.get("message").cloned().unwrap_or_else(|| json!({}));
+        
+        events.push(delta_chunk(&index, json!({"role": "assistant", "content": ""})));
+        for key in ["reasoning_content", "reasoning"] {
+            if let Some(reasoning) = message.get(key).and_then(|r| r.as_str()) {
+                for part in text_chunks(reasoning) {
+                    events.push(delta_chunk(&index, json!({key: part})));
+                }
+            }
+        }
+        if let Some(content) = message.get("content").and_then(|c| c.as_str()) {
+            for part in text_chunks(content) {
+                events.push(delta_chunk(&index, json!({"content": part})));
+            }
+        }
+        if let Some(tool_calls) = message.get("tool_calls").and_then(|t| t.as_array()).filter(|t| !t.is_empty()) {
+            let tool_calls: Vec<Value> = tool_calls.iter().enumerate()
+                .map(|(tool_index, tool_call)| {
+                    let mut tool_call = tool_call.clone();
+                    tool_call["index"] = json!(tool_index);
+                    tool_call
+                })
+                .collect();
+            events.push(delta_chunk(&index, json!({"tool_calls": tool_calls})));
+        }
+        let finish_reason = choice.get("finish_reason").cloned().unwrap_or_else(|| json!("stop"));
+        events.push(format!("data: {}", chunk(json!([{"index": index, "delta": {}, "finish_reason": finish_reason}]))));
+    }
+    
+    if let Some(usage) = resp.get("usage").filter(|u| !u.is_null()) {
+        let mut usage_chunk = chunk(json!([]));
+        usage_chunk["usage"] = usage.clone();
+        events.push(format!("data: {}", usage_chunk));
+    }
+    events.push("data: [DONE]".to_string());
+    events
+}
+
+/// 按 SSE_TEXT_CHUNK_CHARS 个字符拆分文本 (空文本不产生分片)
+fn text_chunks(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    chars.chunks(SSE_TEXT_CHUNK_CHARS).map(|chunk| chunk.iter().collect()).collect()
+}
+
+/// 将 OpenAI Responses API 请求转换为 Chat Completions 格式 (字节版本)
+pub fn responses_to_chat(body: &[u8], options: &ConversionOptions) -> Result<Vec<u8>, ConversionError> {
+    let responses_req: Value = serde_json::from_slice(body)
+        .map_err(|e| ConversionError::InvalidRequest(format!("Failed to parse Responses request: {}", e)))?;
+    let chat_req = responses_request_to_chat(&responses_req, options)?;
+    serde_json::to_vec(&chat_req)
+        .map_err(|e| ConversionError::Internal(format!("Failed to serialize Chat Completions request: {}", e)))
+}
+
+/// 将 OpenAI Responses API 请求转换为 Chat Completions 格式
+///
+/// - instructions 转换为开头的 system 消息 (角色按 system_role)
+/// - input 为字符串时作为一条 user 消息；为数组时逐项转换：message -> 对应角色的消息，
+///   function_call -> assistant 消息的 tool_calls，function_call_output -> tool 消息，reasoning 等其他项忽略
+/// - 只转换 function 类型的工具，内置工具 (如 web_search) 上游无法执行，直接忽略
+/// - previous_response_id 依赖服务端保存的会话状态，Chat Completions 无法支持，忽略
+pub fn responses_request_to_chat(responses_req: &Value, options: &ConversionOptions) -> Result<Value, ConversionError> {
+    let mut chat_messages = Vec::new();
+    
+    if let Some(instructions) = responses_req.get("instructions").and_then(|i| i.as_str()).filter(|i| !i.is_empty()) {
+        chat_messages.push(json!({
+            "role": options.system_role.as_str(),
+            "content": instructions
+        }));
+    }
+    
+    match responses_req.get("input") {
+        Some(Value::String(text)) => chat_messages.push(json!({"role": "user", "content": text})),
+        Some(Value::Array(items)) => {
+            for item in items {
+                convert_responses_input_item(item, options, &mut chat_messages)?;
+            }
+        }
+        None | Some(Value::Null) => {}
+        Some(_) => return Err(ConversionError::InvalidRequest("'input' must be a string or an array".to_string())),
+    }
+    
+    if options.merge_consecutive_roles {
+        chat_messages = merge_consecutive_roles(chat_messages);
+    }
+    
+    // 应用模型映射
+    let original_model = responses_req.get("model")
+        .and_then(|m| m.as_str())
+        .ok_or_else(|| ConversionError::InvalidRequest("Missing 'model' field in request".to_string()))?;
+    let model = options.model_mapping.get(original_model)
+        .map(|s| s.as_str())
+        .unwrap_or(original_model);
+    
+    let stream = responses_req.get("stream")
+        .and_then(|s| s.as_bool())
+        .unwrap_or(false);
+    
+    let mut chat_req = json!({
+        "model": model,
+        "messages": chat_messages,
+        "stream": stream
+    });
+    // 流式响应末尾需要 usage 才能在 response.completed 中给出用量
+    if stream && !options.disable_stream_usage {
+        chat_req["stream_options"] = json!({"include_usage": true});
+    }
+    
+    // Responses 的 max_output_tokens 是可选的，没有时只在配置了默认值时发送
+    let max_tokens = responses_req.get("max_output_tokens")
+        .and_then(|m| m.as_u64())
+        .or(options.max_tokens_default)
+        .map(|m| options.max_tokens_cap.map_or(m, |cap| m.min(cap)));
+    if let Some(max_tokens) = max_tokens {
+        chat_req["max_tokens"] = json!(max_tokens);
+    }
+    
+    for key in ["temperature", "top_p", "parallel_tool_calls", "user"] {
+        if let Some(value) = responses_req.get(key).filter(|v| !v.is_null()) {
+            chat_req[key] = value.clone();
+        }
+    }
+    
+    // 工具定义：Responses 的 function 工具是扁平结构，Chat 需要包在 function 字段中
+    if let Some(tools) = responses_req.get("tools").and_then(|t| t.as_array()) {
+        let chat_tools: Vec<Value> = tools.iter()
+            .filter(|tool| tool.get("type").and_then(|t| t.as_str()) == Some("function"))
+            .filter_map(|tool| {
+                let mut function = json!({"name": tool.get("name")?});
+                for key in ["description", "parameters", "strict"] {
+                    if let Some(value) = tool.get(key).filter(|v| !v.is_null()) {
+                        function[key] = value.clone();
+                    }
+                }
+                Some(json!({"type": "function", "function": function}))
+            })
+            .collect();
+        if !chat_tools.is_empty() {
+            chat_req["tools"] = Value::Array(chat_tools);
+        }
+    }
+    match responses_req.get("tool_choice") {
+        Some(Value::String(choice)) => chat_req["tool_choice"] = json!(choice),
+        Some(choice) if choice.get("type").and_then(|t| t.as_str()) == Some("function") => {
+            if let Some(name) = choice.get("name") {
+                chat_req["tool_choice"] = json!({"type": "function", "function": {"name": name}});
+            }
+        }
+        _ => {}
+    }
+    
+    // 结构化输出：text.format 对应 Chat 的 response_format
+    if let Some(format) = responses_req.pointer("/text/format") {
+        match format.get("type").and_then(|t| t.as_str()) {
+            Some("json_schema") => {
+                let mut json_schema = json!({});
+                for key in ["name", "description", "schema", "strict"] {
+                    if let Some(value) = format.get(key).filter(|v| !v.is_null()) {
+                        json_schema[key] = value.clone();
+                    }
+                }
+                chat_req["response_format"] = json!({"type": "json_schema", "json_schema": json_schema});
+            }
+            Some("json_object") => chat_req["response_format"] = json!({"type": "json_object"}),
+            _ => {}
+        }
+    }
+    
+    // reasoning.effort 只能原样对应 reasoning_effort 参数，其他推理参数名无法转换
+    if options.reasoning_param_name.as_deref() == Some("reasoning_effort") {
+        if let Some(effort) = responses_req.pointer("/reasoning/effort").filter(|e| !e.is_null()) {
+            chat_req["reasoning_effort"] = effort.clone();
+        }
+    }
+    
+    Ok(chat_req)
+}
+
+/// 转换一个 Responses input 数组项，追加到 Chat 消息列表
+fn convert_responses_input_item(item: &Value, options: &ConversionOptions, chat_messages: &mut Vec<Value>) -> Result<(), ConversionError> {
+    // 没有 type 的项是简写的消息 ({"role", "content"})
+    match item.get("type").and_then(|t| t.as_str()).unwrap_or("message") {
+        "message" => {
+            let role = item.get("role")
+                .and_then(|r| r.as_str())
+                .ok_or_else(|| ConversionError::InvalidRequest("Input message is missing 'role'".to_string()))?;
+            let role = match role {
+                "system" | "developer" => options.system_role.as_str(),
+                other => other,
+            };
+            chat_messages.push(json!({
+                "role": role,
+                "content": responses_content_to_chat(item.get("content"))
+            }));
+        }
+        "function_call" => {
+            let tool_call = json!({
+                "id": item.get("call_id").cloned().unwrap_or(Value::Null),
+                "type": "function",
+                "function": {
+                    "name": item.get("name").cloned().unwrap_or(Value::Null),
+                    "arguments": item.get("arguments").and_then(|a| a.as_str()).unwrap_or("{}")
+                }
+            });
+            // 连续的 function_call (以及紧随其后的 assistant 文本) 合并到同一条 assistant 消息
+            match chat_messages.last_mut() {
+                Some(last) if last["role"] == "assistant" => {
+                    match last.get_mut("tool_calls").and_then(|t| t.as_array_mut()) {
+                        Some(tool_calls) => tool_calls.push(tool_call),
+                        None => last["tool_calls"] = json!([tool_call]),
+                    }
+                }
+                _ => chat_messages.push(json!({
+                    "role": "assistant",
+                    "content": Value::Null,
+                    "tool_calls": [tool_call]
+                })),
+            }
+        }
+        "function_call_output" => {
+            let output = match item.get("output") {
+                Some(Value::String(output)) => output.clone(),
+                Some(Value::Array(parts)) => parts.iter()
+                    .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                Some(Value::Null) | None => String::new(),
+                Some(other) => other.to_string(),
+            };
+            chat_messages.push(json!({
+                "role": "tool",
+                "tool_call_id": item.get("call_id").cloned().unwrap_or(Value::Null),
+                "content": output
+            }));
+        }
+        // reasoning、内置工具调用等项上游无法使用，忽略
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Responses 的消息内容 (字符串或 input_text / output_text / input_image 数组) 转换为 Chat 的 content
+/// 只有文本时合并为字符串，包含图片时转换为 text / image_url 数组
+fn responses_content_to_chat(content: Option<&Value>) -> Value {
+    let Some(parts) = content.and_then(|c| c.as_array()) else {
+        return content.cloned().unwrap_or_else(|| json!(""));
+    };
+    
+    let mut chat_parts = Vec::new();
+    for part in parts {
+        match part.get("type").and_then(|t| t.as_str()).unwrap_or("") {
+            "input_text" | "output_text" | "text" => {
+                if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
+                    chat_parts.push(json!({"type": "text", "text": text}));
+                }
+            }
+            "refusal" => {
+                if let Some(refusal) = part.get("refusal").and_then(|t| t.as_str()) {
+                    chat_parts.push(json!({"type": "text", "text": refusal}));
+                }
+            }
+            "input_image" => {
+                if let Some(url) = part.get("image_url").and_then(|u| u.as_str()) {
+                    let mut image_url = json!({"url": url});
+                    if let Some(detail) = part.get("detail").filter(|d| !d.is_null()) {
+                        image_url["detail"] = detail.clone();
+                    }
+                    chat_parts.push(json!({"type": "image_url", "image_url": image_url}));
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    if chat_parts.iter().all(|p| p["type"] == "text") {
+        let texts: Vec<&str> = chat_parts.iter().filter_map(|p| p["text"].as_str()).collect();
+        Value::String(texts.join("\n"))
+    } else {
+        Value::Array(chat_parts)
+    }
+}
+
+/// 将 Anthropic Messages API 请求转换为 OpenAI Responses API 格式
+///
+/// 先按 anthropic_request_to_openai 转换为 Chat Completions 请求 (模型映射、max_tokens、system 角色等选项同样生效)，
+/// 再转换为 Responses 的结构：
+/// - 消息转换为 input 中的 message 项，assistant 的 tool_calls 拆分为 function_call 项，tool 消息转换为 function_call_output 项
+/// - max_tokens -> max_output_tokens，function 工具展开为扁平结构，reasoning_effort -> reasoning.effort
+/// - Responses 不支持 stop 序列，直接丢弃；temperature 只在客户端指定时发送 (推理模型不接受该参数)
+pub fn anthropic_request_to_responses(anthropic_req: &Value, options: &ConversionOptions) -> Result<Value, ConversionError> {
+    let chat_req = anthropic_request_to_openai(anthropic_req, options)?;
+    
+    let mut input = Vec::new();
+    for message in chat_req.get("messages").and_then(|m| m.as_array()).into_iter().flatten() {
+        let role = message.get("role").and_then(|r| r.as_str()).unwrap_or("user");
+        if role == "tool" {
+            input.push(json!({
+                "type": "function_call_output",
+                "call_id": message.get("tool_call_id").cloned().unwrap_or(Value::Null),
+                "output": chat_content_text(message.get("content"))
+            }));
+            continue;
+        }
+        if let Some(content) = chat_content_to_responses(message.get("content"), role) {
+            input.push(json!({"type": "message", "role": role, "content": content}));
+        }
+        for tool_call in message.get("tool_calls").and_then(|t| t.as_array()).into_iter().flatten() {
+            let function = tool_call.get("function");
+            input.push(json!({
+                "type": "function_call",
+                "call_id": tool_call.get("id").cloned().unwrap_or(Value::Null),
+                "name": function.and_then(|f| f.get("name")).cloned().unwrap_or(Value::Null),
+                "arguments": function.and_then(|f| f.get("arguments")).and_then(|a| a.as_str()).unwrap_or("{}")
+            }));
+        }
+    }
+    
+    let mut responses_req = json!({
+        "model": chat_req["model"],
+        "input": input,
+        "max_output_tokens": chat_req["max_tokens"],
+        "stream": chat_req["stream"]
+    });
+    if let Some(temperature) = anthropic_req.get("temperature").filter(|t| !t.is_null()) {
+        responses_req["temperature"] = temperature.clone();
+    }
+    for key in ["top_p", "user", "parallel_tool_calls"] {
+        if let Some(value) = chat_req.get(key).filter(|v| !v.is_null()) {
+            responses_req[key] = value.clone();
+        }
+    }
+    
+    // 工具定义：Chat 的 function 字段展开为 Responses 的扁平结构
+    if let Some(tools) = chat_req.get("tools").and_then(|t| t.as_array()) {
+        let responses_tools: Vec<Value> = tools.iter()
+            .filter_map(|tool| {
+                let mut flat = tool.get("function")?.clone();
+                flat["type"] = json!("function");
+                Some(flat)
+            })
+            .collect();
+        responses_req["tools"] = Value::Array(responses_tools);
+    }
+    match chat_req.get("tool_choice") {
+        Some(Value::String(choice)) => responses_req["tool_choice"] = json!(choice),
+        Some(choice) => {
+            if let Some(name) = choice.pointer("/function/name") {
+                responses_req["tool_choice"] = json!({"type": "function", "name": name});
+            }
+        }
+        None => {}
+    }
+    
+    if let Some(effort) = chat_req.get("reasoning_effort").filter(|e| !e.is_null()) {
+        responses_req["reasoning"] = json!({"effort": effort});
+    }
+    
+    Ok(responses_req)
+}
+
+/// Chat 消息的 content 转换为 Responses message 项的 content：字符串原样保留，
+/// 数组中的 text / image_url 转换为 input_text (assistant 为 output_text) / input_image；内容为空时返回 None
+fn chat_content_to_responses(content: Option<&Value>, role: &str) -> Option<Value> {
+    match content? {
+        Value::String(text) if !text.is_empty() => Some(json!(text)),
+        Value::Array(parts) => {
+            let text_type = if role == "assistant" { "output_text" } else { "input_text" };
+            let parts: Vec<Value> = parts.iter()
+                .filter_map(|part| match part.get("type").and_then(|t| t.as_str())? {
+                    "text" => Some(json!({"type": text_type, "text": part.get("text")?})),
+                    "image_url" => {
+                        let mut image = json!({"type": "input_image", "image_url": part.pointer("/image_url/url")?});
+                        if let Some(detail) = part.pointer("/image_url/detail").filter(|d| !d.is_null()) {
+                            image["detail"] = detail.clone();
+                        }
+                        Some(image)
+                    }
+                    _ => None,
+                })
+                .collect();
+            (!parts.is_empty()).then_some(Value::Array(parts))
+        }
+        _ => None,
+    }
+}
+
+/// Chat 消息 content 中的文本 (字符串，或数组中 text 部分以换行拼接)
+fn chat_content_text(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(parts)) => parts.iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// 将 Responses API 非流式响应转换为 Anthropic 格式 (字节版本)
+pub fn responses_response_to_anthropic(responses_body: &[u8], model: &str) -> Result<Vec<u8>, String> {
+    let resp: Value = serde_json::from_slice(responses_body)
+        .map_err(|e| format!("Failed to parse Responses response: {}", e))?;
+    let anthropic_resp = responses_response_value_to_anthropic(&resp, model);
+    serde_json::to_vec(&anthropic_resp)
+        .map_err(|e| format!("Failed to serialize Anthropic response: {}", e))
+}
+
+/// 将 Responses API 非流式响应转换为 Anthropic 格式
+///
+/// 输出项依次转换为内容块：reasoning 的摘要 -> thinking，message 的 output_text / refusal -> text，
+/// function_call -> tool_use (call_id 作为 id)。有工具调用时 stop_reason 为 tool_use，
+/// 因 max_output_tokens 未完成时为 max_tokens
+pub fn responses_response_value_to_anthropic(resp: &Value, model: &str) -> Value {
+    let mut content = Vec::new();
+    for item in resp.get("output").and_then(|o| o.as_array()).into_iter().flatten() {
+        match item.get("type").and_then(|t| t.as_str()).unwrap_or("") {
+            "reasoning" => {
+                let thinking: Vec<&str> = item.get("summary").and_then(|s| s.as_array()).into_iter().flatten()
+                    .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+                    .collect();
+                if !thinking.is_empty() {
+                    content.push(json!({"type": "thinking", "thinking": thinking.join("\n"), "signature": ""}));
+                }
+            }
+            "message" => {
+                let text: String = item.get("content").and_then(|c| c.as_array()).into_iter().flatten()
+                    .filter_map(|p| p.get("text").or_else(|| p.get("refusal")).and_then(|t| t.as_str()))
+                    .collect();
+                content.push(json!({"type": "text", "text": text}));
+            }
+            "function_call" => {
+                let input = item.get("arguments")
+                    .and_then(|a| a.as_str())
+                    .and_then(|a| serde_json::from_str::<Value>(a).ok())
+                    .unwrap_or_else(|| json!({}));
+                content.push(json!({
+                    "type": "tool_use",
+                    "id": item.get("call_id").and_then(|i| i.as_str())
+                        .map(|i| i.to_string())
+                        .unwrap_or_else(|| format!("toolu_{}", uuid::Uuid::new_v4().simple())),
+                    "name": item.get("name").cloned().unwrap_or(Value::Null),
+                    "input": input
+                }));
+            }
+            _ => {}
+        }
+    }
+    if content.is_empty() {
+        content.push(json!({"type": "text", "text": ""}));
+    }
+    
+    let stop_reason = if content.iter().any(|b| b["type"] == "tool_use") {
+        "tool_use"
+    } else {
+        responses_stop_reason(resp)
+    };
+    let token = |key: &str| resp.pointer(&format!("/usage/{}", key)).and_then(|t| t.as_u64()).unwrap_or(0);
+    json!({
+        "id": format!("msg_{}", &uuid::Uuid::new_v4().simple().to_string()[..24]),
+        "type": "message",
+        "role": "assistant",
+        "content": content,
+        "model": model,
+        "stop_reason": stop_reason,
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": token("input_tokens"),
+            "output_tokens": token("output_tokens")
+        }
+    })
+}
+
+/// Responses 的 response 对象 (不含工具调用时) 对应的 Anthropic stop_reason
+fn responses_stop_reason(resp: &Value) -> &'static str {
+    match resp.pointer("/incomplete_details/reason").and_then(|r| r.as_str()) {
+        Some("max_output_tokens") => "max_tokens",
+        _ => "end_turn",
+    }
+}
+
+/// 将 Chat Completions 非流式响应转换为 Responses API 格式 (字节版本)
+pub fn chat_response_to_responses(chat_body: &[u8], model: &str, content_path: Option<&str>) -> Result<Vec<u8>, String> {
+    let chat_resp: Value = serde_json::from_slice(chat_body)
+        .map_err(|e| format!("Failed to parse Chat Completions response: {}", e))?;
+    let responses_resp = chat_response_value_to_responses(&chat_resp, model, content_path);
+    serde_json::to_vec(&responses_resp)
+        .map_err(|e| format!("Failed to serialize Responses response: {}", e))
+}
+
+/// 将 Chat Completions 非流式响应转换为 Responses API 格式
+///
+/// 第一个候选的推理内容、文本和 tool_calls 依次转换为 reasoning、message、function_call 输出项；
+/// finish_reason 为 length 时 status 为 incomplete。content_path 含义与 openai_response_value_to_anthropic 相同
+pub fn chat_response_value_to_responses(chat_resp: &Value, model: &str, content_path: Option<&str>) -> Value {
+    let choice = chat_resp.pointer("/choices/0");
+    let mut output = Vec::new();
+    
+    if let Some(message) = choice.and_then(|c| c.get("message")) {
+        let (text, from_path) = message_text(message, content_path);
+        if let Some(reasoning) = message.get("reasoning_content")
+            .or_else(|| message.get("reasoning"))
+            .and_then(|r| r.as_str())
+            .filter(|r| !r.is_empty())
+            .filter(|r| !from_path || *r != text) {
+            output.push(responses_reasoning_item(&responses_item_id("rs"), reasoning));
+        }
+        if !text.is_empty() {
+            output.push(responses_message_item(&responses_item_id("msg"), text, "completed"));
+        }
+        for tool_call in message.get("tool_calls").and_then(|t| t.as_array()).into_iter().flatten() {
+            let function = tool_call.get("function");
+            let call_id = tool_call.get("id").and_then(|i| i.as_str())
+                .map(|i| i.to_string())
+                .unwrap_or_else(|| format!("call_{}", uuid::Uuid::new_v4().simple()));
+            output.push(responses_function_call_item(
+                &responses_item_id("fc"),
+                &call_id,
+                function.and_then(|f| f.get("name")).and_then(|n| n.as_str()).unwrap_or(""),
+                function.and_then(|f| f.get("arguments")).and_then(|a| a.as_str()).unwrap_or("{}"),
+                "completed",
+            ));
+        }
+    }
+    
+    let status = match choice.and_then(|c| c.get("finish_reason")).and_then(|f| f.as_str()) {
+        Some("length") => "incomplete",
+        _ => "completed",
+    };
+    let usage = chat_resp.get("usage").filter(|u| !u.is_null()).map(responses_usage).unwrap_or(Value::Null);
+    responses_object(&responses_item_id("resp"), model, unix_timestamp(), status, output, usage)
+}
+
+/// 将 Chat Completions 非流式响应转换为 Responses API 的 SSE 事件序列
+/// 用于上游忽略 `stream: true` 返回完整 JSON 时，向流式客户端回放
+pub fn chat_response_to_responses_sse(chat_body: &[u8], model: &str, content_path: Option<&str>) -> Result<Vec<String>, String> {
+    let chat_resp: Value = serde_json::from_slice(chat_body)
+        .map_err(|e| format!("Failed to parse Chat Completions response: {}", e))?;
+    
+    // 把完整消息当作一个 delta 分片交给流式转换器 (delta 中的 tool_calls 需要 index)
+    let choice = chat_resp.pointer("/choices/0").cloned().unwrap_or(Value::Null);
+    let mut delta = choice.get("message").cloned().unwrap_or_else(|| json!({}));
+    if let Some(tool_calls) = delta.get_mut("tool_calls").and_then(|t| t.as_array_mut()) {
+        for (index, tool_call) in tool_calls.iter_mut().enumerate() {
+            tool_call["index"] = json!(index);
+        }
+    }
+    let mut chunk = json!({
+        "choices": [{
+            "index": 0,
+            "delta": delta,
+            "finish_reason": choice.get("finish_reason").cloned().unwrap_or(Value::Null)
+        }]
+    });
+    if let Some(usage) = chat_resp.get("usage") {
+        chunk["usage"] = usage.clone();
+    }
+    
+    let mut converter = ChatToResponsesSseConverter::new(&responses_item_id("resp"), model)
+        .with_content_path(content_path);
+    let mut events = converter.convert_line(&format!("data: {}", chunk));
+    events.extend(converter.finish());
+    Ok(events)
+}
+
+/// 将完整的 Responses API 响应拆分为 SSE 事件序列
+/// 用于供应商禁用流式时，向请求了流式的 Responses 客户端回放
+pub fn responses_response_to_sse(responses_body: &[u8]) -> Result<Vec<String>, String> {
+    let resp: Value = serde_json::from_slice(responses_body)
+        .map_err(|e| format!("Failed to parse Responses response: {}", e))?;
+    Ok(responses_response_value_to_sse(&resp))
+}
+
+/// 将完整的 Responses API 响应 (Value) 拆分为 SSE 事件序列
+///
+/// response.created / response.in_progress 之后逐个回放输出项：message 的 output_text 按 SSE_TEXT_CHUNK_CHARS 拆分为
+/// output_text.delta，reasoning 的摘要拆分为 reasoning_summary_text.delta，function_call 的参数作为一个
+/// function_call_arguments.delta；其他类型的输出项只发送 added / done。最后按 status 发送
+/// response.completed / response.incomplete / response.failed
+pub fn responses_response_value_to_sse(resp: &Value) -> Vec<String> {
+    let mut events = Vec::new();
+    let mut sequence_number = 0u64;
+    let mut push = |event_type: &str, mut data: Value| {
+        data["type"] = json!(event_type);
+        data["sequence_number"] = json!(sequence_number);
+        sequence_number += 1;
+        events.push(format!("event: {}\ndata: {}", event_type, data));
+    };
+    
+    let mut in_progress = resp.clone();
+    in_progress["status"] = json!("in_progress");
+    in_progress["output"] = json!([]);
+    in_progress["usage"] = Value::Null;
+    if let Some(obj) = in_progress.as_object_mut() {
+        obj.remove("incomplete_details");
+        obj.remove("error");
+    }
+    push("response.created", json!({"response": in_progress.clone()}));
+    push("response.in_progress", json!({"response": in_progress}));
+    
+    let output = resp.get("output").and_then(|o| o.as_array()).cloned().unwrap_or_default();
+    for (output_index, item) in output.iter().enumerate() {
+        let item_id = item.get("id").cloned().unwrap_or(Value::Null);
+        let mut added = item.clone();
+        added["status"] = json!("in_progress");
+        match item.get("type").and_then(|t| t.as_str()).unwrap_or("") {
+            "message" => {
+                added["content"] = json!([]);
+                push("response.output_item.added", json!({"output_index": output_index, "item": added}));
+                let parts = item.get("content").and_then(|c| c.as_array()).cloned().unwrap_or_default();
+                for (content_index, part) in parts.iter().enumerate() {
+                    let is_text = part.get("type").and_then(|t| t.as_str()) == Some("output_text");
+                    let mut empty_part = part.clone();
+                    if is_text {
+                        empty_part["text"] = json!("");
+                    }
+                    push("response.content_part.added", json!({
+                        "item_id": item_id, "output_index": output_index, "content_index": content_index, "part": empty_part
+                    }));
+                    if is_text {
+                        let text = part.get("text").and_then(|t| t.as_str()).unwrap_or("");
+                        for delta in text_chunks(text) {
+                            push("response.output_text.delta", json!({
+                                "item_id": item_id, "output_index": output_index, "content_index": content_index, "delta": delta
+                            }));
+                        }
+                        push("response.output_text.done", json!({
+                            "item_id": item_id, "output_index": output_index, "content_index": content_index, "text": text
+                        }));
+                    }
+                    push("response.content_part.done", json!({
+                        "item_id": item_id, "output_index": output_index, "content_index": content_index, "part": part
+                    }));
+                }
+            }
+            "reasoning" => {
+                added["summary"] = json!([]);
+                push("response.output_item.added", json!({"output_index": output_index, "item": added}));
+                let summary = item.get("summary").and_then(|s| s.as_array()).cloned().unwrap_or_default();
+                for (summary_index, part) in summary.iter().enumerate() {
+                    let text = part.get("text").and_then(|t| t.as_str()).unwrap_or("");
+                    push("response.reasoning_summary_part.added", json!({
+                        "item_id": item_id, "output_index": output_index, "summary_index": summary_index,
+                        "part": {"type": "summary_text", "text": ""}
+                    }));
+                    for delta in text_chunks(text) {
+                        push("response.reasoning_summary_text.delta", json!({
+                            "item_id": item_id, "output_index": output_index, "summary_index": summary_index, "delta": delta
+                        }));
+                    }
+                    push("response.reasoning_summary_text.done", json!({
+                        "item_id": item_id, "output_index": output_index, "summary_index": summary_index, "text": text
+                    }));
+                    push("response.reasoning_summary_part.done", json!({
+                        "item_id": item_id, "output_index": output_index, "summary_index": summary_index, "part": part
+                    }));
+                }
+            }
+            "function_call" => {
+                added["arguments"] = json!("");
+                push("response.output_item.added", json!({"output_index": output_index, "item": added}));
+                let arguments = item.get("arguments").and_then(|a| a.as_str()).unwrap_or("");
+                if !arguments.is_empty() {
+                    push("response.function_call_arguments.delta", json!({
+                        "item_id": item_id, "output_index": output_index, "delta": arguments
+                    }));
+                }
+                push("response.function_call_arguments.done", json!({
+                    "item_id": item_id, "output_index": output_index, "arguments": arguments
+                }));
+            }
+            _ => push("response.output_item.added", json!({"output_index": output_index, "item": added})),
+        }
+        push("response.output_item.done", json!({"output_index": output_index, "item": item}));
+    }
+    
+    let status = match resp.get("status").and_then(|s| s.as_str()) {
+        Some("incomplete") => "incomplete",
+        Some("failed") => "failed",
+        _ => "completed",
+    };
+    push(&format!("response.{}", status), json!({"response": resp}));
+    events
+}
+
+/// 流式输出中当前打开的 Responses 输出项
+enum OpenResponsesItem {
+    Message { id: String, text: String },
+    Reasoning { id: String, text: String },
+    // 工具调用在拿到参数 (或结束) 时才发送 output_item.added，此前的分片可能只带 id / name
+    FunctionCall { tool_index: usize, id: String, call_id: String, name: String, arguments: String, announced: bool },
+}
+
+/// Chat Completions SSE -> Responses API SSE 的流式转换器
+///
+/// 按 delta 内容依次打开 reasoning、message、function_call 输出项，类型变化时先关闭上一个输出项：
+/// - delta.reasoning_content -> response.reasoning_summary_text.delta
+/// - delta.content -> response.output_text.delta
+/// - delta.tool_calls -> response.function_call_arguments.delta
+///
+/// 结束事件 (response.completed / response.incomplete) 在 [DONE] 或流结束时发送，
+/// 以便带上 finish_reason 之后单独发送的 usage 分片
+pub struct ChatToResponsesSseConverter {
+    response_id: String,
+    model: String,
+    created_at: u64,
+    started: bool,
+    finished: bool,
+    sequence_number: u64,
+    // 已完成的输出项，结束时放入完整的 response
+    output: Vec<Value>,
+    open_item: Option<OpenResponsesItem>,
+    usage: Option<Value>,
+    incomplete: bool,
+    // 流在工具调用中途中断时已收到的参数字节数
+    interrupted_tool_call: Option<usize>,
+    // 标准 content 为空时读取文本的 JSON Pointer (相对于 delta)
+    content_path: Option<String>,
+}
+
+impl ChatToResponsesSseConverter {
+    pub fn new(response_id: &str, model: &str) -> Self {
+        Self {
+            response_id: response_id.to_string(),
+            model: model.to_string(),
+            created_at: unix_timestamp(),
+            started: false,
+            finished: false,
+            sequence_number: 0,
+            output: Vec::new(),
+            open_item: None,
+            usage: None,
+            incomplete: false,
+            interrupted_tool_call: None,
+            content_path: None,
+        }
+    }
+    
+    /// 设置非标准的文本位置：delta.content 为空时按该 JSON Pointer 读取文本
+    pub fn with_content_path(mut self, content_path: Option<&str>) -> Self {
+        self.content_path = content_path.map(|p| p.to_string());
+        self
+    }
+    
+    /// 转换一行 Chat Completions SSE (`data: {...}`)，输出 Responses 的 `event: xxx\ndata: {...}`
+    pub fn convert_line(&mut self, line: &str) -> Vec<String> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        let Some(data) = line.strip_prefix("data: ") else {
+            return events;
+        };
+        if data.trim() == "[DONE]" {
+            return self.finish();
+        }
+        let Ok(chunk) = serde_json::from_str::<Value>(data) else {
+            return events;
+        };
+        
+        self.ensure_started(&mut events);
+        
+        // 开启 include_usage 时 usage 在最后一个 (choices 为空的) 分片中
+        if let Some(usage) = chunk.get("usage").filter(|u| !u.is_null()) {
+            self.usage = Some(responses_usage(usage));
+        }
+        
+        let Some(choice) = chunk.get("choices").and_then(|c| c.as_array()).and_then(|c| c.first()) else {
+            return events;
+        };
+        
+        if let Some(delta) = choice.get("delta") {
+            let (content, from_path) = message_text(delta, self.content_path.as_deref());
+            if let Some(reasoning) = delta.get("reasoning_content")
+                .or_else(|| delta.get("reasoning"))
+                .and_then(|r| r.as_str())
+                .filter(|r| !r.is_empty())
+                .filter(|r| !from_path || *r != content) {
+                self.reasoning_delta(reasoning, &mut events);
+            }
+            if !content.is_empty() {
+                self.text_delta(content, &mut events);
+            }
+            if let Some(tool_calls) = delta.get("tool_calls").and_then(|t| t.as_array()) {
+                for tool_call in tool_calls {
+                    self.tool_call_delta(tool_call, &mut events);
+                }
+            }
+        }
+        
+        if let Some(finish_reason) = choice.get("finish_reason").and_then(|f| f.as_str()) {
+            self.incomplete = finish_reason == "length";
+            self.close_item(&mut events);
+        }
+        
+        events
+    }
+    
+    /// 结束序列 (关闭当前输出项 + response.completed / response.incomplete)
+    /// 上游未发送 [DONE] 就结束流时由调用方调用；已结束时返回空
+    ///
+    /// 中断发生在工具调用中途且已发送的参数不是完整 JSON 时，丢弃该工具调用并以 response.failed 结束，
+    /// 不让客户端把不完整的参数当作正常的工具调用执行
+    pub fn finish(&mut self) -> Vec<String> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        self.ensure_started(&mut events);
+        
+        let incomplete_arguments = match &self.open_item {
+            Some(OpenResponsesItem::FunctionCall { arguments, .. }) if !tool_arguments_complete(arguments) => Some(arguments.len()),
+            _ => None,
+        };
+        if let Some(len) = incomplete_arguments {
+            self.interrupted_tool_call = Some(len);
+            self.open_item = None;
+            self.finished = true;
+            let mut response = responses_object(
+                &self.response_id,
+                &self.model,
+                self.created_at,
+                "failed",
+                self.output.clone(),
+                self.usage.clone().unwrap_or(Value::Null),
+            );
+            response["error"] = json!({
+                "code": "server_error",
+                "message": "Upstream stream was interrupted in the middle of a tool call; the tool arguments are incomplete"
+            });
+            self.push_event(&mut events, "response.failed", json!({"response": response}));
+            return events;
+        }
+        
+        self.close_item(&mut events);
+        self.finished = true;
+        
+        let status = if self.incomplete { "incomplete" } else { "completed" };
+        let response = responses_object(
+            &self.response_id,
+            &self.model,
+            self.created_at,
+            status,
+            self.output.clone(),
+            self.usage.clone().unwrap_or(Value::Null),
+        );
+        self.push_event(&mut events, &format!("response.{}", status), json!({"response": response}));
+        events
+    }
+    
+    /// 流是否在工具调用中途中断 (finish 发送了 response.failed)，返回已收到的参数字节数
+    pub fn interrupted_tool_call(&self) -> Option<usize> {
+        self.interrupted_tool_call
+    }
+    
+    fn ensure_started(&mut self, events: &mut Vec<String>) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        let response = responses_object(&self.response_id, &self.model, self.created_at, "in_progress", Vec::new(), Value::Null);
+        self.push_event(events, "response.created", json!({"response": response.clone()}));
+        self.push_event(events, "response.in_progress", json!({"response": response}));
+    }
+    
+    /// 追加一个事件 (自动填入 type 和递增的 sequence_number)
+    fn push_event(&mut self, events: &mut Vec<String>, event_type: &str, mut data: Value) {
+        data["type"] = json!(event_type);
+        data["sequence_number"] = json!(self.sequence_number);
+        self.sequence_number += 1;
+        events.push(format!("event: {}\ndata: {}", event_type, data));
+    }
+    
+    fn text_delta(&mut self, text: &str, events: &mut Vec<String>) {
+        if !matches!(self.open_item, Some(OpenResponsesItem::Message { .. })) {
+            self.close_item(events);
+            let output_index = self.output.len();
+            let id = responses_item_id("msg");
+            let mut item = responses_message_item(&id, "", "in_progress");
+            item["content"] = json!([]);
+            self.push_event(events, "response.output_item.added", json!({"output_index": output_index, "item": item}));
+            self.push_event(events, "response.content_part.added", json!({
+                "item_id": id,
+                "output_index": output_index,
+                "content_index": 0,
+                "part": {"type": "output_text", "text": "", "annotations": []}
+            }));
+            self.open_item = Some(OpenResponsesItem::Message { id, text: String::new() });
+        }
+        let Some(OpenResponsesItem::Message { id, text: buffer }) = &mut self.open_item else {
+            return;
+        };
+        buffer.push_str(text);
+        let item_id = id.clone();
+        let output_index = self.output.len();
+        self.push_event(events, "response.output_text.delta", json!({
+            "item_id": item_id,
+            "output_index": output_index,
+            "content_index": 0,
+            "delta": text
+        }));
+    }
+    
+    fn reasoning_delta(&mut self, text: &str, events: &mut Vec<String>) {
+        if !matches!(self.open_item, Some(OpenResponsesItem::Reasoning { .. })) {
+            self.close_item(events);
+            let output_index = self.output.len();
+            let id = responses_item_id("rs");
+            self.push_event(events, "response.output_item.added", json!({
+                "output_index": output_index,
+                "item": {"type": "reasoning", "id": id, "summary": []}
+            }));
+            self.push_event(events, "response.reasoning_summary_part.added", json!({
+                "item_id": id,
+                "output_index": output_index,
+                "summary_index": 0,
+                "part": {"type": "summary_text", "text": ""}
+            }));
+            self.open_item = Some(OpenResponsesItem::Reasoning { id, text: String::new() });
+        }
+        let Some(OpenResponsesItem::Reasoning { id, text: buffer }) = &mut self.open_item else {
+            return;
+        };
+        buffer.push_str(text);
+        let item_id = id.clone();
+        let output_index = self.output.len();
+        self.push_event(events, "response.reasoning_summary_text.delta", json!({
+            "item_id": item_id,
+            "output_index": output_index,
+            "summary_index": 0,
+            "delta": text
+        }));
+    }
+    
+    /// 处理一个 tool_calls delta 分片 (同一调用的后续分片通常只带 index 和参数片段)
+    fn tool_call_delta(&mut self, tool_call: &Value, events: &mut Vec<String>) {
+        let tool_index = tool_call.get("index").and_then(|i| i.as_u64()).unwrap_or(0) as usize;
+        let function = tool_call.get("function");
+        let arguments_delta = function.and_then(|f| f.get("arguments")).and_then(|a| a.as_str()).unwrap_or("");
+        
+        let is_open = matches!(&self.open_item, Some(OpenResponsesItem::FunctionCall { tool_index: open, .. }) if *open == tool_index);
+        if !is_open {
+            self.close_item(events);
+            self.open_item = Some(OpenResponsesItem::FunctionCall {
+                tool_index,
+                id: responses_item_id("fc"),
+                call_id: String::new(),
+                name: String::new(),
+                arguments: String::new(),
+                announced: false,
+            });
+        }
+        if let Some(OpenResponsesItem::FunctionCall { call_id, name, .. }) = &mut self.open_item {
+            call_id.push_str(tool_call.get("id").and_then(|i| i.as_str()).unwrap_or(""));
+            name.push_str(function.and_then(|f| f.get("name")).and_then(|n| n.as_str()).unwrap_or(""));
+        }
+        if arguments_delta.is_empty() {
+            return;
+        }
+        
+        self.announce_function_call(events);
+        let output_index = self.output.len();
+        let Some(OpenResponsesItem::FunctionCall { id, arguments, .. }) = &mut self.open_item else {
+            return;
+        };
+        arguments.push_str(arguments_delta);
+        let item_id = id.clone();
+        self.push_event(events, "response.function_call_arguments.delta", json!({
+            "item_id": item_id,
+            "output_index": output_index,
+            "delta": arguments_delta
+        }));
+    }
+    
+    /// 为当前的工具调用发送 output_item.added (缺少 call_id 时生成一个)
+    fn announce_function_call(&mut self, events: &mut Vec<String>) {
+        let output_index = self.output.len();
+        let Some(OpenResponsesItem::FunctionCall { id, call_id, name, announced, .. }) = &mut self.open_item else {
+            return;
+        };
+        if *announced {
+            return;
+        }
+        *announced = true;
+        if call_id.is_empty() {
+            *call_id = format!("call_{}", uuid::Uuid::new_v4().simple());
+        }
+        let item = responses_function_call_item(id, call_id, name, "", "in_progress");
+        self.push_event(events, "response.output_item.added", json!({"output_index": output_index, "item": item}));
+    }
+    
+    /// 关闭当前输出项：发送对应的 done 事件并记入 output
+    fn close_item(&mut self, events: &mut Vec<String>) {
+        self.announce_function_call(events);
+        let output_index = self.output.len();
+        let item = match self.open_item.take() {
+            None => return,
+            Some(OpenResponsesItem::Message { id, text }) => {
+                self.push_event(events, "response.output_text.done", json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "text": text
+                }));
+                self.push_event(events, "response.content_part.done", json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": {"type": "output_text", "text": text, "annotations": []}
+                }));
+                responses_message_item(&id, &text, "completed")
+            }
+            Some(OpenResponsesItem::Reasoning { id, text }) => {
+                self.push_event(events, "response.reasoning_summary_text.done", json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "summary_index": 0,
+                    "text": text
+                }));
+                self.push_event(events, "response.reasoning_summary_part.done", json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "summary_index": 0,
+                    "part": {"type": "summary_text", "text": text}
+                }));
+                responses_reasoning_item(&id, &text)
+            }
+            Some(OpenResponsesItem::FunctionCall { id, call_id, name, arguments, .. }) => {
+                // 没有参数的工具调用以 {} 作为参数，客户端总能解析为 JSON
+                let arguments = if arguments.trim().is_empty() { "{}".to_string() } else { arguments };
+                self.push_event(events, "response.function_call_arguments.done", json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "arguments": arguments
+                }));
+                responses_function_call_item(&id, &call_id, &name, &arguments, "completed")
+            }
+        };
+        self.push_event(events, "response.output_item.done", json!({"output_index": output_index, "item": item}));
+        self.output.push(item);
+    }
+}
+
+/// 生成 Responses 对象 id (如 resp_xxx、msg_xxx、fc_xxx)
+fn responses_item_id(prefix: &str) -> String {
+    format!("{}_{}", prefix, uuid::Uuid::new_v4().simple())
+}
+
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// 完整的 Responses response 对象
+fn responses_object(id: &str, model: &str, created_at: u64, status: &str, output: Vec<Value>, usage: Value) -> Value {
+    let mut response = json!({
+        "id": id,
+        "object": "response",
+        "created_at": created_at,
+        "status": status,
+        "model": model,
+        "output": output,
+        "usage": usage
+    });
+    if status == "incomplete" {
+        response["incomplete_details"] = json!({"reason": "max_output_tokens"});
+    }
+    response
+}
+
+fn responses_message_item(id: &str, text: &str, status: &str) -> Value {
+    json!({
+        "type": "message",
+        "id": id,
+        "status": status,
+        "role": "assistant",
+        "content": [{"type": "output_text", "text": text, "annotations": []}]
+    })
+}
+
+fn responses_reasoning_item(id: &str, text: &str) -> Value {
+    json!({
+        "type": "reasoning",
+        "id": id,
+        "summary": [{"type": "summary_text", "text": text}]
+    })
+}
+
+fn responses_function_call_item(id: &str, call_id: &str, name: &str, arguments: &str, status: &str) -> Value {
+    json!({
+        "type": "function_call",
+        "id": id,
+        "call_id": call_id,
+        "name": name,
+        "arguments": arguments,
+        "status": status
+    })
+}
+
+/// Chat 的 usage (prompt_tokens / completion_tokens) 转换为 Responses 的 usage
+fn responses_usage(usage: &Value) -> Value {
+    let token = |key: &str| usage.get(key).and_then(|t| t.as_u64()).unwrap_or(0);
+    let (input_tokens, output_tokens) = (token("prompt_tokens"), token("completion_tokens"));
+    json!({
+        "input_tokens": input_tokens,
+        "output_tokens": output_tokens,
+        "total_tokens": usage.get("total_tokens").and_then(|t| t.as_u64()).unwrap_or(input_tokens + output_tokens)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// 解析 SSE 事件 (`event: xxx\ndata: {...}`) 中的 data 部分
+    fn event_data(event: &str) -> Value {
+        let data = event.lines()
+            .find_map(|line| line.strip_prefix("data: "))
+            .expect("event has no data line");
+        serde_json::from_str(data).expect("event data is not JSON")
+    }
+    
+    fn event_types(events: &[String]) -> Vec<String> {
+        events.iter()
+            .map(|e| event_data(e)["type"].as_str().unwrap_or("").to_string())
+            .collect()
+    }
+    
+    fn chat_chunk(delta: Value, finish_reason: Value) -> String {
+        format!("data: {}", json!({"choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]}))
+    }
+    
+    #[test]
+    fn converts_basic_anthropic_request() {
+        let request = json!({
+            "model": "claude-sonnet",
+            "system": "You are helpful",
+            "max_tokens": 1024,
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+        let mut options = ConversionOptions::default();
+        options.model_mapping.insert("claude-sonnet".to_string(), "gpt-4o".to_string());
+        
+        let openai = anthropic_request_to_openai(&request, &options).unwrap();
+        assert_eq!(openai["model"], "gpt-4o");
+        assert_eq!(openai["max_tokens"], 1024);
+        assert_eq!(openai["stream"], false);
+        assert!(openai.get("stream_options").is_none());
+        assert_eq!(openai["messages"], json!([
+            {"role": "system", "content": "You are helpful"},
+            {"role": "user", "content": "hi"}
+        ]));
+    }
+    
+    #[test]
+    fn keeps_inline_system_messages_in_place() {
+        let request = json!({
+            "model": "claude-sonnet",
+            "system": "You are helpful",
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "system", "content": "Tool context"},
+                {"role": "assistant", "content": "hello"},
+                {"role": "developer", "content": [{"type": "text", "text": "Be brief"}]},
+                {"role": "user", "content": "again"}
+            ]
+        });
+        let openai = anthropic_request_to_openai(&request, &ConversionOptions::default()).unwrap();
+        let roles: Vec<&str> = openai["messages"].as_array().unwrap()
+            .iter()
+            .map(|m| m["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(roles, ["system", "user", "system", "assistant", "system", "user"]);
+        assert_eq!(openai["messages"][2]["content"], "Tool context");
+        assert_eq!(openai["messages"][4]["content"], "Be brief");
+        
+        let options = ConversionOptions { system_role: SystemRole::Developer, ..Default::default() };
+        let openai = anthropic_request_to_openai(&request, &options).unwrap();
+        assert_eq!(openai["messages"][2]["role"], "developer");
+    }
+    
+    #[test]
+    fn streaming_request_asks_for_usage() {
+        let request = json!({"model": "m", "stream": true, "messages": [{"role": "user", "content": "hi"}]});
+        let openai = anthropic_request_to_openai(&request, &ConversionOptions::default()).unwrap();
+        assert_eq!(openai["stream_options"], json!({"include_usage": true}));
+        
+        // 不接受 stream_options 的上游可以关闭
+        let options = ConversionOptions { disable_stream_usage: true, ..Default::default() };
+        let openai = anthropic_request_to_openai(&request, &options).unwrap();
+        assert!(openai.get("stream_options").is_none());
+    }
+    
+    #[test]
+    fn normalizes_stop_sequences() {
+        let request = |stop: Value| json!({"model": "m", "stop_sequences": stop, "messages": [{"role": "user", "content": "hi"}]});
+        let defaults = ConversionOptions::default();
+        
+        // 空数组不发送 stop
+        let openai = anthropic_request_to_openai(&request(json!([])), &defaults).unwrap();
+        assert!(openai.get("stop").is_none());
+        
+        // 单个序列默认仍为数组，开启 single_stop_as_string 时为字符串
+        let openai = anthropic_request_to_openai(&request(json!(["END"])), &defaults).unwrap();
+        assert_eq!(openai["stop"], json!(["END"]));
+        let as_string = ConversionOptions { single_stop_as_string: true, ..Default::default() };
+        let openai = anthropic_request_to_openai(&request(json!(["END"])), &as_string).unwrap();
+        assert_eq!(openai["stop"], json!("END"));
+        
+        // 超过上限时截断 (默认 4 个，可按供应商配置)
+        let many = json!(["a", "b", "c", "d", "e", "f"]);
+        let openai = anthropic_request_to_openai(&request(many.clone()), &defaults).unwrap();
+        assert_eq!(openai["stop"], json!(["a", "b", "c", "d"]));
+        let two = ConversionOptions { max_stop_sequences: Some(2), ..Default::default() };
+        let openai = anthropic_request_to_openai(&request(many.clone()), &two).unwrap();
+        assert_eq!(openai["stop"], json!(["a", "b"]));
+        
+        // 丢弃的数量通过字节版本的结果返回，由调用方记录
+        let body = serde_json::to_vec(&request(many)).unwrap();
+        assert_eq!(anthropic_to_openai(&body, &defaults).unwrap().dropped_stop_sequences, 2);
+        assert_eq!(anthropic_to_openai(&body, &two).unwrap().dropped_stop_sequences, 4);
+        let body = serde_json::to_vec(&request(json!(["a", "", "b"]))).unwrap();
+        assert_eq!(anthropic_to_openai(&body, &two).unwrap().dropped_stop_sequences, 0);
+    }
+    
+    #[test]
+    fn maps_metadata_user_id_to_user() {
+        let request = json!({
+            "model": "m",
+            "metadata": {"user_id": "user-123"},
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+        let openai = anthropic_request_to_openai(&request, &ConversionOptions::default()).unwrap();
+        assert_eq!(openai["user"], "user-123");
+        
+        for metadata in [json!(null), json!({}), json!({"user_id": ""})] {
+            let request = json!({"model": "m", "metadata": metadata, "messages": [{"role": "user", "content": "hi"}]});
+            let openai = anthropic_request_to_openai(&request, &ConversionOptions::default()).unwrap();
+            assert!(openai.get("user").is_none());
+        }
+        let openai = anthropic_request_to_openai(&json!({"model": "m", "messages": []}), &ConversionOptions::default()).unwrap();
+        assert!(openai.get("user").is_none());
+    }
+    
+    #[test]
+    fn missing_model_is_invalid_request() {
+        let request = json!({"messages": [{"role": "user", "content": "hi"}]});
+        let err = anthropic_request_to_openai(&request, &ConversionOptions::default()).unwrap_err();
+        assert!(matches!(err, ConversionError::InvalidRequest(_)));
+    }
+    
+    #[test]
+    fn converts_tool_use_and_tool_result() {
+        let request = json!({
+            "model": "m",
+            "messages": [
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "checking"},
+                    {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "sunny"}
+                ]}
+            ]
+        });
+        let openai = anthropic_request_to_openai(&request, &ConversionOptions::default()).unwrap();
+        let messages = openai["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["role"], "assistant");
+        assert_eq!(messages[0]["content"], "checking");
+        assert_eq!(messages[0]["tool_calls"][0]["id"], "toolu_1");
+        assert_eq!(messages[0]["tool_calls"][0]["function"]["name"], "get_weather");
+        let arguments: Value = serde_json::from_str(messages[0]["tool_calls"][0]["function"]["arguments"].as_str().unwrap()).unwrap();
+        assert_eq!(arguments, json!({"city": "Paris"}));
+        assert_eq!(messages[1], json!({"role": "tool", "tool_call_id": "toolu_1", "content": "sunny"}));
+    }
+    
+    #[test]
+    fn converts_openai_response_with_tool_calls() {
+        let response = json!({
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "lookup", "arguments": "{\"q\":\"x\"}"}}]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5}
+        });
+        let anthropic = openai_response_value_to_anthropic(&response, "claude", None);
+        assert_eq!(anthropic["stop_reason"], "tool_use");
+        assert_eq!(anthropic["content"], json!([
+            {"type": "tool_use", "id": "call_1", "name": "lookup", "input": {"q": "x"}}
+        ]));
+        assert_eq!(anthropic["usage"], json!({"input_tokens": 10, "output_tokens": 5}));
+    }
+    
+    #[test]
+    fn streams_text_as_anthropic_events() {
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        let mut events = converter.convert_line(&chat_chunk(json!({"role": "assistant", "content": "Hel"}), Value::Null));
+        events.extend(converter.convert_line(&chat_chunk(json!({"content": "lo"}), Value::Null)));
+        events.extend(converter.convert_line(&chat_chunk(json!({}), json!("stop"))));
+        events.extend(converter.convert_line("data: [DONE]"));
+        events.extend(converter.finish());
+        
+        assert_eq!(event_types(&events), [
+            "message_start",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]);
+        assert_eq!(event_data(&events[2])["delta"]["text"], "Hel");
+        assert_eq!(event_data(&events[5])["delta"]["stop_reason"], "end_turn");
+    }
+    
+    #[test]
+    fn length_finish_reason_maps_to_max_tokens() {
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        converter.convert_line(&chat_chunk(json!({"content": "partial"}), Value::Null));
+        let mut events = converter.convert_line(&chat_chunk(json!({}), json!("length")));
+        events.extend(converter.convert_line("data: [DONE]"));
+        let message_delta = events.iter().find(|e| event_data(e)["type"] == "message_delta").unwrap();
+        assert_eq!(event_data(message_delta)["delta"]["stop_reason"], "max_tokens");
+        
+        let response = json!({
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "partial"}, "finish_reason": "length"}]
+        });
+        let anthropic = openai_response_value_to_anthropic(&response, "claude", None);
+        assert_eq!(anthropic["stop_reason"], "max_tokens");
+    }
+    
+    #[test]
+    fn reads_text_from_non_standard_content_path() {
+        // 非流式：message.content 为空，文本在 message.output.text
+        let response = json!({
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "", "output": {"text": "nested"}}, "finish_reason": "stop"}]
+        });
+        let anthropic = openai_response_value_to_anthropic(&response, "claude", Some("/output/text"));
+        assert_eq!(anthropic["content"], json!([{"type": "text", "text": "nested"}]));
+        // 未配置路径时仍按标准位置读取
+        let anthropic = openai_response_value_to_anthropic(&response, "claude", None);
+        assert_eq!(anthropic["content"], json!([{"type": "text", "text": ""}]));
+        
+        // 流式：delta.content 缺失时按路径读取，标准位置有文本时优先使用标准位置
+        let mut converter = OpenAISseConverter::new("msg_1", "claude").with_content_path(Some("/output/text"));
+        let mut events = converter.convert_line(&chat_chunk(json!({"output": {"text": "from path"}}), Value::Null));
+        events.extend(converter.convert_line(&chat_chunk(json!({"content": " standard", "output": {"text": "ignored"}}), json!("stop"))));
+        let text: String = events.iter()
+            .map(|e| event_data(e))
+            .filter(|d| d["type"] == "content_block_delta")
+            .map(|d| d["delta"]["text"].as_str().unwrap_or("").to_string())
+            .collect();
+        assert_eq!(text, "from path standard");
+    }
+    
+    #[test]
+    fn holds_message_delta_until_usage_arrives() {
+        // include_usage：usage 在 finish_reason 之后的分片中，message_delta 等到该分片再发送
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        converter.convert_line(&chat_chunk(json!({"content": "hi"}), Value::Null));
+        let events = converter.convert_line(&chat_chunk(json!({}), json!("stop")));
+        assert_eq!(event_types(&events), ["content_block_stop"]);
+        let events = converter.convert_line(r#"data: {"choices":[],"usage":{"prompt_tokens":9,"completion_tokens":3}}"#);
+        assert_eq!(event_types(&events), ["message_delta", "message_stop"]);
+        assert_eq!(event_data(&events[0])["delta"]["stop_reason"], "end_turn");
+        assert_eq!(event_data(&events[0])["usage"], json!({"input_tokens": 9, "output_tokens": 3}));
+        assert!(converter.convert_line("data: [DONE]").is_empty());
+        assert!(converter.finish().is_empty());
+        
+        // usage 与 finish_reason 在同一个分片中时立即结束
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        let events = converter.convert_line(&format!("data: {}", json!({
+            "choices": [{"index": 0, "delta": {"content": "hi"}, "finish_reason": "length"}],
+            "usage": {"prompt_tokens": 9, "completion_tokens": 1}
+        })));
+        assert_eq!(event_types(&events).last().map(String::as_str), Some("message_stop"));
+        assert_eq!(event_data(&events[events.len() - 2])["usage"]["output_tokens"], 1);
+        
+        // 上游不发送 usage：[DONE] 或流结束时以 0 结束
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        converter.convert_line(&chat_chunk(json!({"content": "hi"}), json!("stop")));
+        let events = converter.finish();
+        assert_eq!(event_types(&events), ["message_delta", "message_stop"]);
+        assert_eq!(event_data(&events[0])["usage"]["output_tokens"], 0);
+    }
+    
+    #[test]
+    fn streams_tool_call_arguments_as_input_json_delta() {
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        let mut events = converter.convert_line(&chat_chunk(json!({"tool_calls": [
+            {"index": 0, "id": "call_1", "type": "function", "function": {"name": "lookup", "arguments": ""}}
+        ]}), Value::Null));
+        events.extend(converter.convert_line(&chat_chunk(json!({"tool_calls": [
+            {"index": 0, "function": {"arguments": "{\"q\":1}"}}
+        ]}), Value::Null)));
+        events.extend(converter.convert_line(&chat_chunk(json!({}), json!("tool_calls"))));
+        events.extend(converter.convert_line("data: [DONE]"));
+        
+        let start = event_data(&events[1]);
+        assert_eq!(start["content_block"], json!({"type": "tool_use", "id": "call_1", "name": "lookup", "input": {}}));
+        assert_eq!(event_data(&events[2])["delta"], json!({"type": "input_json_delta", "partial_json": "{\"q\":1}"}));
+        assert_eq!(event_data(&events[4])["delta"]["stop_reason"], "tool_use");
+    }
+    
+    #[test]
+    fn mid_argument_disconnect_signals_an_error() {
+        let tool_call = |arguments: &str| chat_chunk(json!({"tool_calls": [{
+            "index": 0,
+            "id": "call_1",
+            "function": {"name": "read_file", "arguments": arguments}
+        }]}), Value::Null);
+        
+        // Anthropic：参数不完整时关闭 tool_use 块并发送 error，不发送 message_delta
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        converter.convert_line(&tool_call(r#"{"path": "src/ma"#));
+        let events = converter.finish();
+        assert_eq!(event_types(&events), ["content_block_stop", "error"]);
+        assert!(converter.finish().is_empty());
+        assert_eq!(converter.interrupted_tool_call(), Some(16));
+        
+        // 参数恰好是完整 JSON 时按 tool_use 正常结束
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        converter.convert_line(&tool_call(r#"{"path": "src/main.rs"}"#));
+        let events = converter.finish();
+        assert_eq!(event_types(&events), ["content_block_stop", "message_delta", "message_stop"]);
+        assert_eq!(event_data(&events[1])["delta"]["stop_reason"], "tool_use");
+        assert_eq!(converter.interrupted_tool_call(), None);
+        
+        // Responses：参数不完整时以 response.failed 结束，输出中不包含该工具调用
+        let mut converter = ChatToResponsesSseConverter::new("resp_1", "gpt-4o");
+        converter.convert_line(&chat_chunk(json!({"content": "Reading"}), Value::Null));
+        converter.convert_line(&tool_call(r#"{"path": "src/ma"#));
+        let events = converter.finish();
+        assert_eq!(event_types(&events), ["response.failed"]);
+        let response = &event_data(&events[0])["response"];
+        assert_eq!(response["status"], "failed");
+        assert_eq!(response["output"].as_array().unwrap().len(), 1);
+        assert_eq!(response["output"][0]["type"], "message");
+        assert_eq!(converter.interrupted_tool_call(), Some(16));
+        
+        let mut converter = ChatToResponsesSseConverter::new("resp_1", "gpt-4o");
+        converter.convert_line(&tool_call(r#"{"path": "src/main.rs"}"#));
+        let events = converter.finish();
+        assert_eq!(event_types(&events).last().map(String::as_str), Some("response.completed"));
+    }
+    
+    #[test]
+    fn disconnect_after_zero_argument_tool_call_ends_normally() {
+        // 没有参数的工具调用：上游只发送 id 和名称，参数为空
+        let tool_call = chat_chunk(json!({"tool_calls": [{
+            "index": 0,
+            "id": "call_1",
+            "function": {"name": "list_files", "arguments": ""}
+        }]}), Value::Null);
+        
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        converter.convert_line(&tool_call);
+        let events = converter.finish();
+        assert_eq!(event_types(&events), ["content_block_start", "content_block_stop", "message_delta", "message_stop"]);
+        assert_eq!(event_data(&events[0])["content_block"]["name"], "list_files");
+        assert_eq!(event_data(&events[2])["delta"]["stop_reason"], "tool_use");
+        assert_eq!(converter.interrupted_tool_call(), None);
+        
+        let mut converter = ChatToResponsesSseConverter::new("resp_1", "gpt-4o");
+        converter.convert_line(&tool_call);
+        let events = converter.finish();
+        assert_eq!(event_types(&events).last().map(String::as_str), Some("response.completed"));
+        let response = &event_data(events.last().unwrap())["response"];
+        assert_eq!(response["output"][0]["type"], "function_call");
+        assert_eq!(response["output"][0]["name"], "list_files");
+        assert_eq!(response["output"][0]["arguments"], "{}");
+        assert_eq!(converter.interrupted_tool_call(), None);
+    }
+    
+    #[test]
+    fn converts_responses_request_to_chat() {
+        let request = json!({
+            "model": "gpt-4o",
+            "instructions": "Be brief",
+            "input": [
+                {"role": "user", "content": [{"type": "input_text", "text": "weather?"}]},
+                {"type": "function_call", "call_id": "call_1", "name": "get_weather", "arguments": "{}"},
+                {"type": "function_call_output", "call_id": "call_1", "output": "sunny"}
+            ],
+            "max_output_tokens": 100,
+            "stream": true,
+            "tools": [
+                {"type": "function", "name": "get_weather", "parameters": {"type": "object"}},
+                {"type": "web_search"}
+            ]
+        });
+        let chat = responses_request_to_chat(&request, &ConversionOptions::default()).unwrap();
+        assert_eq!(chat["messages"], json!([
+            {"role": "system", "content": "Be brief"},
+            {"role": "user", "content": "weather?"},
+            {"role": "assistant", "content": null, "tool_calls": [
+                {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{}"}}
+            ]},
+            {"role": "tool", "tool_call_id": "call_1", "content": "sunny"}
+        ]));
+        assert_eq!(chat["max_tokens"], 100);
+        assert_eq!(chat["stream_options"], json!({"include_usage": true}));
+        assert_eq!(chat["tools"], json!([
+            {"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}}
+        ]));
+    }
+    
+    #[test]
+    fn converts_chat_response_to_responses() {
+        let response = json!({
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "hello"}, "finish_reason": "length"}],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 2}
+        });
+        let responses = chat_response_value_to_responses(&response, "gpt-4o", None);
+        assert_eq!(responses["status"], "incomplete");
+        assert_eq!(responses["output"][0]["type"], "message");
+        assert_eq!(responses["output"][0]["content"][0]["text"], "hello");
+        assert_eq!(responses["usage"], json!({"input_tokens": 3, "output_tokens": 2, "total_tokens": 5}));
+    }
+    
+    #[test]
+    fn streams_chat_chunks_as_responses_events() {
+        let mut converter = ChatToResponsesSseConverter::new("resp_1", "gpt-4o");
+        let mut events = converter.convert_line(&chat_chunk(json!({"content": "hi"}), Value::Null));
+        events.extend(converter.convert_line(&chat_chunk(json!({}), json!("stop"))));
+        events.extend(converter.convert_line(&format!("data: {}", json!({
+            "choices": [],
+            "usage": {"prompt_tokens": 4, "completion_tokens": 1}
+        }))));
+        events.extend(converter.convert_line("data: [DONE]"));
+        
+        assert_eq!(event_types(&events), [
+            "response.created",
+            "response.in_progress",
+            "response.output_item.added",
+            "response.content_part.added",
+            "response.output_text.delta",
+            "response.output_text.done",
+            "response.content_part.done",
+            "response.output_item.done",
+            "response.completed",
+        ]);
+        let sequence: Vec<u64> = events.iter().map(|e| event_data(e)["sequence_number"].as_u64().unwrap()).collect();
+        assert_eq!(sequence, (0..events.len() as u64).collect::<Vec<_>>());
+        let completed = event_data(events.last().unwrap());
+        assert_eq!(completed["response"]["output"][0]["content"][0]["text"], "hi");
+        assert_eq!(completed["response"]["usage"]["output_tokens"], 1);
+    }
+    
+    // Responses 上游的流式响应 (节选自 /v1/responses 的实际事件序列)
+    const RESPONSES_TEXT_STREAM: &str = r#"event: response.created
+data: {"type":"response.created","sequence_number":0,"response":{"id":"resp_1","status":"in_progress","output":[]}}
+
+event: response.output_item.added
+data: {"type":"response.output_item.added","sequence_number":1,"output_index":0,"item":{"type":"reasoning","id":"rs_1","summary":[]}}
+
+event: response.reasoning_summary_text.delta
+data: {"type":"response.reasoning_summary_text.delta","sequence_number":2,"item_id":"rs_1","output_index":0,"summary_index":0,"delta":"Greet back"}
+
+event: response.output_item.added
+data: {"type":"response.output_item.added","sequence_number":3,"output_index":1,"item":{"type":"message","id":"msg_1","role":"assistant","content":[]}}
+
+event: response.output_text.delta
+data: {"type":"response.output_text.delta","sequence_number":4,"item_id":"msg_1","output_index":1,"content_index":0,"delta":"Hel"}
+
+event: response.output_text.delta
+data: {"type":"response.output_text.delta","sequence_number":5,"item_id":"msg_1","output_index":1,"content_index":0,"delta":"lo"}
+
+event: response.output_text.done
+data: {"type":"response.output_text.done","sequence_number":6,"item_id":"msg_1","output_index":1,"content_index":0,"text":"Hello"}
+
+event: response.completed
+data: {"type":"response.completed","sequence_number":7,"response":{"id":"resp_1","status":"completed","output":[{"type":"reasoning","id":"rs_1"},{"type":"message","id":"msg_1"}],"usage":{"input_tokens":12,"output_tokens":5,"total_tokens":17}}}
+"#;
+    
+    const RESPONSES_TOOL_STREAM: &str = r#"event: response.output_item.added
+data: {"type":"response.output_item.added","sequence_number":1,"output_index":0,"item":{"type":"function_call","id":"fc_1","call_id":"call_1","name":"read_file","arguments":"","status":"in_progress"}}
+
+event: response.function_call_arguments.delta
+data: {"type":"response.function_call_arguments.delta","sequence_number":2,"item_id":"fc_1","output_index":0,"delta":"{\"path\":"}
+
+event: response.function_call_arguments.delta
+data: {"type":"response.function_call_arguments.delta","sequence_number":3,"item_id":"fc_1","output_index":0,"delta":"\"a.rs\"}"}
+
+event: response.completed
+data: {"type":"response.completed","sequence_number":4,"response":{"id":"resp_1","status":"completed","output":[{"type":"function_call","id":"fc_1","call_id":"call_1","name":"read_file","arguments":"{\"path\":\"a.rs\"}"}],"usage":{"input_tokens":20,"output_tokens":8}}}
+"#;
+    
+    #[test]
+    fn streams_responses_events_as_anthropic() {
+        let events = responses_sse_to_anthropic(RESPONSES_TEXT_STREAM, "msg_1", "claude");
+        assert_eq!(event_types(&events), [
+            "message_start",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_stop",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]);
+        assert_eq!(event_data(&events[2])["delta"], json!({"type": "thinking_delta", "thinking": "Greet back"}));
+        let text: String = events.iter()
+            .map(|e| event_data(e))
+            .filter(|d| d["delta"]["type"] == "text_delta")
+            .map(|d| d["delta"]["text"].as_str().unwrap_or("").to_string())
+            .collect();
+        assert_eq!(text, "Hello");
+        assert_eq!(event_data(&events[5])["index"], 1);
+        
+        let message_delta = event_data(&events[8]);
+        assert_eq!(message_delta["delta"]["stop_reason"], "end_turn");
+        assert_eq!(message_delta["usage"], json!({"input_tokens": 12, "output_tokens": 5}));
+    }
+    
+    #[test]
+    fn streams_responses_function_calls_as_tool_use() {
+        let events = responses_sse_to_anthropic(RESPONSES_TOOL_STREAM, "msg_1", "claude");
+        assert_eq!(event_types(&events), [
+            "message_start",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]);
+        assert_eq!(event_data(&events[1])["content_block"], json!({"type": "tool_use", "id": "call_1", "name": "read_file", "input": {}}));
+        let arguments: String = events[2..4].iter()
+            .map(|e| event_data(e)["delta"]["partial_json"].as_str().unwrap_or("").to_string())
+            .collect();
+        assert_eq!(arguments, r#"{"path":"a.rs"}"#);
+        assert_eq!(event_data(&events[5])["delta"]["stop_reason"], "tool_use");
+    }
+    
+    #[test]
+    fn ends_responses_stream_on_incomplete_failure_and_disconnect() {
+        // max_output_tokens 截断 -> max_tokens
+        let incomplete = r#"data: {"type":"response.output_text.delta","output_index":0,"delta":"Hi"}
+data: {"type":"response.incomplete","response":{"status":"incomplete","incomplete_details":{"reason":"max_output_tokens"},"output":[],"usage":{"input_tokens":3,"output_tokens":1}}}"#;
+        let events = responses_sse_to_anthropic(incomplete, "msg_1", "claude");
+        assert_eq!(event_data(&events[events.len() - 2])["delta"]["stop_reason"], "max_tokens");
+        
+        // response.failed -> error 事件，不发送 message_delta
+        let failed = r#"data: {"type":"response.output_text.delta","output_index":0,"delta":"Hi"}
+data: {"type":"response.failed","response":{"status":"failed","error":{"code":"server_error","message":"boom"}}}"#;
+        let events = responses_sse_to_anthropic(failed, "msg_1", "claude");
+        assert_eq!(event_types(&events), ["message_start", "content_block_start", "content_block_delta", "content_block_stop", "error"]);
+        assert_eq!(event_data(&events[4])["error"]["message"], "boom");
+        
+        // 没有完成事件就断开：补发 end_turn 结束序列
+        let events = responses_sse_to_anthropic(r#"data: {"type":"response.output_text.delta","output_index":0,"delta":"Hi"}"#, "msg_1", "claude");
+        assert_eq!(event_types(&events).last().map(String::as_str), Some("message_stop"));
+        assert_eq!(event_data(&events[events.len() - 2])["delta"]["stop_reason"], "end_turn");
+    }
+    
+    #[test]
+    fn converts_anthropic_request_to_responses() {
+        let request = json!({
+            "model": "claude-sonnet",
+            "max_tokens": 1024,
+            "stream": true,
+            "system": "Be brief",
+            "stop_sequences": ["END"],
+            "messages": [
+                {"role": "user", "content": "read a.rs"},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "Reading"},
+                    {"type": "tool_use", "id": "call_1", "name": "read_file", "input": {"path": "a.rs"}}
+                ]},
+                {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "call_1", "content": "fn main() {}"}]}
+            ],
+            "tools": [{"name": "read_file", "description": "Read a file", "input_schema": {"type": "object"}}],
+            "tool_choice": {"type": "tool", "name": "read_file"}
+        });
+        let mut options = ConversionOptions::default();
+        options.model_mapping.insert("claude-sonnet".to_string(), "gpt-5-codex".to_string());
+        let responses = anthropic_request_to_responses(&request, &options).unwrap();
+        
+        assert_eq!(responses["model"], "gpt-5-codex");
+        assert_eq!(responses["max_output_tokens"], 1024);
+        assert_eq!(responses["stream"], true);
+        assert_eq!(responses["input"], json!([
+            {"type": "message", "role": "system", "content": "Be brief"},
+            {"type": "message", "role": "user", "content": "read a.rs"},
+            {"type": "message", "role": "assistant", "content": "Reading"},
+            {"type": "function_call", "call_id": "call_1", "name": "read_file", "arguments": r#"{"path":"a.rs"}"#},
+            {"type": "function_call_output", "call_id": "call_1", "output": "fn main() {}"}
+        ]));
+        assert_eq!(responses["tools"], json!([
+            {"type": "function", "name": "read_file", "description": "Read a file", "parameters": {"type": "object"}}
+        ]));
+        assert_eq!(responses["tool_choice"], json!({"type": "function", "name": "read_file"}));
+        // Chat 专有的参数不发送；未指定 temperature 时不发送默认值
+        for key in ["messages", "stop", "stream_options", "max_tokens", "temperature"] {
+            assert!(responses.get(key).is_none(), "{} should not be sent", key);
+        }
+    }
+    
+    #[test]
+    fn converts_responses_response_to_anthropic() {
+        let response = json!({
+            "id": "resp_1",
+            "status": "completed",
+            "output": [
+                {"type": "reasoning", "id": "rs_1", "summary": [{"type": "summary_text", "text": "Need the file"}]},
+                {"type": "message", "id": "msg_1", "role": "assistant", "content": [{"type": "output_text", "text": "Reading", "annotations": []}]},
+                {"type": "function_call", "id": "fc_1", "call_id": "call_1", "name": "read_file", "arguments": r#"{"path":"a.rs"}"#}
+            ],
+            "usage": {"input_tokens": 20, "output_tokens": 8, "total_tokens": 28}
+        });
+        let anthropic = responses_response_value_to_anthropic(&response, "claude");
+        assert_eq!(anthropic["content"], json!([
+            {"type": "thinking", "thinking": "Need the file", "signature": ""},
+            {"type": "text", "text": "Reading"},
+            {"type": "tool_use", "id": "call_1", "name": "read_file", "input": {"path": "a.rs"}}
+        ]));
+        assert_eq!(anthropic["stop_reason"], "tool_use");
+        assert_eq!(anthropic["usage"], json!({"input_tokens": 20, "output_tokens": 8}));
+        
+        let truncated = json!({"status": "incomplete", "incomplete_details": {"reason": "max_output_tokens"}, "output": []});
+        let anthropic = responses_response_value_to_anthropic(&truncated, "claude");
+        assert_eq!(anthropic["stop_reason"], "max_tokens");
+        assert_eq!(anthropic["content"], json!([{"type": "text", "text": ""}]));
+    }
+    
+    #[test]
+    fn clamps_max_tokens_to_cap() {
+        let options = ConversionOptions { max_tokens_cap: Some(8192), ..Default::default() };
+        let request = json!({"model": "m", "max_tokens": 64000, "messages": []});
+        assert_eq!(anthropic_request_to_openai(&request, &options).unwrap()["max_tokens"], 8192);
+        
+        let request = json!({"model": "m", "max_tokens": 1000, "messages": []});
+        assert_eq!(anthropic_request_to_openai(&request, &options).unwrap()["max_tokens"], 1000);
+    }
+    
+    #[test]
+    fn uses_max_tokens_default_when_omitted() {
+        let request = json!({"model": "m", "messages": []});
+        assert_eq!(anthropic_request_to_openai(&request, &ConversionOptions::default()).unwrap()["max_tokens"], 4096);
+        
+        let options = ConversionOptions { max_tokens_default: Some(2048), ..Default::default() };
+        assert_eq!(anthropic_request_to_openai(&request, &options).unwrap()["max_tokens"], 2048);
+        
+        // 默认值同样受上限约束
+        let options = ConversionOptions { max_tokens_default: Some(16000), max_tokens_cap: Some(8192), ..Default::default() };
+        assert_eq!(anthropic_request_to_openai(&request, &options).unwrap()["max_tokens"], 8192);
+    }
+    
+    #[test]
+    fn stream_skips_empty_choices_chunks() {
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        // 只有 role 的首个分片 (choices 为空)、缺少 choices 的分片、只有 usage 的末尾分片
+        let mut events = converter.convert_line(r#"data: {"id":"c1","choices":[]}"#);
+        events.extend(converter.convert_line(r#"data: {"id":"c1","object":"chat.completion.chunk"}"#));
+        events.extend(converter.convert_line(&chat_chunk(json!({"content": "hi"}), Value::Null)));
+        events.extend(converter.convert_line(r#"data: {"id":"c1","choices":[],"usage":{"prompt_tokens":3,"completion_tokens":1}}"#));
+        events.extend(converter.convert_line("data: [DONE]"));
+        
+        // 上游没有发送 finish_reason：由 finish 补发结束序列
+        events.extend(converter.finish());
+        assert_eq!(event_types(&events), [
+            "message_start",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]);
+        assert!(converter.finish().is_empty());
+    }
+    
+    #[test]
+    fn stream_with_only_empty_choices_still_finishes() {
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        let mut events = converter.convert_line(r#"data: {"id":"c1","choices":[]}"#);
+        events.extend(converter.finish());
+        assert_eq!(event_types(&events), [
+            "message_start",
+            "content_block_start",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]);
+    }
+    
+    #[test]
+    fn expands_every_choice_into_content_blocks() {
+        // 故意打乱顺序：按 index 展开，而不是按数组顺序
+        let response = json!({
+            "choices": [
+                {"index": 1, "message": {"role": "assistant", "content": "second"}, "finish_reason": "stop"},
+                {"index": 0, "message": {"role": "assistant", "content": "first"}, "finish_reason": "stop"}
+            ],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 4}
+        });
+        let anthropic = openai_response_value_to_anthropic(&response, "claude", None);
+        assert_eq!(anthropic["content"], json!([
+            {"type": "text", "text": "first"},
+            {"type": "text", "text": "second"}
+        ]));
+        assert_eq!(anthropic["stop_reason"], "end_turn");
+        assert_eq!(anthropic["usage"]["output_tokens"], 4);
+    }
+    
+    #[test]
+    fn non_stream_response_without_choices_keeps_the_turn() {
+        for response in [
+            json!({"choices": [], "usage": {"prompt_tokens": 3, "completion_tokens": 0}}),
+            json!({"id": "chatcmpl-1", "object": "chat.completion"}),
+        ] {
+            let anthropic = openai_response_value_to_anthropic(&response, "claude", None);
+            assert_eq!(anthropic["content"], json!([{"type": "text", "text": ""}]));
+            assert_eq!(anthropic["stop_reason"], "end_turn");
+        }
+    }
+    
+    #[test]
+    fn replays_chat_response_as_chat_chunks() {
+        let response = json!({
+            "id": "chatcmpl-1",
+            "created": 1700000000,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "hello",
+                    "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "f", "arguments": "{}"}}]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7}
+        });
+        let events = chat_response_value_to_chat_sse(&response);
+        assert_eq!(events.last().map(String::as_str), Some("data: [DONE]"));
+        
+        let chunks: Vec<Value> = events[..events.len() - 1].iter().map(|e| event_data(e)).collect();
+        assert!(chunks.iter().all(|c| c["object"] == "chat.completion.chunk" && c["id"] == "chatcmpl-1"));
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "hello");
+        assert_eq!(chunks[2]["choices"][0]["delta"]["tool_calls"][0]["index"], 0);
+        assert_eq!(chunks[2]["choices"][0]["delta"]["tool_calls"][0]["function"]["name"], "f");
+        assert_eq!(chunks[3]["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(chunks[4]["choices"], json!([]));
+        assert_eq!(chunks[4]["usage"]["total_tokens"], 7);
+        
+        // 回放的分片交给流式转换器后与原始响应一致
+        let mut converter = OpenAISseConverter::new("msg_1", "claude");
+        let anthropic: Vec<String> = events.iter().flat_map(|e| converter.convert_line(e)).collect();
+        assert_eq!(event_data(&anthropic[2])["delta"]["text"], "hello");
+        assert_eq!(event_data(&anthropic[anthropic.len() - 2])["delta"]["stop_reason"], "tool_use");
+    }
+    
+    #[test]
+    fn replays_responses_response_as_responses_events() {
+        let response = json!({
+            "id": "resp_1",
+            "object": "response",
+            "status": "completed",
+            "model": "gpt-4o",
+            "output": [
+                {"type": "reasoning", "id": "rs_1", "summary": [{"type": "summary_text", "text": "think"}]},
+                {"type": "message", "id": "msg_1", "status": "completed", "role": "assistant",
+                 "content": [{"type": "output_text", "text": "hi", "annotations": []}]},
+                {"type": "function_call", "id": "fc_1", "call_id": "call_1", "name": "f", "arguments": "{\"a\":1}", "status": "completed"}
+            ],
+            "usage": {"input_tokens": 4, "output_tokens": 3, "total_tokens": 7}
+        });
+        let events = responses_response_value_to_sse(&response);
+        assert_eq!(event_types(&events), [
+            "response.created",
+            "response.in_progress",
+            "response.output_item.added",
+            "response.reasoning_summary_part.added",
+            "response.reasoning_summary_text.delta",
+            "response.reasoning_summary_text.done",
+            "response.reasoning_summary_part.done",
+            "response.output_item.done",
+            "response.output_item.added",
+            "response.content_part.added",
+            "response.output_text.delta",
+            "response.output_text.done",
+            "response.content_part.done",
+            "response.output_item.done",
+            "response.output_item.added",
+            "response.function_call_arguments.delta",
+            "response.function_call_arguments.done",
+            "response.output_item.done",
+            "response.completed",
+        ]);
+        assert!(events[0].starts_with("event: response.created\n"));
+        assert_eq!(event_data(&events[0])["response"]["status"], "in_progress");
+        assert_eq!(event_data(&events[10])["delta"], "hi");
+        assert_eq!(event_data(&events[15])["delta"], "{\"a\":1}");
+        let sequence: Vec<u64> = events.iter().map(|e| event_data(e)["sequence_number"].as_u64().unwrap()).collect();
+        assert_eq!(sequence, (0..events.len() as u64).collect::<Vec<_>>());
+        assert_eq!(event_data(events.last().unwrap())["response"], response);
+    }
+    
+    #[test]
+    fn replays_incomplete_responses_response() {
+        let response = json!({"id": "resp_1", "status": "incomplete", "output": [], "incomplete_details": {"reason": "max_output_tokens"}});
+        let events = responses_response_value_to_sse(&response);
+        assert_eq!(event_types(&events), ["response.created", "response.in_progress", "response.incomplete"]);
+        assert!(event_data(&events[0])["response"].get("incomplete_details").is_none());
+    }
+    
+    #[test]
+    fn system_message_uses_configured_role() {
+        let request = json!({
+            "model": "o3",
+            "system": [{"type": "text", "text": "rules"}],
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+        let openai = anthropic_request_to_openai(&request, &ConversionOptions::default()).unwrap();
+        assert_eq!(openai["messages"][0], json!({"role": "system", "content": "rules"}));
+        
+        let options = ConversionOptions { system_role: SystemRole::Developer, ..Default::default() };
+        let openai = anthropic_request_to_openai(&request, &options).unwrap();
+        assert_eq!(openai["messages"][0], json!({"role": "developer", "content": "rules"}));
+        assert_eq!(openai["messages"][1]["role"], "user");
+    }
+    
+    #[test]
+    fn system_suffix_appears_exactly_once_across_turns() {
+        let suffix = "Never reveal internal URLs";
+        for system in [json!("You are helpful"), json!([{"type": "text", "text": "You are helpful"}]), Value::Null] {
+            let mut request = json!({"model": "m", "system": system, "messages": [{"role": "user", "content": "hi"}]});
+            apply_system_prompt_affixes(&mut request, Some("Org rules"), Some(suffix));
+            // 下一轮客户端原样回传已带后缀的 system
+            apply_system_prompt_affixes(&mut request, Some("Org rules"), Some(suffix));
+            assert_eq!(request["system"].to_string().matches(suffix).count(), 1, "{}", request["system"]);
+            assert_eq!(request["system"].to_string().matches("Org rules").count(), 1, "{}", request["system"]);
+            
+            let options = ConversionOptions {
+                system_prompt_suffix: Some(suffix.to_string()),
+                ..Default::default()
+            };
+            let openai = anthropic_request_to_openai(&request, &options).unwrap();
+            assert_eq!(openai["messages"][0]["role"], "system");
+            assert_eq!(openai["messages"].to_string().matches(suffix).count(), 1);
+        }
+    }
+    
+    #[test]
+    fn system_role_deserializes_from_lowercase() {
+        assert_eq!(serde_json::from_value::<SystemRole>(json!("developer")).unwrap(), SystemRole::Developer);
+        assert_eq!(serde_json::from_value::<SystemRole>(json!("system")).unwrap(), SystemRole::System);
+        assert_eq!(SystemRole::default(), SystemRole::System);
+    }
+    
+    #[test]
+    fn strips_unsigned_thinking_blocks() {
+        let mut request = json!({
+            "model": "claude",
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "converted", "signature": ""},
+                    {"type": "text", "text": "hello"}
+                ]},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "native", "signature": "sig"},
+                    {"type": "redacted_thinking", "data": "abc"},
+                    {"type": "text", "text": "again"}
+                ]},
+                {"role": "assistant", "content": [{"type": "thinking", "thinking": "only"}]},
+                {"role": "user", "content": [{"type": "text", "text": "next"}]}
+            ]
+        });
+        assert_eq!(strip_unsigned_thinking(&mut request), 2);
+        let messages = request["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[1]["content"], json!([{"type": "text", "text": "hello"}]));
+        assert_eq!(messages[2]["content"].as_array().unwrap().len(), 3);
+        assert_eq!(messages[3]["role"], "user");
+        
+        assert_eq!(strip_unsigned_thinking(&mut request), 0);
+    }
+}
@@ -1,17 +1,27 @@
 use crate::{
-    launcher::Launcher,
+    launcher::{LaunchPreview, LaunchedProcess, LaunchedProcessInfo, Launcher},
     models::*,
     scanner::Scanner,
     storage::Storage,
     updater,
 };
 use chrono::Utc;
-use tauri::State;
+use tauri::{AppHandle, Emitter, State};
 use std::sync::Mutex;
 use std::process::Command;
 
 pub struct AppState {
     pub storage: Mutex<Storage>,
+    /// Processes spawned by launch commands (dead entries are pruned lazily)
+    pub processes: Mutex<Vec<LaunchedProcess>>,
+}
+
+/// Remember spawned processes so they can be listed and killed later
+fn track_launched(state: &AppState, launched: Vec<LaunchedProcess>) -> Result<(), String> {
+    let mut processes = state.processes.lock().map_err(|e| e.to_string())?;
+    processes.retain_mut(|p| p.is_alive());
+    processes.extend(launched);
+    Ok(())
 }
 
 #[tauri::command]
@@ -29,17 +39,30 @@ pub async fn save_config(
     storage.save_config(&config).map_err(|e| e.to_string())
 }
 
+/// Scan a workspace directory. `max_depth` overrides the workspace's saved scan depth when given.
 #[tauri::command]
 pub async fn scan_workspace(
     path: String,
-    max_depth: usize,
+    max_depth: Option<usize>,
     state: State<'_, AppState>,
-) -> Result<Vec<Project>, String> {
-    let scanned_projects = Scanner::scan_directory(&path, max_depth).map_err(|e| e.to_string())?;
-    
+) -> Result<ScanResult, String> {
     let storage = state.storage.lock().map_err(|e| e.to_string())?;
     let mut config = storage.load_config().map_err(|e| e.to_string())?;
     
+    // Use the matching workspace's scan settings as defaults
+    let normalized = path.replace("\\", "/").to_lowercase();
+    let workspace = config.workspaces.iter()
+        .find(|w| w.path.replace("\\", "/").to_lowercase() == normalized);
+    let max_depth = max_depth
+        .or_else(|| workspace.and_then(|w| w.scan_max_depth))
+        .unwrap_or(1);
+    let ignore = workspace.map(|w| w.scan_ignore.clone()).unwrap_or_default();
+    
+    let ScanResult { projects: scanned_projects, warnings } = Scanner::scan_directory(&path, max_depth, &ignore).map_err(|e| e.to_string())?;
+    for warning in &warnings {
+        println!("Scan warning: {}", warning);
+    }
+    
     // Normalize workspace path for comparison
     let ws_path = std::path::Path::new(&path);
     let ws_path_str = ws_path.to_string_lossy().to_string();
@@ -127,7 +150,51 @@ pub async fn scan_workspace(
         .filter(|p| scanned_map.contains_key(&p.path))
         .collect();
         
-    Ok(result)
+    Ok(ScanResult { projects: result, warnings })
+}
+
+/// Add projects from a list of directories that don't belong to a workspace.
+/// Paths already known (by canonical path) are skipped.
+#[tauri::command]
+pub async fn import_projects_from_paths(
+    paths: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<ImportSummary, String> {
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let mut config = storage.load_config().map_err(|e| e.to_string())?;
+    
+    let normalize = |p: &str| -> String {
+        let cleaned = p.strip_prefix(r"\\?\").unwrap_or(p);
+        cleaned.replace("\\", "/").to_lowercase()
+    };
+    let mut known: std::collections::HashSet<String> = config.projects.iter()
+        .map(|p| normalize(&p.path))
+        .collect();
+    
+    let mut summary = ImportSummary::default();
+    for path in paths {
+        let path = path.trim();
+        if path.is_empty() {
+            continue;
+        }
+        match Scanner::detect_path(path) {
+            Ok(project) => {
+                if known.insert(normalize(&project.path)) {
+                    summary.added.push(project);
+                } else {
+                    summary.skipped.push(path.to_string());
+                }
+            }
+            Err(e) => summary.errors.push(format!("{}: {}", path, e)),
+        }
+    }
+    
+    if !summary.added.is_empty() {
+        config.projects.extend(summary.added.iter().cloned());
+        storage.save_config(&config).map_err(|e| e.to_string())?;
+    }
+    
+    Ok(summary)
 }
 
 #[tauri::command]
@@ -143,6 +210,9 @@ pub async fn add_workspace(
         path,
         auto_scan,
         created_at: Utc::now(),
+        default_launch_config: None,
+        scan_max_depth: None,
+        scan_ignore: Vec::new(),
     };
 
     let storage = state.storage.lock().map_err(|e| e.to_string())?;
@@ -153,6 +223,22 @@ pub async fn add_workspace(
     Ok(workspace)
 }
 
+/// Update a workspace's settings (name, auto scan, scan depth/ignore list, default launch config)
+#[tauri::command]
+pub async fn update_workspace(
+    workspace: Workspace,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let mut config = storage.load_config().map_err(|e| e.to_string())?;
+    
+    let existing = config.workspaces.iter_mut().find(|w| w.id == workspace.id)
+        .ok_or("Workspace not found")?;
+    *existing = workspace;
+    
+    storage.save_config(&config).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub async fn remove_workspace(
     workspace_id: String,
@@ -222,6 +308,69 @@ pub async fn delete_project(
     storage.save_config(&config).map_err(|e| e.to_string())
 }
 
+/// Merge a duplicate project entry into another one and delete the duplicate.
+/// The kept project keeps its own path; tags are unioned, starred is OR-ed and
+/// the most recent last_opened wins.
+#[tauri::command]
+pub async fn merge_projects(
+    keep_id: String,
+    merge_id: String,
+    state: State<'_, AppState>,
+) -> Result<Project, String> {
+    if keep_id == merge_id {
+        return Err("Cannot merge a project into itself".to_string());
+    }
+
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let mut config = storage.load_config().map_err(|e| e.to_string())?;
+
+    let merge_idx = config.projects.iter().position(|p| p.id == merge_id)
+        .ok_or("Project to merge not found")?;
+    if !config.projects.iter().any(|p| p.id == keep_id) {
+        return Err("Project to keep not found".to_string());
+    }
+    let merged = config.projects.remove(merge_idx);
+
+    let project = config.projects.iter_mut().find(|p| p.id == keep_id)
+        .ok_or("Project to keep not found")?;
+    for tag in merged.tags {
+        if !project.tags.contains(&tag) {
+            project.tags.push(tag);
+        }
+    }
+    project.starred |= merged.starred;
+    project.last_opened = project.last_opened.max(merged.last_opened);
+    let project = project.clone();
+
+    // Point recent entries at the kept project, dropping the duplicate that creates
+    let mut seen = std::collections::HashSet::new();
+    config.recent_projects = config.recent_projects
+        .into_iter()
+        .map(|id| if id == merge_id { keep_id.clone() } else { id })
+        .filter(|id| seen.insert(id.clone()))
+        .collect();
+
+    storage.save_config(&config).map_err(|e| e.to_string())?;
+    Ok(project)
+}
+
+/// Reject a tag whose parent doesn't exist or would create a cycle
+fn validate_tag_parent(tags: &[Tag], tag: &Tag) -> Result<(), String> {
+    let Some(parent_id) = &tag.parent_id else {
+        return Ok(());
+    };
+    if parent_id == &tag.id {
+        return Err("A tag cannot be its own parent".to_string());
+    }
+    if !tags.iter().any(|t| &t.id == parent_id) {
+        return Err(format!("Parent tag '{}' not found", parent_id));
+    }
+    if Tag::descendant_ids(tags, &tag.id).contains(parent_id) {
+        return Err("A tag cannot be nested under one of its own children".to_string());
+    }
+    Ok(())
+}
+
 #[tauri::command]
 pub async fn add_tag(
     tag: Tag,
@@ -229,6 +378,7 @@ pub async fn add_tag(
 ) -> Result<(), String> {
     let storage = state.storage.lock().map_err(|e| e.to_string())?;
     let mut config = storage.load_config().map_err(|e| e.to_string())?;
+    validate_tag_parent(&config.tags, &tag)?;
     config.tags.push(tag);
     storage.save_config(&config).map_err(|e| e.to_string())
 }
@@ -236,32 +386,126 @@ pub async fn add_tag(
 #[tauri::command]
 pub async fn update_tag(
     tag: Tag,
+    app: AppHandle,
     state: State<'_, AppState>,
-) -> Result<(), String> {
+) -> Result<Vec<Tag>, String> {
     let storage = state.storage.lock().map_err(|e| e.to_string())?;
     let mut config = storage.load_config().map_err(|e| e.to_string())?;
+    validate_tag_parent(&config.tags, &tag)?;
     
     if let Some(idx) = config.tags.iter().position(|t| t.id == tag.id) {
         config.tags[idx] = tag;
     }
     
-    storage.save_config(&config).map_err(|e| e.to_string())
+    storage.save_config(&config).map_err(|e| e.to_string())?;
+    emit_tags_changed(&app, &config.tags);
+    Ok(config.tags)
+}
+
+/// Rename a tag, keeping its color, icon and parent
+#[tauri::command]
+pub async fn rename_tag(
+    tag_id: String,
+    name: String,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<Vec<Tag>, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Tag name must not be empty".to_string());
+    }
+    
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let mut config = storage.load_config().map_err(|e| e.to_string())?;
+    let tag = config.tags.iter_mut()
+        .find(|t| t.id == tag_id)
+        .ok_or_else(|| format!("Tag not found: {}", tag_id))?;
+    tag.name = name.to_string();
+    
+    storage.save_config(&config).map_err(|e| e.to_string())?;
+    emit_tags_changed(&app, &config.tags);
+    Ok(config.tags)
+}
+
+/// Notify all windows that the tag list changed
+fn emit_tags_changed(app: &AppHandle, tags: &[Tag]) {
+    let _ = app.emit("tags-changed", tags);
 }
 
+/// Delete a tag. Child tags are moved up to the deleted tag's parent,
+/// or deleted along with it when `cascade` is true.
 #[tauri::command]
 pub async fn delete_tag(
     tag_id: String,
+    cascade: Option<bool>,
+    app: AppHandle,
     state: State<'_, AppState>,
-) -> Result<(), String> {
+) -> Result<Vec<Tag>, String> {
     let storage = state.storage.lock().map_err(|e| e.to_string())?;
     let mut config = storage.load_config().map_err(|e| e.to_string())?;
-    config.tags.retain(|t| t.id != tag_id);
+    
+    let mut removed_ids = vec![tag_id.clone()];
+    if cascade.unwrap_or(false) {
+        removed_ids.extend(Tag::descendant_ids(&config.tags, &tag_id));
+    } else {
+        let parent_id = config.tags.iter()
+            .find(|t| t.id == tag_id)
+            .and_then(|t| t.parent_id.clone());
+        for tag in config.tags.iter_mut().filter(|t| t.parent_id.as_ref() == Some(&tag_id)) {
+            tag.parent_id = parent_id.clone();
+        }
+    }
+    config.tags.retain(|t| !removed_ids.contains(&t.id));
     
     // Remove tag from all projects
     for project in &mut config.projects {
-        project.tags.retain(|t| t != &tag_id);
+        project.tags.retain(|t| !removed_ids.contains(t));
     }
-    storage.save_config(&config).map_err(|e| e.to_string())
+    storage.save_config(&config).map_err(|e| e.to_string())?;
+    
+    emit_tags_changed(&app, &config.tags);
+    let _ = app.emit("projects-changed", &config.projects);
+    Ok(config.tags)
+}
+
+/// Tags as a nested parent/child structure
+#[tauri::command]
+pub async fn get_tag_tree(state: State<'_, AppState>) -> Result<Vec<TagNode>, String> {
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let config = storage.load_config().map_err(|e| e.to_string())?;
+    Ok(Tag::build_tree(&config.tags))
+}
+
+/// Count the projects using each tag (keyed by tag id, unused tags report 0),
+/// so the UI can warn before a tag is deleted
+#[tauri::command]
+pub async fn get_tag_usage(
+    include_projects: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<std::collections::HashMap<String, TagUsage>, String> {
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let config = storage.load_config().map_err(|e| e.to_string())?;
+    let include_projects = include_projects.unwrap_or(false);
+
+    let mut usage: std::collections::HashMap<String, TagUsage> = config.tags.iter()
+        .map(|t| (t.id.clone(), TagUsage {
+            count: 0,
+            project_ids: include_projects.then(Vec::new),
+        }))
+        .collect();
+    for project in &config.projects {
+        for tag_id in &project.tags {
+            let entry = usage.entry(tag_id.clone()).or_insert_with(|| TagUsage {
+                count: 0,
+                project_ids: include_projects.then(Vec::new),
+            });
+            entry.count += 1;
+            if let Some(ids) = &mut entry.project_ids {
+                ids.push(project.id.clone());
+            }
+        }
+    }
+    Ok(usage)
 }
 
 #[tauri::command]
@@ -289,6 +533,31 @@ pub async fn record_project_open(
     storage.save_config(&config).map_err(|e| e.to_string())
 }
 
+/// Empty the recent projects list without touching the projects themselves
+#[tauri::command]
+pub async fn clear_recent_projects(
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let mut config = storage.load_config().map_err(|e| e.to_string())?;
+    config.recent_projects.clear();
+    storage.save_config(&config).map_err(|e| e.to_string())
+}
+
+/// Clear recent projects and every project's last opened time (stars and tags are kept)
+#[tauri::command]
+pub async fn clear_project_history(
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let mut config = storage.load_config().map_err(|e| e.to_string())?;
+    config.recent_projects.clear();
+    for project in &mut config.projects {
+        project.last_opened = None;
+    }
+    storage.save_config(&config).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub async fn toggle_project_star(
     project_id: String,
@@ -344,7 +613,50 @@ pub async fn launch_tool(
         }
     }
     
-    Launcher::launch(project, &tag_configs).map_err(|e| e.to_string())
+    let launched = Launcher::launch(project, &tag_configs).map_err(|e| e.to_string())?;
+    track_launched(&state, launched)
+}
+
+/// Open a project with its preferred launch config, resolved in order:
+/// project default, then the default of the workspace containing it, then the global default.
+/// Falls back to launching all of the project's tag configs when no default is set.
+#[tauri::command]
+pub async fn open_project_default(
+    project_id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let config = storage.load_config().map_err(|e| e.to_string())?;
+    
+    let project = config.projects.iter().find(|p| p.id == project_id)
+        .ok_or("Project not found")?;
+    
+    let project_path = project.path.replace("\\", "/").to_lowercase();
+    let workspace_default = config.workspaces.iter()
+        .filter(|w| project_path.starts_with(&w.path.replace("\\", "/").to_lowercase()))
+        .find_map(|w| w.default_launch_config.clone());
+    
+    let default_tag_id = project.default_launch_config.clone()
+        .or(workspace_default)
+        .or_else(|| config.default_launch_config.clone());
+    
+    let launched = match default_tag_id {
+        Some(tag_id) => {
+            let tag = config.tags.iter().find(|t| t.id == tag_id)
+                .ok_or_else(|| format!("Default launch config '{}' not found", tag_id))?;
+            let tag_config = tag.config.clone()
+                .ok_or_else(|| format!("Tag '{}' has no launch configuration", tag.name))?;
+            Launcher::launch(project, &[(tag_config, tag.category.clone())]).map_err(|e| e.to_string())?
+        }
+        None => {
+            let tag_configs: Vec<_> = project.tags.iter()
+                .filter_map(|tag_id| config.tags.iter().find(|t| &t.id == tag_id))
+                .filter_map(|tag| tag.config.clone().map(|conf| (conf, tag.category.clone())))
+                .collect();
+            Launcher::launch(project, &tag_configs).map_err(|e| e.to_string())?
+        }
+    };
+    track_launched(&state, launched)
 }
 
 #[tauri::command]
@@ -362,7 +674,68 @@ pub async fn launch_custom(
         
     // For custom launch, we assume it's a CLI tool or script that might benefit from a window
     // or we can treat it as Custom category
-    Launcher::launch(project, &[(config, TagCategory::Custom)]).map_err(|e| e.to_string())
+    let launched = Launcher::launch(project, &[(config, TagCategory::Custom)]).map_err(|e| e.to_string())?;
+    track_launched(&state, launched)
+}
+
+/// Processes launched by the app. Entries reported as no longer alive are dropped afterwards.
+#[tauri::command]
+pub async fn list_launched_processes(state: State<'_, AppState>) -> Result<Vec<LaunchedProcessInfo>, String> {
+    let mut processes = state.processes.lock().map_err(|e| e.to_string())?;
+    let infos: Vec<LaunchedProcessInfo> = processes.iter_mut().map(|p| p.info()).collect();
+    processes.retain_mut(|p| p.is_alive());
+    Ok(infos)
+}
+
+/// Terminate a process launched by the app (best effort: detached tools may have re-parented themselves).
+/// The entry is kept if the kill fails so it can be retried.
+#[tauri::command]
+pub async fn kill_launched_process(pid: u32, state: State<'_, AppState>) -> Result<(), String> {
+    let mut processes = state.processes.lock().map_err(|e| e.to_string())?;
+    let idx = processes.iter().position(|p| p.child.id() == pid)
+        .ok_or_else(|| format!("No launched process with pid {}", pid))?;
+    let process = &mut processes[idx];
+    if process.is_alive() {
+        process.child.kill().map_err(|e| e.to_string())?;
+        let _ = process.child.try_wait();
+    }
+    processes.remove(idx);
+    Ok(())
+}
+
+/// Show the command line, environment and working directory a launch would use, without spawning.
+/// With `config_id` (a tag id) only that tag's config is previewed, otherwise all of the project's tag configs.
+#[tauri::command]
+pub async fn preview_launch(
+    project_id: String,
+    config_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<LaunchPreview>, String> {
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    let config = storage.load_config().map_err(|e| e.to_string())?;
+    
+    let project = config.projects.iter().find(|p| p.id == project_id)
+        .ok_or("Project not found")?;
+    
+    let tag_ids: Vec<&String> = match &config_id {
+        Some(id) => vec![id],
+        None => project.tags.iter().collect(),
+    };
+    let mut tag_configs = Vec::new();
+    for tag_id in tag_ids {
+        let tag = config.tags.iter().find(|t| &t.id == tag_id);
+        match (tag, &config_id) {
+            (Some(tag), _) => {
+                if let Some(conf) = &tag.config {
+                    tag_configs.push((conf.clone(), tag.category.clone()));
+                }
+            }
+            (None, Some(_)) => return Err(format!("Launch config '{}' not found", tag_id)),
+            (None, None) => {}
+        }
+    }
+    
+    Ok(Launcher::preview(project, &tag_configs))
 }
 
 #[tauri::command]
@@ -464,16 +837,21 @@ pub async fn refresh_all_workspaces(
     storage.save_config(&config).map_err(|e| e.to_string())?;
     
     // Step 2: Rescan each workspace and update projects
-    let workspace_paths_original: Vec<String> = config.workspaces.iter()
-        .map(|w| w.path.clone())
+    let workspace_paths_original: Vec<(String, usize, Vec<String>)> = config.workspaces.iter()
+        .map(|w| (w.path.clone(), w.scan_max_depth.unwrap_or(1), w.scan_ignore.clone()))
         .collect();
     
     drop(storage);
     
-    for ws_path in workspace_paths_original {
+    for (ws_path, max_depth, ignore) in workspace_paths_original {
         let storage = state.storage.lock().map_err(|e| e.to_string())?;
-        let scanned_projects = match Scanner::scan_directory(&ws_path, 1) {
-            Ok(projects) => projects,
+        let scanned_projects = match Scanner::scan_directory(&ws_path, max_depth, &ignore) {
+            Ok(result) => {
+                for warning in &result.warnings {
+                    println!("Scan warning: {}", warning);
+                }
+                result.projects
+            }
             Err(_) => continue, // Skip if workspace path doesn't exist
         };
         
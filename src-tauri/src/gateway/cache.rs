@@ -1,15 +1,23 @@
 use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheEntry {
     pub response_body: Vec<u8>,
     pub status: u16,
     pub headers: Vec<(String, String)>,
     pub created_at: u64,
     pub ttl_seconds: u64,
+    // 最近一次访问时间 (毫秒)，用于 LRU 淘汰
+    pub last_accessed: u64,
 }
 
 impl CacheEntry {
@@ -20,103 +28,263 @@ impl CacheEntry {
             .as_secs();
         now > self.created_at + self.ttl_seconds
     }
+    
+    /// 条目占用的字节数 (响应体 + 响应头)
+    fn size_bytes(&self) -> usize {
+        self.response_body.len()
+            + self.headers.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>()
+    }
+}
+
+/// 缓存统计
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub max_entries: usize,
+    pub total_bytes: usize,
+    pub max_total_bytes: usize,
+}
+
+#[derive(Default)]
+struct CacheStore {
+    entries: HashMap<String, CacheEntry>,
+    total_bytes: usize,
+}
+
+impl CacheStore {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.total_bytes = self.total_bytes.saturating_sub(entry.size_bytes());
+        }
+    }
+    
+    fn evict_expired(&mut self) {
+        self.entries.retain(|_, entry| !entry.is_expired());
+        self.total_bytes = self.entries.values().map(|e| e.size_bytes()).sum();
+    }
+    
+    /// 淘汰最久未访问的条目
+    fn evict_lru(&mut self) -> bool {
+        let oldest = self.entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_accessed)
+            .map(|(k, _)| k.clone());
+        match oldest {
+            Some(key) => {
+                self.remove(&key);
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 pub struct CacheManager {
-    cache: Arc<RwLock<HashMap<String, CacheEntry>>>,
+    cache: Arc<RwLock<CacheStore>>,
     max_entries: usize,
+    // 缓存总字节数上限 (0 = 不限制)
+    max_total_bytes: usize,
     default_ttl: u64,
+    // 持久化文件 (gzip 压缩的 JSON)，开启 cache_persist 时定期及退出前写入、启动时读取
+    file_path: PathBuf,
+    persist: Arc<AtomicBool>,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
 }
 
 impl CacheManager {
-    pub fn new(max_entries: usize, default_ttl: u64) -> Self {
+    /// 创建缓存；persist 为 true 时从 app_dir 下的缓存文件恢复未过期的条目
+    pub fn new(app_dir: PathBuf, max_entries: usize, max_total_bytes: usize, default_ttl: u64, persist: bool) -> Self {
+        let file_path = app_dir.join("gateway_cache.json.gz");
+        let mut store = CacheStore::default();
+        if persist {
+            if let Some(entries) = Self::load_file(&file_path) {
+                store.entries = entries;
+                // 丢弃已过期的条目，并按当前预算淘汰多余的条目
+                store.evict_expired();
+                while store.entries.len() > max_entries
+                    || (max_total_bytes > 0 && store.total_bytes > max_total_bytes)
+                {
+                    if !store.evict_lru() {
+                        break;
+                    }
+                }
+            }
+        }
+        
         Self {
-            cache: Arc::new(RwLock::new(HashMap::new())),
+            cache: Arc::new(RwLock::new(store)),
             max_entries,
+            max_total_bytes,
             default_ttl,
+            file_path,
+            persist: Arc::new(AtomicBool::new(persist)),
+        }
+    }
+    
+    /// 读取缓存文件 (gzip 压缩的 JSON)
+    fn load_file(path: &Path) -> Option<HashMap<String, CacheEntry>> {
+        let bytes = fs::read(path).ok()?;
+        let mut json = Vec::new();
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut json).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+    
+    /// 切换缓存持久化 (配置变更时调用)；关闭时删除已写入磁盘的缓存文件
+    pub fn set_persist(&self, persist: bool) {
+        self.persist.store(persist, Ordering::SeqCst);
+        if !persist && self.file_path.exists() {
+            if let Err(e) = fs::remove_file(&self.file_path) {
+                eprintln!("Failed to remove cache file: {}", e);
+            }
         }
     }
     
-    /// 生成缓存 Key (基于路径和请求体的 SHA256)
-    pub fn generate_key(path: &str, body: &[u8]) -> String {
+    /// 将未过期的缓存条目写入磁盘 (清理任务定期调用，退出时再调用一次；未开启持久化时不写入)
+    pub fn persist(&self) {
+        if !self.persist.load(Ordering::SeqCst) {
+            return;
+        }
+        let json = {
+            let Ok(cache) = self.cache.read() else {
+                return;
+            };
+            let live: HashMap<&String, &CacheEntry> = cache.entries
+                .iter()
+                .filter(|(_, entry)| !entry.is_expired())
+                .collect();
+            match serde_json::to_vec(&live) {
+                Ok(json) => json,
+                Err(e) => {
+                    eprintln!("Failed to serialize cache: {}", e);
+                    return;
+                }
+            }
+        };
+        
+        // 先写临时文件再重命名，避免退出过程中被打断留下损坏的文件
+        let tmp_path = self.file_path.with_extension("tmp");
+        let result = (|| -> std::io::Result<()> {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&json)?;
+            fs::write(&tmp_path, encoder.finish()?)?;
+            fs::rename(&tmp_path, &self.file_path)
+        })();
+        if let Err(e) = result {
+            eprintln!("Failed to persist cache: {}", e);
+        }
+    }
+    
+    /// 生成缓存 Key：SHA256(命名空间 + 路径 + 请求体)
+    ///
+    /// 命名空间由调用方按供应商生成 (供应商 id、实际模型、cache_namespace)，
+    /// 不同供应商/模型的响应互不命中；各部分之间以 0 字节分隔，避免拼接歧义
+    pub fn generate_key(namespace: &str, path: &str, body: &[u8]) -> String {
         let mut hasher = Sha256::new();
+        hasher.update(namespace.as_bytes());
+        hasher.update([0u8]);
         hasher.update(path.as_bytes());
+        hasher.update([0u8]);
         hasher.update(body);
         format!("{:x}", hasher.finalize())
     }
     
-    /// 获取缓存
+    /// 获取缓存 (命中时刷新访问时间，供 LRU 淘汰使用)
     pub fn get(&self, key: &str) -> Option<CacheEntry> {
-        let cache = self.cache.read().ok()?;
-        let entry = cache.get(key)?;
+        let mut cache = self.cache.write().ok()?;
+        let entry = cache.entries.get_mut(key)?;
         
         if entry.is_expired() {
-            // 过期了，返回 None（下次写入时会覆盖）
+            // 过期了：立即移除，释放条目数和字节预算
+            cache.remove(key);
             None
         } else {
+            entry.last_accessed = now_millis();
             Some(entry.clone())
         }
     }
     
-    /// 设置缓存
-    pub fn set(&self, key: String, response_body: Vec<u8>, status: u16, headers: Vec<(String, String)>) {
+    /// 设置缓存 (ttl_seconds 为 None 时使用默认 TTL)
+    pub fn set(&self, key: String, response_body: Vec<u8>, status: u16, headers: Vec<(String, String)>, ttl_seconds: Option<u64>) {
         let mut cache = match self.cache.write() {
             Ok(c) => c,
             Err(_) => return,
         };
         
-        // 如果超过最大条目数，清理过期条目
-        if cache.len() >= self.max_entries {
-            self.evict_expired_internal(&mut cache);
-            
-            // 如果还是满了，删除最旧的
-            if cache.len() >= self.max_entries {
-                // 简单策略：删除第一个找到的
-                if let Some(k) = cache.keys().next().cloned() {
-                    cache.remove(&k);
-                }
-            }
-        }
-        
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
         
-        cache.insert(key, CacheEntry {
+        let entry = CacheEntry {
             response_body,
             status,
             headers,
             created_at: now,
-            ttl_seconds: self.default_ttl,
-        });
+            ttl_seconds: ttl_seconds.unwrap_or(self.default_ttl),
+            last_accessed: now_millis(),
+        };
+        let entry_size = entry.size_bytes();
+        
+        // 单个条目超过总字节预算，不缓存
+        if self.max_total_bytes > 0 && entry_size > self.max_total_bytes {
+            return;
+        }
+        
+        // 覆盖同 key 的旧条目
+        cache.remove(&key);
+        
+        let over_budget = |cache: &CacheStore| {
+            cache.entries.len() >= self.max_entries
+                || (self.max_total_bytes > 0 && cache.total_bytes + entry_size > self.max_total_bytes)
+        };
+        
+        // 超出预算时先清理过期条目，再按 LRU 淘汰，直到条目数和字节数都满足预算
+        if over_budget(&cache) {
+            cache.evict_expired();
+        }
+        while over_budget(&cache) {
+            if !cache.evict_lru() {
+                break;
+            }
+        }
+        
+        cache.total_bytes += entry_size;
+        cache.entries.insert(key, entry);
     }
     
     /// 清理过期条目
     pub fn evict_expired(&self) {
         if let Ok(mut cache) = self.cache.write() {
-            self.evict_expired_internal(&mut cache);
+            cache.evict_expired();
         }
     }
     
-    fn evict_expired_internal(&self, cache: &mut HashMap<String, CacheEntry>) {
-        cache.retain(|_, entry| !entry.is_expired());
-    }
-    
     /// 清空所有缓存
     pub fn clear(&self) {
         if let Ok(mut cache) = self.cache.write() {
-            cache.clear();
+            cache.entries.clear();
+            cache.total_bytes = 0;
         }
     }
     
     /// 获取缓存统计
-    pub fn stats(&self) -> (usize, usize) {
-        let cache = match self.cache.read() {
-            Ok(c) => c,
-            Err(_) => return (0, self.max_entries),
+    pub fn stats(&self) -> CacheStats {
+        let (entries, total_bytes) = match self.cache.read() {
+            Ok(c) => (c.entries.len(), c.total_bytes),
+            Err(_) => (0, 0),
         };
-        (cache.len(), self.max_entries)
+        CacheStats {
+            entries,
+            max_entries: self.max_entries,
+            total_bytes,
+            max_total_bytes: self.max_total_bytes,
+        }
     }
 }
 
@@ -125,7 +293,10 @@ impl Clone for CacheManager {
         Self {
             cache: self.cache.clone(),
             max_entries: self.max_entries,
+            max_total_bytes: self.max_total_bytes,
             default_ttl: self.default_ttl,
+            file_path: self.file_path.clone(),
+            persist: self.persist.clone(),
         }
     }
 }
@@ -2,9 +2,12 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
+use std::sync::{LazyLock, RwLock};
 use anyhow::{Context, Result};
+use super::converter::{ConversionOptions, SystemRole};
+use super::tokenizer::TOKENIZER_NAMES;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
 pub enum ApiType {
     #[default]
     Anthropic,      // /v1/messages - Claude Code
@@ -12,6 +15,19 @@ pub enum ApiType {
     OpenAIChat,     // /v1/chat/completions - Cline, Continue, etc.
 }
 
+/// 供应商选择策略 (决定首次尝试及故障转移的顺序，冷却中的供应商始终跳过)
+/// 也接受 "priority" / "weighted_random" 写法
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub enum SelectionStrategy {
+    #[default]
+    Ordered,      // 按配置顺序
+    LatencyAware, // 按近期 p95 延迟升序，样本不足的供应商使用中位数
+    #[serde(alias = "weighted_random")]
+    Weighted,     // 按有效权重 (配置权重 * 失败衰减/成功恢复的动态系数) 加权随机排序
+    #[serde(alias = "priority")]
+    Priority,     // 按配置权重降序 (确定性)，权重相同时保持配置顺序
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Provider {
     pub id: String,
@@ -39,8 +55,233 @@ pub struct Provider {
     // Claude Code 代理模式：将 Anthropic 请求转换为 OpenAI 格式
     #[serde(default)]
     pub claude_code_proxy: bool,
+    
+    // Responses 代理模式：将 OpenAI Responses 请求 (/v1/responses) 转换为 Chat Completions 格式，
+    // 用于只支持 Chat Completions 的供应商
+    #[serde(default)]
+    pub responses_proxy: bool,
+    
+    // Claude Code 代理模式下上游使用 Responses API：Anthropic 请求转换为 /v1/responses，
+    // 响应 (含流式) 转换回 Anthropic 格式，用于只提供 Responses 接口的供应商 (如 CodeX 后端)
+    #[serde(default)]
+    pub responses_upstream: bool,
+    
+    // max_tokens 上限 (转换时超过则截断) 与默认值 (客户端未指定时使用)
+    #[serde(default)]
+    pub max_tokens_cap: Option<u64>,
+    #[serde(default)]
+    pub max_tokens_default: Option<u64>,
+    
+    // 协议转换时 system 提示使用的角色 ("system" 或 "developer")
+    #[serde(default)]
+    pub system_role: SystemRole,
+    
+    // 上游推理参数名 (如 "reasoning_effort")，配置后透传 Anthropic 的 thinking 配置
+    #[serde(default)]
+    pub reasoning_param_name: Option<String>,
+    
+    // 强制按流式处理该供应商的响应 (用于自定义 content-type 的流式上游)
+    #[serde(default)]
+    pub force_streaming: bool,
+    
+    // 禁用流式：向上游发送 stream: false，缓冲完整响应后按客户端协议回放为 SSE
+    #[serde(default)]
+    pub disable_streaming: bool,
+    
+    // 跨协议兜底：同类型供应商全部失败后，经协议转换为这些 API 类型提供服务
+    #[serde(default)]
+    pub fallback_for: Vec<ApiType>,
+    
+    // 供应商标签 (如 "free-tier"、"production")，用于按标签路由和统计
+    #[serde(default)]
+    pub tags: Vec<String>,
+    
+    // 自定义认证头 (如 api-key、x-goog-api-key) 及值模板 ({key} 替换为 API Key)
+    // 未设置时按协议使用默认的 Authorization: Bearer {key} 或 x-api-key: {key}
+    #[serde(default)]
+    pub auth_header_name: Option<String>,
+    #[serde(default)]
+    pub auth_header_format: Option<String>,
+    
+    // 最大并发请求数 (None = 不限制)，达到上限时尝试下一个供应商或排队等待
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    
+    // 协议转换时合并相邻的同角色消息 (上游拒绝连续的 user/assistant 消息时开启)
+    #[serde(default)]
+    pub merge_consecutive_roles: bool,
+    
+    // 协议转换时 stop 序列的最大数量 (默认 4，超出截断)，以及只有一个序列时是否以字符串发送
+    #[serde(default)]
+    pub max_stop_sequences: Option<usize>,
+    #[serde(default)]
+    pub single_stop_as_string: bool,
+    
+    // 协议转换时不请求流式 usage (stream_options.include_usage)：上游见到该参数返回 400 时开启，输出 token 改为估算
+    #[serde(default)]
+    pub disable_stream_usage: bool,
+    
+    // 非标准响应的文本位置 (JSON Pointer，相对于 choices[0].message / choices[0].delta，如 "/output_text")，
+    // 标准的 content 字段为空时从该位置读取文本
+    #[serde(default)]
+    pub response_content_path: Option<String>,
+    
+    // 上游对返回错误的请求也按输入 token 计费：开启后故障转移中失败的尝试同样计入费用
+    #[serde(default)]
+    pub bill_failed_requests: bool,
+    
+    // 精确计数输入 token 的 tokenizer：编码名称 (如 "cl100k_base") 或 "auto" (按模型名推断)；
+    // 为空、未开启 tiktoken feature 或模型没有已知编码时按 chars_per_token 估算
+    #[serde(default)]
+    pub tokenizer: Option<String>,
+    
+    // 估算 token 数时每个 token 对应的字符数 (默认 4，适用于英文；中日韩文本约 1~1.5)
+    #[serde(default)]
+    pub chars_per_token: Option<f64>,
+    
+    // 上游代理 (如 http://proxy.corp:8080)，为空时直连
+    #[serde(default)]
+    pub proxy_url: Option<String>,
+    
+    // 缓存命名空间：参与缓存 Key 计算，修改后该供应商的旧缓存全部失效
+    #[serde(default)]
+    pub cache_namespace: Option<String>,
+    
+    // 来自环境变量的供应商 (不会写回配置文件)
+    #[serde(default)]
+    pub from_env: bool,
 }
 
+/// 缓存 TTL 规则：按路径前缀和/或 API 类型匹配，首个匹配的规则生效
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheTtlRule {
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+    #[serde(default)]
+    pub api_type: Option<ApiType>,
+    pub ttl_seconds: u64,
+}
+
+impl CacheTtlRule {
+    fn matches(&self, path: &str, api_type: &ApiType) -> bool {
+        self.path_prefix.as_deref().is_none_or(|prefix| path.starts_with(prefix))
+            && self.api_type.as_ref().is_none_or(|t| t == api_type)
+    }
+}
+
+/// 按输入 token 数路由的规则：估算的输入 token 数落在 [min_input_tokens, max_input_tokens) 内
+/// (且 API 类型匹配) 时生效，首个匹配的规则生效
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenRoutingRule {
+    #[serde(default)]
+    pub min_input_tokens: Option<u32>,
+    #[serde(default)]
+    pub max_input_tokens: Option<u32>,
+    #[serde(default)]
+    pub api_type: Option<ApiType>,
+    // 优先使用的供应商 id (按列表顺序排在其他供应商之前)
+    #[serde(default)]
+    pub provider_ids: Vec<String>,
+    // 只使用 provider_ids 中的供应商，不再故障转移到其他供应商
+    #[serde(default)]
+    pub exclusive: bool,
+    // 改写请求中的模型名 (如换用长上下文模型)
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+impl TokenRoutingRule {
+    fn matches(&self, input_tokens: u32, api_type: &ApiType) -> bool {
+        self.min_input_tokens.is_none_or(|min| input_tokens >= min)
+            && self.max_input_tokens.is_none_or(|max| input_tokens < max)
+            && self.api_type.as_ref().is_none_or(|t| t == api_type)
+    }
+}
+
+impl Provider {
+    /// 上游认证头名称和值模板 ({key} 替换为 API Key)
+    /// 未自定义时：Anthropic 为 x-api-key: {key}，OpenAI (含代理转换模式) 为 Authorization: Bearer {key}；
+    /// 只自定义了名称时模板默认为 {key}
+    pub fn auth_header(&self, api_type: &ApiType, use_proxy_conversion: bool) -> (&str, &str) {
+        let custom_name = self.auth_header_name.as_deref().map(str::trim).filter(|n| !n.is_empty());
+        let (default_name, default_format) = match (custom_name, api_type) {
+            (Some(name), _) => (name, "{key}"),
+            (None, ApiType::Anthropic) if !use_proxy_conversion => ("x-api-key", "{key}"),
+            (None, _) => ("Authorization", "Bearer {key}"),
+        };
+        let format = self.auth_header_format.as_deref().filter(|f| !f.is_empty()).unwrap_or(default_format);
+        (default_name, format)
+    }
+    
+    /// 对指定 API 类型的请求是否启用代理转换 (Claude Code 代理模式 / Responses 代理模式)
+    pub fn proxy_conversion_for(&self, api_type: &ApiType) -> bool {
+        match api_type {
+            ApiType::Anthropic => self.claude_code_proxy,
+            ApiType::OpenAIResponses => self.responses_proxy,
+            ApiType::OpenAIChat => false,
+        }
+    }
+    
+    /// 实际使用的 API Key：配置为 `${ENV_VAR}` 占位符时读取同名变量
+    /// (进程环境变量优先，其次是配置文件旁的 .env，与环境变量供应商一致；都未设置时为空)
+    pub fn resolved_api_key(&self) -> String {
+        let dotenv = DOTENV_VARS.read().unwrap_or_else(|e| e.into_inner());
+        self.resolve_api_key(|var| std::env::var(var).ok(), &dotenv)
+    }
+    
+    /// 按占位符解析 API Key：先查进程环境变量 (由 env 读取)，再查 .env
+    fn resolve_api_key(&self, env: impl Fn(&str) -> Option<String>, dotenv: &HashMap<String, String>) -> String {
+        match api_key_placeholder(&self.api_key) {
+            Some(var) => env(var)
+                .filter(|v| !v.is_empty())
+                .or_else(|| dotenv.get(var).cloned())
+                .unwrap_or_default(),
+            None => self.api_key.clone(),
+        }
+    }
+    
+    /// 导出时替代 API Key 的环境变量占位符，如 `${VBDING_MY_PROVIDER_API_KEY}`
+    pub fn api_key_env_placeholder(&self) -> String {
+        let id: String = self.id.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        format!("${{VBDING_{}_API_KEY}}", id)
+    }
+    
+    /// 非标准响应文本位置 (空字符串视为未配置)
+    pub fn response_content_path(&self) -> Option<&str> {
+        self.response_content_path.as_deref().filter(|p| !p.is_empty())
+    }
+    
+    /// 估算 token 数使用的字符/token 比例 (未设置或无效时使用默认值)
+    pub fn effective_chars_per_token(&self) -> f64 {
+        self.chars_per_token
+            .filter(|c| c.is_finite() && *c > 0.0)
+            .unwrap_or(DEFAULT_CHARS_PER_TOKEN)
+    }
+    
+    /// 该供应商的协议转换选项
+    pub fn conversion_options(&self) -> ConversionOptions {
+        ConversionOptions {
+            model_mapping: self.model_mapping.clone(),
+            max_tokens_cap: self.max_tokens_cap,
+            max_tokens_default: self.max_tokens_default,
+            system_role: self.system_role,
+            reasoning_param_name: self.reasoning_param_name.clone(),
+            // 全局 system 前缀/后缀由调用方按网关配置填充
+            system_prompt_prefix: None,
+            system_prompt_suffix: None,
+            merge_consecutive_roles: self.merge_consecutive_roles,
+            max_stop_sequences: self.max_stop_sequences,
+            single_stop_as_string: self.single_stop_as_string,
+            disable_stream_usage: self.disable_stream_usage,
+        }
+    }
+}
+
+/// 按字符数估算 token 时的默认比例 (英文约 4 字符 / token)
+pub const DEFAULT_CHARS_PER_TOKEN: f64 = 4.0;
+
 fn default_api_types() -> Vec<ApiType> {
     vec![ApiType::Anthropic] // 默认为 Anthropic 以兼容旧配置
 }
@@ -67,6 +308,14 @@ pub struct GatewayConfig {
     #[serde(default = "default_true")]
     pub chat_enabled: bool,
     
+    // Unix 域套接字路径 (macOS/Linux)，配置后该网关改为监听套接字而非 TCP 端口
+    #[serde(default)]
+    pub anthropic_socket_path: Option<String>,
+    #[serde(default)]
+    pub responses_socket_path: Option<String>,
+    #[serde(default)]
+    pub chat_socket_path: Option<String>,
+    
     // 旧字段兼容 (已废弃，用于迁移)
     #[serde(default)]
     pub port: u16,
@@ -84,10 +333,119 @@ pub struct GatewayConfig {
     pub cache_ttl_seconds: u64,
     #[serde(default = "default_cache_max_entries")]
     pub cache_max_entries: usize,
+    // 缓存总字节数上限 (0 = 不限制)
+    #[serde(default = "default_cache_max_total_bytes")]
+    pub cache_max_total_bytes: usize,
+    // 后台清理过期缓存的间隔 (秒，0 = 不清理，只在写入时按需淘汰)
+    #[serde(default = "default_cache_eviction_interval")]
+    pub cache_eviction_interval_seconds: u64,
+    
+    // 主动健康检查间隔 (秒)：定期请求各供应商的 /v1/models，0 = 关闭
+    #[serde(default)]
+    pub health_check_interval_seconds: u64,
+    
+    // 退出时将未过期的缓存写入磁盘，下次启动时恢复 (缓存文件包含响应内容，默认关闭)
+    #[serde(default)]
+    pub cache_persist: bool,
+    // 按路径/API 类型覆盖默认 TTL
+    #[serde(default)]
+    pub cache_ttl_rules: Vec<CacheTtlRule>,
+    
+    // 按估算的输入 token 数选择供应商/模型 (如超长上下文的请求发往长上下文供应商)
+    #[serde(default)]
+    pub token_routing_rules: Vec<TokenRoutingRule>,
     
     // 熔断配置
     #[serde(default = "default_cooldown")]
     pub circuit_breaker_cooldown_seconds: u64,
+    // 连续失败时冷却时间按 2 的幂次增长，最长不超过该值 (秒)
+    #[serde(default = "default_max_cooldown")]
+    pub circuit_breaker_max_cooldown_seconds: u64,
+    
+    // 单次请求最多尝试的供应商数量 (0 = 不限制，冷却中跳过的不计入)
+    #[serde(default)]
+    pub max_failover_attempts: usize,
+    
+    // 供应商达到 max_concurrent 上限时排队等待许可，而不是直接尝试下一个供应商
+    #[serde(default)]
+    pub queue_at_concurrency_limit: bool,
+    
+    // 连接错误 (非 HTTP 错误) 时对同一供应商立即重试的次数
+    #[serde(default = "default_connection_retry_count")]
+    pub connection_retry_count: u32,
+    
+    // 供应商选择策略 (兼容 load_balance_strategy 字段名)
+    #[serde(default, alias = "load_balance_strategy")]
+    pub selection_strategy: SelectionStrategy,
+    
+    // 按持久化的可靠性评分排序供应商 (重启后首个请求不会先打到近期不稳定的供应商)
+    #[serde(default)]
+    pub reliability_ordering: bool,
+    
+    // 对冲请求：非流式请求同时发往前两个健康供应商，取最先成功的响应 (会增加费用)
+    #[serde(default)]
+    pub hedge: bool,
+    // 主请求超过该时间未返回才发出第二个请求 (毫秒)
+    #[serde(default = "default_hedge_delay_ms")]
+    pub hedge_delay_ms: u64,
+    
+    // 滚动成功率降级：最近 demotion_window 次请求的成功率低于阈值时冷却该供应商 (0 = 关闭，默认关闭)
+    #[serde(default)]
+    pub demotion_window: usize,
+    #[serde(default = "default_demotion_min_success_rate")]
+    pub demotion_min_success_rate: f64,
+    
+    // 统计保留：小时桶数量 / 天桶数量
+    #[serde(default = "default_hourly_retention")]
+    pub hourly_retention: usize,
+    #[serde(default = "default_daily_retention")]
+    pub daily_retention: usize,
+    // 统计文件使用 gzip 压缩存储 (gateway_stats.json.gz)
+    #[serde(default)]
+    pub stats_compression: bool,
+    
+    // 流式响应未返回 usage 时，按累计输出文本估算 output_tokens 和费用 (请求日志标记为 estimated)
+    #[serde(default = "default_true")]
+    pub estimate_streaming_output_tokens: bool,
+    
+    // 配置文件以紧凑 JSON 保存 (默认格式化输出，便于手动编辑；两种格式均可读取)
+    #[serde(default)]
+    pub compact_json: bool,
+    
+    // 严格校验 Anthropic /v1/messages 请求体，格式错误时直接返回 400 (指出具体字段) 而不是转发
+    #[serde(default)]
+    pub strict_validation: bool,
+    
+    // 调试捕获：在请求日志中保留上游请求的 URL、请求头和请求体 (用于导出 curl 复现)
+    #[serde(default)]
+    pub debug_capture: bool,
+    
+    // 请求日志只保存 token/费用等统计，不保存提示词和响应内容；
+    // 上游错误响应体可能回显提示词，开启后以长度 + SHA256 前缀代替原文写入 error_message
+    #[serde(default)]
+    pub redact_error_bodies: bool,
+    
+    // 关闭后 error_message 只记录 HTTP 状态，完全不保存上游错误响应体
+    #[serde(default = "default_true")]
+    pub store_error_bodies: bool,
+    
+    // 转发给客户端的上游响应头：allowlist 非空时只转发列表中的响应头，denylist 中的始终不转发
+    // (名称不区分大小写；逐跳头总是被移除)
+    #[serde(default)]
+    pub response_header_allowlist: Vec<String>,
+    #[serde(default = "default_response_header_denylist")]
+    pub response_header_denylist: Vec<String>,
+    
+    // 全局 system 提示前缀/后缀 (如组织统一的安全要求)：注入到每个 Anthropic 请求的 system 中，
+    // 原生转发和协议转换两条路径都生效，已包含时不重复添加
+    #[serde(default)]
+    pub system_prompt_prefix: Option<String>,
+    #[serde(default)]
+    pub system_prompt_suffix: Option<String>,
+    
+    // 基准测试按 API 类型使用的默认模型名 (命令中未指定模型时使用)
+    #[serde(default)]
+    pub benchmark_models: HashMap<ApiType, String>,
 }
 
 fn default_anthropic_port() -> u16 { 12345 }
@@ -96,7 +454,22 @@ fn default_chat_port() -> u16 { 12347 }
 fn default_true() -> bool { true }
 fn default_cache_ttl() -> u64 { 600 } // 10 分钟
 fn default_cache_max_entries() -> usize { 1000 }
+fn default_cache_max_total_bytes() -> usize { 100 * 1024 * 1024 } // 100 MB
+fn default_cache_eviction_interval() -> u64 { 60 }
 fn default_cooldown() -> u64 { 60 }
+fn default_max_cooldown() -> u64 { 1800 } // 30 分钟
+fn default_hedge_delay_ms() -> u64 { 500 }
+fn default_connection_retry_count() -> u32 { 1 }
+fn default_demotion_min_success_rate() -> f64 { 0.5 }
+fn default_response_header_denylist() -> Vec<String> {
+    // 可识别上游供应商/账号的响应头
+    ["openai-organization", "openai-project", "x-request-id", "set-cookie"]
+        .iter()
+        .map(|h| h.to_string())
+        .collect()
+}
+fn default_hourly_retention() -> usize { 24 }
+fn default_daily_retention() -> usize { 30 }
 
 impl Default for GatewayConfig {
     fn default() -> Self {
@@ -107,6 +480,9 @@ impl Default for GatewayConfig {
             anthropic_enabled: true,
             responses_enabled: true,
             chat_enabled: true,
+            anthropic_socket_path: None,
+            responses_socket_path: None,
+            chat_socket_path: None,
             port: 0, // 废弃字段
             enabled: true,
             providers: vec![],
@@ -114,13 +490,63 @@ impl Default for GatewayConfig {
             cache_enabled: true,
             cache_ttl_seconds: 600,
             cache_max_entries: 1000,
+            cache_max_total_bytes: default_cache_max_total_bytes(),
+            cache_eviction_interval_seconds: default_cache_eviction_interval(),
+            health_check_interval_seconds: 0,
+            cache_persist: false,
+            cache_ttl_rules: Vec::new(),
+            token_routing_rules: Vec::new(),
             circuit_breaker_cooldown_seconds: 60,
+            circuit_breaker_max_cooldown_seconds: default_max_cooldown(),
+            max_failover_attempts: 0,
+            connection_retry_count: default_connection_retry_count(),
+            queue_at_concurrency_limit: false,
+            selection_strategy: SelectionStrategy::default(),
+            reliability_ordering: false,
+            hedge: false,
+            hedge_delay_ms: default_hedge_delay_ms(),
+            demotion_window: 0,
+            demotion_min_success_rate: default_demotion_min_success_rate(),
+            hourly_retention: default_hourly_retention(),
+            daily_retention: default_daily_retention(),
+            stats_compression: false,
+            estimate_streaming_output_tokens: true,
+            strict_validation: false,
+            debug_capture: false,
+            redact_error_bodies: false,
+            store_error_bodies: true,
+            response_header_allowlist: Vec::new(),
+            response_header_denylist: default_response_header_denylist(),
+            system_prompt_prefix: None,
+            system_prompt_suffix: None,
+            benchmark_models: HashMap::new(),
+            compact_json: false,
         }
     }
 }
 
 impl GatewayConfig {
+    /// 加载配置，并合并环境变量 / .env 中定义的供应商
+    ///
+    /// 环境变量供应商追加到列表末尾 (同名变量中进程环境变量优先于 .env 文件)。
+    /// 与文件中的供应商 id 相同时保留文件中的定义并跳过环境变量供应商：
+    /// 环境变量供应商不会写回文件，如果覆盖文件中的定义，下次保存时该供应商会永久丢失。
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut config = Self::load_file(&path)?;
+        
+        let dotenv = reload_dotenv(path.as_ref());
+        for env_provider in load_env_providers(&dotenv) {
+            if config.providers.iter().any(|p| p.id == env_provider.id) {
+                eprintln!("⚠️ Provider '{}' from environment conflicts with a provider in the config file, skipped", env_provider.id);
+                continue;
+            }
+            config.providers.push(env_provider);
+        }
+        
+        Ok(config)
+    }
+    
+    fn load_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         if !path.as_ref().exists() {
             return Ok(Self::default());
         }
@@ -156,13 +582,144 @@ impl GatewayConfig {
     }
 
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let content = serde_json::to_string_pretty(self).context("Failed to serialize gateway config")?;
+        // 环境变量定义的供应商不写入文件，避免密钥落盘
+        let mut persisted = self.clone();
+        persisted.providers.retain(|p| !p.from_env);
+        let content = if persisted.compact_json {
+            serde_json::to_string(&persisted)
+        } else {
+            serde_json::to_string_pretty(&persisted)
+        }
+        .context("Failed to serialize gateway config")?;
         if let Some(parent) = path.as_ref().parent() {
             fs::create_dir_all(parent).context("Failed to create config directory")?;
         }
         fs::write(path, content).context("Failed to write gateway config")
     }
     
+    /// 校验配置：端口、供应商 ID/名称/地址及数值范围，返回第一个发现的问题
+    pub fn validate(&self) -> Result<(), String> {
+        let ports = [
+            ("anthropic_port", self.anthropic_port),
+            ("responses_port", self.responses_port),
+            ("chat_port", self.chat_port),
+        ];
+        for (i, (name, port)) in ports.iter().enumerate() {
+            if *port == 0 {
+                return Err(format!("{} must not be 0", name));
+            }
+            if let Some((other, _)) = ports[..i].iter().find(|(_, p)| p == port) {
+                return Err(format!("{} and {} use the same port {}", other, name, port));
+            }
+        }
+        
+        let mut ids = std::collections::HashSet::new();
+        for provider in &self.providers {
+            if provider.id.trim().is_empty() {
+                return Err(format!("Provider '{}' has an empty id", provider.name));
+            }
+            if !ids.insert(provider.id.as_str()) {
+                return Err(format!("Duplicate provider id '{}'", provider.id));
+            }
+            if provider.name.trim().is_empty() {
+                return Err(format!("Provider '{}' has an empty name", provider.id));
+            }
+            if !provider.base_url.starts_with("http://") && !provider.base_url.starts_with("https://") {
+                return Err(format!("Provider '{}' base_url must start with http:// or https://", provider.name));
+            }
+            if let Some(name) = &provider.auth_header_name {
+                if axum::http::HeaderName::from_bytes(name.trim().as_bytes()).is_err() {
+                    return Err(format!("Provider '{}' auth_header_name '{}' is not a valid header name", provider.name, name));
+                }
+            }
+            if provider.auth_header_format.as_ref().is_some_and(|f| !f.contains("{key}")) {
+                return Err(format!("Provider '{}' auth_header_format must contain {{key}}", provider.name));
+            }
+            if provider.max_concurrent == Some(0) {
+                return Err(format!("Provider '{}' max_concurrent must be at least 1", provider.name));
+            }
+            if let Some(url) = provider.proxy_url.as_deref().map(str::trim).filter(|u| !u.is_empty()) {
+                if reqwest::Proxy::all(url).is_err() {
+                    return Err(format!("Provider '{}' proxy_url '{}' is not a valid proxy URL", provider.name, url));
+                }
+            }
+            if let Some(path) = provider.response_content_path.as_deref().filter(|p| !p.is_empty()) {
+                if !path.starts_with('/') {
+                    return Err(format!("Provider '{}' response_content_path '{}' must be a JSON pointer starting with '/'", provider.name, path));
+                }
+            }
+            if let Some(tokenizer) = provider.tokenizer.as_deref().filter(|t| !t.is_empty()) {
+                if !TOKENIZER_NAMES.contains(&tokenizer) {
+                    return Err(format!("Provider '{}' has unknown tokenizer '{}' (expected one of: {})",
+                        provider.name, tokenizer, TOKENIZER_NAMES.join(", ")));
+                }
+            }
+            if provider.chars_per_token.is_some_and(|c| !c.is_finite() || c <= 0.0) {
+                return Err(format!("Provider '{}' chars_per_token must be a positive number", provider.name));
+            }
+            if provider.max_stop_sequences == Some(0) {
+                return Err(format!("Provider '{}' max_stop_sequences must be at least 1", provider.name));
+            }
+        }
+        
+        for (i, rule) in self.token_routing_rules.iter().enumerate() {
+            if let (Some(min), Some(max)) = (rule.min_input_tokens, rule.max_input_tokens) {
+                if min >= max {
+                    return Err(format!("token_routing_rules[{}]: min_input_tokens must be less than max_input_tokens", i));
+                }
+            }
+            if rule.exclusive && rule.provider_ids.is_empty() {
+                return Err(format!("token_routing_rules[{}]: exclusive rules must list provider_ids", i));
+            }
+        }
+        
+        if !(0.0..=1.0).contains(&self.demotion_min_success_rate) {
+            return Err("demotion_min_success_rate must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+    
+    /// 上游响应头是否转发给客户端 (逐跳头由调用方另行移除)
+    pub fn forwards_response_header(&self, name: &str) -> bool {
+        let listed = |list: &[String]| list.iter().any(|h| h.eq_ignore_ascii_case(name));
+        (self.response_header_allowlist.is_empty() || listed(&self.response_header_allowlist))
+            && !listed(&self.response_header_denylist)
+    }
+    
+    /// 计算指定请求的缓存 TTL (无匹配规则时返回 None，使用默认 TTL)
+    pub fn cache_ttl_for(&self, path: &str, api_type: &ApiType) -> Option<u64> {
+        self.cache_ttl_rules
+            .iter()
+            .find(|rule| rule.matches(path, api_type))
+            .map(|rule| rule.ttl_seconds)
+    }
+    
+    /// 查找与请求输入 token 数匹配的路由规则
+    pub fn token_routing_rule_for(&self, input_tokens: u32, api_type: &ApiType) -> Option<&TokenRoutingRule> {
+        self.token_routing_rules
+            .iter()
+            .find(|rule| rule.matches(input_tokens, api_type))
+    }
+    
+    /// 指定 API 类型的网关是否启用
+    pub fn is_api_type_enabled(&self, api_type: &ApiType) -> bool {
+        match api_type {
+            ApiType::Anthropic => self.anthropic_enabled,
+            ApiType::OpenAIResponses => self.responses_enabled,
+            ApiType::OpenAIChat => self.chat_enabled,
+        }
+    }
+    
+    /// 指定 API 类型的网关配置的 Unix 域套接字路径 (未配置或为空时使用 TCP 端口)
+    pub fn socket_path_for(&self, api_type: &ApiType) -> Option<&str> {
+        let path = match api_type {
+            ApiType::Anthropic => &self.anthropic_socket_path,
+            ApiType::OpenAIResponses => &self.responses_socket_path,
+            ApiType::OpenAIChat => &self.chat_socket_path,
+        };
+        path.as_deref().map(str::trim).filter(|p| !p.is_empty())
+    }
+    
     /// 获取支持指定 API 类型的供应商列表
     pub fn get_providers_for_api_type(&self, api_type: &ApiType) -> Vec<&Provider> {
         self.providers
@@ -170,4 +727,257 @@ impl GatewayConfig {
             .filter(|p| p.enabled && p.api_types.contains(api_type))
             .collect()
     }
+    
+    /// 获取声明为指定 API 类型跨协议兜底的供应商列表 (不含原生支持该类型的供应商)
+    pub fn get_fallback_providers_for_api_type(&self, api_type: &ApiType) -> Vec<&Provider> {
+        self.providers
+            .iter()
+            .filter(|p| p.enabled && p.fallback_for.contains(api_type) && !p.api_types.contains(api_type))
+            .collect()
+    }
+}
+
+/// 解析 API 类型名称 (支持 "anthropic" / "responses" / "chat" 及枚举名)
+fn parse_api_type(name: &str) -> Option<ApiType> {
+    match name.trim().to_lowercase().as_str() {
+        "anthropic" => Some(ApiType::Anthropic),
+        "responses" | "openairesponses" => Some(ApiType::OpenAIResponses),
+        "chat" | "openaichat" => Some(ApiType::OpenAIChat),
+        _ => None,
+    }
+}
+
+// 最近一次读取的 .env 变量，用于解析 `${ENV_VAR}` 形式的 API Key 占位符
+static DOTENV_VARS: LazyLock<RwLock<HashMap<String, String>>> = LazyLock::new(Default::default);
+
+/// 重新读取配置文件旁的 .env (加载配置、导入供应商时调用)，返回读取到的变量
+pub fn reload_dotenv(config_path: &Path) -> HashMap<String, String> {
+    let vars = read_dotenv(&config_path.with_file_name(".env"));
+    *DOTENV_VARS.write().unwrap_or_else(|e| e.into_inner()) = vars.clone();
+    vars
+}
+
+/// 读取 .env 文件中的键值对 (忽略空行和 # 注释)
+fn read_dotenv(path: &Path) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    let Ok(content) = fs::read_to_string(path) else {
+        return vars;
+    };
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim().trim_matches('"').trim_matches('\'');
+            vars.insert(key.trim().to_string(), value.to_string());
+        }
+    }
+    vars
+}
+
+/// 解析 `${ENV_VAR}` 形式的 API Key 占位符，返回环境变量名
+pub fn api_key_placeholder(api_key: &str) -> Option<&str> {
+    api_key.trim()
+        .strip_prefix("${")
+        .and_then(|k| k.strip_suffix('}'))
+        .filter(|k| !k.is_empty())
+}
+
+/// 从环境变量解析供应商定义
+///
+/// 格式：`VBDING_PROVIDER_<N>_NAME`、`_BASE_URL`、`_API_KEY`、`_API_TYPES` (逗号分隔)、
+/// 可选 `_ID`、`_PROXY_URL`，N 从 1 开始连续编号。进程环境变量优先于 .env 文件中的同名变量。
+fn load_env_providers(dotenv: &HashMap<String, String>) -> Vec<Provider> {
+    let get = |key: &str| -> Option<String> {
+        std::env::var(key).ok()
+            .or_else(|| dotenv.get(key).cloned())
+            .filter(|v| !v.is_empty())
+    };
+    
+    let mut providers = Vec::new();
+    for index in 1.. {
+        let prefix = format!("VBDING_PROVIDER_{}", index);
+        let (Some(name), Some(base_url)) = (get(&format!("{}_NAME", prefix)), get(&format!("{}_BASE_URL", prefix))) else {
+            break;
+        };
+        
+        let api_types = get(&format!("{}_API_TYPES", prefix))
+            .map(|types| types.split(',').filter_map(parse_api_type).collect())
+            .unwrap_or_else(default_api_types);
+        
+        providers.push(Provider {
+            id: get(&format!("{}_ID", prefix)).unwrap_or_else(|| format!("env-{}", index)),
+            name,
+            base_url,
+            api_key: get(&format!("{}_API_KEY", prefix)).unwrap_or_default(),
+            model_mapping: HashMap::new(),
+            enabled: true,
+            api_types,
+            weight: default_weight(),
+            input_price_per_1k: 0.0,
+            output_price_per_1k: 0.0,
+            claude_code_proxy: false,
+            responses_proxy: false,
+            responses_upstream: false,
+            max_tokens_cap: None,
+            max_tokens_default: None,
+            system_role: SystemRole::default(),
+            reasoning_param_name: None,
+            force_streaming: false,
+            disable_streaming: false,
+            fallback_for: Vec::new(),
+            tags: Vec::new(),
+            auth_header_name: None,
+            auth_header_format: None,
+            max_concurrent: None,
+            merge_consecutive_roles: false,
+            max_stop_sequences: None,
+            single_stop_as_string: false,
+            disable_stream_usage: false,
+            response_content_path: None,
+            bill_failed_requests: false,
+            tokenizer: get(&format!("{}_TOKENIZER", prefix)),
+            chars_per_token: None,
+            proxy_url: get(&format!("{}_PROXY_URL", prefix)),
+            cache_namespace: None,
+            from_env: true,
+        });
+    }
+    
+    if !providers.is_empty() {
+        eprintln!("🔑 Loaded {} provider(s) from environment", providers.len());
+    }
+    providers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    fn provider(id: &str) -> Provider {
+        serde_json::from_value(json!({
+            "id": id,
+            "name": id,
+            "base_url": format!("https://{}.example.com", id),
+            "api_key": "sk-test",
+            "enabled": true
+        })).unwrap()
+    }
+    
+    /// 在独立的临时目录中创建配置文件和 .env
+    fn temp_config_dir(providers: Vec<Provider>, dotenv: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("vibehub-config-test-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = GatewayConfig { providers, ..Default::default() };
+        config.save(dir.join("gateway.json")).unwrap();
+        fs::write(dir.join(".env"), dotenv).unwrap();
+        dir
+    }
+    
+    #[test]
+    fn env_provider_does_not_replace_file_provider() {
+        let dir = temp_config_dir(vec![provider("shared")], "\
+VBDING_PROVIDER_1_ID=shared
+VBDING_PROVIDER_1_NAME=From env
+VBDING_PROVIDER_1_BASE_URL=https://env.example.com
+VBDING_PROVIDER_2_NAME=Env only
+VBDING_PROVIDER_2_BASE_URL=https://env-only.example.com
+");
+        let config = GatewayConfig::load(dir.join("gateway.json")).unwrap();
+        
+        assert_eq!(config.providers.len(), 2);
+        let shared = &config.providers[0];
+        assert_eq!(shared.base_url, "https://shared.example.com");
+        assert!(!shared.from_env);
+        assert_eq!(config.providers[1].id, "env-2");
+        assert!(config.providers[1].from_env);
+        
+        // 保存后文件中的供应商仍然存在
+        config.save(dir.join("gateway.json")).unwrap();
+        let reloaded = GatewayConfig::load_file(dir.join("gateway.json")).unwrap();
+        assert_eq!(reloaded.providers.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["shared"]);
+        
+        fs::remove_dir_all(dir).unwrap();
+    }
+    
+    #[test]
+    fn cache_ttl_rule_matches_path_prefix() {
+        let config = GatewayConfig {
+            cache_ttl_rules: vec![CacheTtlRule { path_prefix: Some("/v1/models".to_string()), api_type: None, ttl_seconds: 3600 }],
+            ..Default::default()
+        };
+        assert_eq!(config.cache_ttl_for("/v1/models", &ApiType::OpenAIChat), Some(3600));
+        assert_eq!(config.cache_ttl_for("/v1/models/gpt-4o", &ApiType::Anthropic), Some(3600));
+        // 没有匹配的规则时使用缓存的默认 TTL
+        assert_eq!(config.cache_ttl_for("/v1/messages", &ApiType::Anthropic), None);
+    }
+    
+    #[test]
+    fn cache_ttl_rules_match_api_type_and_first_rule_wins() {
+        let config = GatewayConfig {
+            cache_ttl_rules: vec![
+                CacheTtlRule { path_prefix: Some("/v1/chat".to_string()), api_type: Some(ApiType::OpenAIChat), ttl_seconds: 30 },
+                CacheTtlRule { path_prefix: None, api_type: Some(ApiType::OpenAIChat), ttl_seconds: 60 },
+                CacheTtlRule { path_prefix: None, api_type: None, ttl_seconds: 120 },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(config.cache_ttl_for("/v1/chat/completions", &ApiType::OpenAIChat), Some(30));
+        assert_eq!(config.cache_ttl_for("/v1/models", &ApiType::OpenAIChat), Some(60));
+        assert_eq!(config.cache_ttl_for("/v1/chat/completions", &ApiType::Anthropic), Some(120));
+    }
+
+    #[test]
+    fn benchmark_models_are_keyed_by_api_type() {
+        let config: GatewayConfig = serde_json::from_value(json!({
+            "providers": [],
+            "benchmark_models": {"Anthropic": "claude-haiku", "OpenAIChat": "gpt-4o-mini"}
+        })).unwrap();
+        assert_eq!(config.benchmark_models.get(&ApiType::Anthropic).map(String::as_str), Some("claude-haiku"));
+        assert!(!config.benchmark_models.contains_key(&ApiType::OpenAIResponses));
+        assert!(GatewayConfig::default().benchmark_models.is_empty());
+    }
+
+    #[test]
+    fn token_routing_rule_boundaries() {
+        let config: GatewayConfig = serde_json::from_value(json!({
+            "providers": [],
+            "token_routing_rules": [
+                {"max_input_tokens": 100000, "provider_ids": ["cheap"]},
+                {"min_input_tokens": 100000, "provider_ids": ["long"], "api_type": "Anthropic"}
+            ]
+        })).unwrap();
+        let route = |tokens: u32, api_type: ApiType| config.token_routing_rule_for(tokens, &api_type)
+            .map(|rule| rule.provider_ids[0].as_str());
+        
+        assert_eq!(route(0, ApiType::Anthropic), Some("cheap"));
+        assert_eq!(route(99_999, ApiType::Anthropic), Some("cheap"));
+        // min 包含边界，max 不包含
+        assert_eq!(route(100_000, ApiType::Anthropic), Some("long"));
+        assert_eq!(route(u32::MAX, ApiType::Anthropic), Some("long"));
+        // api_type 不匹配时不使用该规则
+        assert_eq!(route(100_000, ApiType::OpenAIChat), None);
+    }
+
+    #[test]
+    fn api_key_placeholder_resolves_from_process_env_then_dotenv() {
+        let mut placeholder = provider("placeholder");
+        placeholder.api_key = "${VBDING_TEST_PLACEHOLDER_KEY}".to_string();
+        let dotenv = HashMap::from([("VBDING_TEST_PLACEHOLDER_KEY".to_string(), "sk-dotenv".to_string())]);
+        let no_env = |_: &str| None;
+        assert_eq!(placeholder.resolve_api_key(no_env, &dotenv), "sk-dotenv");
+        assert_eq!(placeholder.resolve_api_key(no_env, &HashMap::new()), "");
+        
+        // 进程环境变量优先，为空时回退到 .env
+        let env = |var: &str| (var == "VBDING_TEST_PLACEHOLDER_KEY").then(|| "sk-process".to_string());
+        assert_eq!(placeholder.resolve_api_key(env, &dotenv), "sk-process");
+        let empty_env = |_: &str| Some(String::new());
+        assert_eq!(placeholder.resolve_api_key(empty_env, &dotenv), "sk-dotenv");
+        
+        // 非占位符的 Key 原样使用
+        assert_eq!(provider("plain").resolve_api_key(env, &dotenv), "sk-test");
+    }
 }
@@ -0,0 +1,96 @@
+// 网关错误类型：供服务器启动和 Tauri 命令返回结构化错误
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::gateway::converter::ConversionError;
+
+#[derive(Debug)]
+pub enum GatewayError {
+    /// 监听端口或 Unix 域套接字绑定失败
+    Bind(String),
+    /// 服务器运行中出错 (接受连接失败等)
+    Server(String),
+    /// 配置无效或读写配置文件失败
+    Config(String),
+    /// 请求的记录/资源不存在
+    NotFound(String),
+    /// 上游请求失败
+    Upstream(String),
+    /// 协议转换失败
+    Conversion(ConversionError),
+    /// 请求超时
+    Timeout(String),
+    /// 命令参数无效
+    InvalidRequest(String),
+    /// 读写文件失败 (配置文件除外)
+    Io(String),
+}
+
+pub type GatewayResult<T> = Result<T, GatewayError>;
+
+impl GatewayError {
+    /// 错误类别 (序列化给前端的 kind 字段)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GatewayError::Bind(_) => "bind",
+            GatewayError::Server(_) => "server",
+            GatewayError::Config(_) => "config",
+            GatewayError::NotFound(_) => "not_found",
+            GatewayError::Upstream(_) => "upstream",
+            GatewayError::Conversion(_) => "conversion",
+            GatewayError::Timeout(_) => "timeout",
+            GatewayError::InvalidRequest(_) => "invalid_request",
+            GatewayError::Io(_) => "io",
+        }
+    }
+}
+
+impl std::fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GatewayError::Bind(msg) => write!(f, "Failed to bind {}", msg),
+            GatewayError::Server(msg) => write!(f, "Server error: {}", msg),
+            GatewayError::Config(msg) => write!(f, "Invalid config: {}", msg),
+            GatewayError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            GatewayError::Upstream(msg) => write!(f, "Upstream error: {}", msg),
+            GatewayError::Conversion(e) => write!(f, "{}", e),
+            GatewayError::Timeout(msg) => write!(f, "Timed out: {}", msg),
+            GatewayError::InvalidRequest(msg) => write!(f, "Invalid request: {}", msg),
+            GatewayError::Io(msg) => write!(f, "I/O error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for GatewayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GatewayError::Conversion(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConversionError> for GatewayError {
+    fn from(e: ConversionError) -> Self {
+        GatewayError::Conversion(e)
+    }
+}
+
+impl From<reqwest::Error> for GatewayError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            GatewayError::Timeout(e.to_string())
+        } else {
+            GatewayError::Upstream(e.to_string())
+        }
+    }
+}
+
+// 序列化为 { kind, message }，前端可按 kind 区分错误类型，message 直接用于展示
+impl Serialize for GatewayError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("GatewayError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
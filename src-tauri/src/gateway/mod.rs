@@ -2,21 +2,42 @@ pub mod config;
 pub mod proxy;
 pub mod stats;
 pub mod cache;
-pub mod converter;
+pub mod error;
+pub mod tokenizer;
+pub use vibehub_converter as converter;
 
-use tauri::{AppHandle, Manager, Runtime, State};
+use tauri::{AppHandle, Emitter, Manager, Runtime, State};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::path::PathBuf;
-use tokio::sync::RwLock;
-use self::config::GatewayConfig;
-use self::stats::{StatsManager, GatewayStats};
+use tokio::sync::{watch, RwLock};
+use dashmap::DashMap;
+use self::config::{ApiType, GatewayConfig};
+use self::stats::{StatsManager, GatewayStats, MonthlyCostProjection, ProviderSla, RequestLog};
+use self::cache::{CacheManager, CacheStats};
+use self::error::{GatewayError, GatewayResult};
 
 pub struct GatewayState(pub Arc<RwLock<GatewayConfig>>);
 pub struct GatewayConfigPath(pub PathBuf);
 pub struct GatewayStatsState(pub Arc<StatsManager>);
+pub struct GatewayShutdown(pub watch::Sender<bool>);
+pub struct GatewayCacheState(pub Arc<CacheManager>);
+pub struct GatewayServersState(pub Arc<DashMap<ApiType, proxy::BoundServer>>);
+pub struct GatewayProviderRuntime(pub proxy::ProviderRuntime);
+
+/// 单个网关服务器的实际运行状态
+#[derive(serde::Serialize)]
+pub struct GatewayServerStatus {
+    pub api_type: ApiType,
+    pub enabled: bool,
+    pub configured_port: u16,
+    pub bound_port: Option<u16>,
+    pub socket_path: Option<String>,
+    pub running: bool,
+}
 
 #[tauri::command]
-pub async fn get_gateway_config(state: State<'_, GatewayState>) -> Result<GatewayConfig, String> {
+pub async fn get_gateway_config(state: State<'_, GatewayState>) -> GatewayResult<GatewayConfig> {
     let config = state.0.read().await;
     Ok(config.clone())
 }
@@ -25,21 +46,698 @@ pub async fn get_gateway_config(state: State<'_, GatewayState>) -> Result<Gatewa
 pub async fn save_gateway_config(
     state: State<'_, GatewayState>,
     path_state: State<'_, GatewayConfigPath>,
+    stats_state: State<'_, GatewayStatsState>,
+    cache_state: State<'_, GatewayCacheState>,
     config: GatewayConfig
-) -> Result<(), String> {
+) -> GatewayResult<()> {
+    apply_config(&state, &path_state, &stats_state, &cache_state, config).await
+}
+
+/// 替换运行中的配置并写盘
+async fn apply_config(
+    state: &GatewayState,
+    path_state: &GatewayConfigPath,
+    stats_state: &GatewayStatsState,
+    cache_state: &GatewayCacheState,
+    config: GatewayConfig,
+) -> GatewayResult<()> {
+    stats_state.0.set_retention(config.hourly_retention, config.daily_retention);
+    stats_state.0.set_compression(config.stats_compression);
+    cache_state.0.set_persist(config.cache_persist);
     let mut current_config = state.0.write().await;
     *current_config = config.clone();
     
     // Save to disk
-    config.save(&path_state.0).map_err(|e| e.to_string())?;
+    config.save(&path_state.0).map_err(|e| GatewayError::Config(format!("failed to save config: {}", e)))?;
     Ok(())
 }
 
+/// 获取格式化的原始配置 JSON (不包含来自环境变量的供应商，与配置文件内容一致)
+#[tauri::command]
+pub async fn get_gateway_config_json(state: State<'_, GatewayState>) -> GatewayResult<String> {
+    let mut config = state.0.read().await.clone();
+    config.providers.retain(|p| !p.from_env);
+    serde_json::to_string_pretty(&config)
+        .map_err(|e| GatewayError::Config(format!("failed to serialize config: {}", e)))
+}
+
+/// 以原始 JSON 替换配置：解析和校验都通过后才生效并写盘，否则返回错误且保持当前配置不变
+#[tauri::command]
+pub async fn set_gateway_config_json(
+    state: State<'_, GatewayState>,
+    path_state: State<'_, GatewayConfigPath>,
+    stats_state: State<'_, GatewayStatsState>,
+    cache_state: State<'_, GatewayCacheState>,
+    json: String,
+) -> GatewayResult<()> {
+    let mut config: GatewayConfig = serde_json::from_str(&json)
+        .map_err(|e| GatewayError::Config(format!("invalid JSON: {}", e)))?;
+    
+    // 保留来自环境变量的供应商 (原始 JSON 中不包含它们)
+    config.providers.retain(|p| !p.from_env);
+    {
+        let current = state.0.read().await;
+        for env_provider in current.providers.iter().filter(|p| p.from_env) {
+            if !config.providers.iter().any(|p| p.id == env_provider.id) {
+                config.providers.push(env_provider.clone());
+            }
+        }
+    }
+    
+    config.validate().map_err(GatewayError::Config)?;
+    apply_config(&state, &path_state, &stats_state, &cache_state, config).await
+}
+
+/// 导出供应商列表 (JSON，不包含来自环境变量的供应商)，用于分享配置
+///
+/// include_keys 为 false 时 API Key 替换为 `${VBDING_<ID>_API_KEY}` 环境变量占位符，
+/// 导入方设置同名环境变量 (或写入配置文件旁的 .env) 即可自动生效
+#[tauri::command]
+pub async fn export_providers(state: State<'_, GatewayState>, include_keys: bool) -> GatewayResult<String> {
+    let config = state.0.read().await;
+    let providers: Vec<config::Provider> = config.providers.iter()
+        .filter(|p| !p.from_env)
+        .cloned()
+        .map(|mut provider| {
+            if !include_keys && !provider.api_key.is_empty() && config::api_key_placeholder(&provider.api_key).is_none() {
+                provider.api_key = provider.api_key_env_placeholder();
+            }
+            provider
+        })
+        .collect();
+    serde_json::to_string_pretty(&providers)
+        .map_err(|e| GatewayError::Config(format!("failed to serialize providers: {}", e)))
+}
+
+#[derive(serde::Serialize)]
+pub struct ImportProvidersResult {
+    pub added: usize,
+    pub updated: usize,
+    // 缺少可用 API Key 的供应商 id (密钥为空或占位符对应的环境变量未设置)，需要用户补充
+    pub missing_keys: Vec<String>,
+}
+
+/// 导入 export_providers 导出的供应商列表并与当前配置合并
+///
+/// id 相同的供应商被覆盖 (导入的 API Key 为空且 base_url 不变时保留现有密钥)，其余追加到末尾；
+/// 校验通过后才生效并写盘。`${VAR}` 占位符从进程环境变量或配置文件旁的 .env 解析
+#[tauri::command]
+pub async fn import_providers(
+    state: State<'_, GatewayState>,
+    path_state: State<'_, GatewayConfigPath>,
+    stats_state: State<'_, GatewayStatsState>,
+    cache_state: State<'_, GatewayCacheState>,
+    json: String,
+) -> GatewayResult<ImportProvidersResult> {
+    let imported: Vec<config::Provider> = serde_json::from_str(&json)
+        .map_err(|e| GatewayError::Config(format!("invalid providers JSON: {}", e)))?;
+    
+    let mut config = state.0.read().await.clone();
+    // 导入的占位符可能引用刚添加到 .env 的变量
+    config::reload_dotenv(&path_state.0);
+    let result = merge_imported_providers(&mut config, imported);
+    
+    config.validate().map_err(GatewayError::Config)?;
+    apply_config(&state, &path_state, &stats_state, &cache_state, config).await?;
+    Ok(result)
+}
+
+/// 将导入的供应商合并到配置中，返回新增/更新数量和缺少 Key 的供应商
+fn merge_imported_providers(config: &mut GatewayConfig, imported: Vec<config::Provider>) -> ImportProvidersResult {
+    let mut result = ImportProvidersResult { added: 0, updated: 0, missing_keys: Vec::new() };
+    for mut provider in imported {
+        provider.from_env = false;
+        match config.providers.iter_mut().find(|p| p.id == provider.id) {
+            Some(existing) => {
+                // 只有地址不变时才沿用已有的 Key，避免把 Key 发送到另一个服务
+                if provider.api_key.is_empty() && provider.base_url == existing.base_url {
+                    provider.api_key = std::mem::take(&mut existing.api_key);
+                }
+                *existing = provider.clone();
+                result.updated += 1;
+            }
+            None => {
+                config.providers.push(provider.clone());
+                result.added += 1;
+            }
+        }
+        if provider.resolved_api_key().is_empty() {
+            result.missing_keys.push(provider.id);
+        }
+    }
+    result
+}
+
+/// 获取各网关服务器的实际绑定端口和运行状态 (配置端口不一定与实际一致)
+#[tauri::command]
+pub async fn get_gateway_status(
+    state: State<'_, GatewayState>,
+    servers: State<'_, GatewayServersState>,
+) -> GatewayResult<Vec<GatewayServerStatus>> {
+    let config = state.0.read().await;
+    let entries = [
+        (ApiType::Anthropic, config.anthropic_enabled, config.anthropic_port),
+        (ApiType::OpenAIResponses, config.responses_enabled, config.responses_port),
+        (ApiType::OpenAIChat, config.chat_enabled, config.chat_port),
+    ];
+    
+    Ok(entries
+        .into_iter()
+        .map(|(api_type, enabled, configured_port)| {
+            let bound = servers.0.get(&api_type).map(|s| s.clone());
+            GatewayServerStatus {
+                api_type,
+                enabled,
+                configured_port,
+                bound_port: bound.as_ref().and_then(|s| s.bound_port),
+                socket_path: bound.as_ref().and_then(|s| s.socket_path.clone()),
+                running: bound.is_some_and(|s| s.running),
+            }
+        })
+        .collect())
+}
+
+/// 供应商健康信息 (含动态有效权重)
+#[derive(serde::Serialize)]
+pub struct ProviderHealthInfo {
+    pub provider_id: String,
+    pub name: String,
+    pub is_healthy: bool,
+    pub consecutive_failures: u32,
+    pub reliability_score: f64,
+    pub weight: u32,
+    pub effective_weight: f64,
+    // 排空中：不再分配新请求 (与禁用、冷却不同)
+    pub draining: bool,
+}
+
+/// 获取各供应商的健康状态和当前有效权重
+#[tauri::command]
+pub async fn get_provider_health(
+    state: State<'_, GatewayStatsState>,
+    config_state: State<'_, GatewayState>,
+    runtime: State<'_, GatewayProviderRuntime>,
+) -> GatewayResult<Vec<ProviderHealthInfo>> {
+    let provider_stats = state.0.get_stats().provider_stats;
+    let config = config_state.0.read().await;
+    Ok(config.providers
+        .iter()
+        .map(|p| {
+            let ps = provider_stats.get(&p.name);
+            ProviderHealthInfo {
+                provider_id: p.id.clone(),
+                name: p.name.clone(),
+                is_healthy: ps.is_none_or(|ps| ps.is_healthy),
+                consecutive_failures: ps.map(|ps| ps.consecutive_failures).unwrap_or(0),
+                reliability_score: ps.map(|ps| ps.reliability_score).unwrap_or(1.0),
+                weight: p.weight,
+                effective_weight: ps.map(|ps| ps.effective_weight(p.weight)).unwrap_or(p.weight as f64),
+                draining: runtime.0.draining.contains(&p.id),
+            }
+        })
+        .collect())
+}
+
+/// 排空供应商：不再分配新请求，进行中的请求正常完成，不触发熔断 (仅在本次运行期间有效)
+#[tauri::command]
+pub async fn drain_provider(
+    config_state: State<'_, GatewayState>,
+    runtime: State<'_, GatewayProviderRuntime>,
+    provider_id: String,
+) -> GatewayResult<()> {
+    let config = config_state.0.read().await;
+    let provider = config.providers.iter()
+        .find(|p| p.id == provider_id)
+        .ok_or_else(|| GatewayError::NotFound(format!("provider {}", provider_id)))?;
+    println!("🚰 Draining provider {}", provider.name);
+    runtime.0.draining.insert(provider_id);
+    Ok(())
+}
+
+/// 取消排空，供应商重新参与选择
+#[tauri::command]
+pub async fn undrain_provider(
+    runtime: State<'_, GatewayProviderRuntime>,
+    provider_id: String,
+) -> GatewayResult<()> {
+    runtime.0.draining.remove(&provider_id);
+    Ok(())
+}
+
+/// 立即解除单个供应商的熔断冷却，并重置其统计中的健康状态
+#[tauri::command]
+pub async fn clear_provider_cooldown<R: Runtime>(
+    app: AppHandle<R>,
+    config_state: State<'_, GatewayState>,
+    stats_state: State<'_, GatewayStatsState>,
+    runtime: State<'_, GatewayProviderRuntime>,
+    provider_id: String,
+) -> GatewayResult<()> {
+    let config = config_state.0.read().await;
+    let provider = config.providers.iter()
+        .find(|p| p.id == provider_id)
+        .ok_or_else(|| GatewayError::NotFound(format!("provider {}", provider_id)))?;
+    
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    runtime.0.health_status.remove(&provider.id);
+    stats_state.0.reset_provider_health(&provider.name);
+    stats_state.0.end_cooldown(&provider.name, now);
+    println!("🔓 Cleared cooldown for provider {}", provider.name);
+    
+    let _ = app.emit("gateway://provider-status", proxy::ProviderStatusEvent {
+        provider_id: provider.id.clone(),
+        status: "reset".to_string(),
+        api_type: "manual".to_string(),
+    });
+    Ok(())
+}
+
+/// 供应商在请求时实际生效的配置：包含环境变量供应商、默认值填充后的字段，
+/// base_url 去掉末尾的 /，API Key 已脱敏
+#[derive(serde::Serialize)]
+pub struct EffectiveProvider {
+    #[serde(flatten)]
+    pub provider: config::Provider,
+    pub api_key_set: bool,
+    // 发往上游的认证头 (按首个 API 类型和代理模式确定，密钥部分已脱敏)
+    pub auth_header: String,
+    // 实际提供服务的 API 类型 (原生支持 + 跨协议兜底)，未启用时为空
+    pub serving_api_types: Vec<ApiType>,
+    pub effective_weight: f64,
+    pub is_healthy: bool,
+}
+
+/// 获取供应商实际生效的配置，用于排查供应商为何未被使用
+#[tauri::command]
+pub async fn get_effective_provider(
+    state: State<'_, GatewayState>,
+    stats_state: State<'_, GatewayStatsState>,
+    provider_id: String,
+) -> GatewayResult<EffectiveProvider> {
+    let config = state.0.read().await;
+    let mut provider = config.providers.iter()
+        .find(|p| p.id == provider_id)
+        .cloned()
+        .ok_or_else(|| GatewayError::NotFound(format!("provider {}", provider_id)))?;
+    
+    let api_key_set = !provider.resolved_api_key().is_empty();
+    if api_key_set {
+        provider.api_key = stats::REDACTED_API_KEY.to_string();
+    }
+    provider.base_url = provider.base_url.trim_end_matches('/').to_string();
+    
+    let api_type = provider.api_types.first().cloned().unwrap_or_default();
+    let (header_name, header_format) = provider.auth_header(&api_type, provider.claude_code_proxy);
+    let auth_header = format!("{}: {}", header_name, header_format.replace("{key}", stats::REDACTED_API_KEY));
+    
+    let serving_api_types = if provider.enabled {
+        let mut types = provider.api_types.clone();
+        types.extend(provider.fallback_for.iter().filter(|t| !provider.api_types.contains(t)).cloned());
+        types
+    } else {
+        Vec::new()
+    };
+    
+    let provider_stats = stats_state.0.get_stats().provider_stats;
+    let ps = provider_stats.get(&provider.name);
+    let effective_weight = ps.map(|ps| ps.effective_weight(provider.weight)).unwrap_or(provider.weight as f64);
+    let is_healthy = ps.is_none_or(|ps| ps.is_healthy);
+    
+    Ok(EffectiveProvider {
+        provider,
+        api_key_set,
+        auth_header,
+        serving_api_types,
+        effective_weight,
+        is_healthy,
+    })
+}
+
+/// 解析统计周期："24h"、"7d"、"90m" 或纯数字 (小时)
+fn parse_period_secs(period: &str) -> Option<u64> {
+    let period = period.trim();
+    let (number, unit_secs) = match period.char_indices().last()? {
+        (i, 'm') => (&period[..i], 60),
+        (i, 'h') => (&period[..i], 3600),
+        (i, 'd') => (&period[..i], 86400),
+        _ => (period, 3600),
+    };
+    number.trim().parse::<u64>().ok().filter(|n| *n > 0).map(|n| n * unit_secs)
+}
+
+/// 计算供应商在指定周期内的 SLA：成功率、可用性 (不在冷却中的时间占比) 和错误率趋势
+#[tauri::command]
+pub async fn get_provider_sla(
+    state: State<'_, GatewayStatsState>,
+    config_state: State<'_, GatewayState>,
+    provider_id: String,
+    period: String,
+) -> GatewayResult<ProviderSla> {
+    let period_secs = parse_period_secs(&period)
+        .ok_or_else(|| GatewayError::InvalidRequest(format!("invalid period '{}', expected e.g. 24h or 7d", period)))?;
+    let provider_name = {
+        let config = config_state.0.read().await;
+        config.providers.iter()
+            .find(|p| p.id == provider_id)
+            .map(|p| p.name.clone())
+            .ok_or_else(|| GatewayError::NotFound(format!("provider {}", provider_id)))?
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(state.0.provider_sla(&provider_id, &provider_name, period_secs, now))
+}
+
+#[tauri::command]
+pub async fn get_gateway_stats(
+    state: State<'_, GatewayStatsState>,
+    config_state: State<'_, GatewayState>,
+) -> GatewayResult<GatewayStats> {
+    let mut stats = state.0.get_stats();
+    
+    // 按当前配置中的供应商标签汇总
+    let config = config_state.0.read().await;
+    let provider_tags: HashMap<String, Vec<String>> = config.providers
+        .iter()
+        .filter(|p| !p.tags.is_empty())
+        .map(|p| (p.name.clone(), p.tags.clone()))
+        .collect();
+    stats.aggregate_by_provider_tag(&provider_tags);
+    
+    Ok(stats)
+}
+
+/// 按 API 类型、状态码类别 (2 = 2xx, 4 = 4xx ...) 和供应商筛选最近请求
 #[tauri::command]
-pub async fn get_gateway_stats(state: State<'_, GatewayStatsState>) -> Result<GatewayStats, String> {
+pub async fn query_recent_requests(
+    state: State<'_, GatewayStatsState>,
+    api_type: Option<String>,
+    status_class: Option<u16>,
+    provider: Option<String>,
+    limit: usize,
+) -> GatewayResult<Vec<RequestLog>> {
+    Ok(state.0.query_recent(api_type.as_deref(), status_class, provider.as_deref(), limit))
+}
+
+/// 将开启 debug_capture 时记录的上游请求导出为 curl 命令 (默认不包含 API Key)
+#[tauri::command]
+pub async fn request_as_curl(
+    state: State<'_, GatewayStatsState>,
+    config_state: State<'_, GatewayState>,
+    request_log_id: String,
+    unredacted: Option<bool>,
+) -> GatewayResult<String> {
+    let log = state.0.find_recent(&request_log_id)
+        .ok_or_else(|| GatewayError::NotFound(format!("request log {}", request_log_id)))?;
+    let captured = log.captured_request
+        .ok_or_else(|| GatewayError::NotFound("captured request; enable debug_capture and retry the request".to_string()))?;
+    
+    // 需要真实密钥时从当前配置中按名称查找供应商
+    let api_key = if unredacted.unwrap_or(false) {
+        let config = config_state.0.read().await;
+        let provider = config.providers.iter()
+            .find(|p| p.name == log.provider)
+            .ok_or_else(|| GatewayError::NotFound(format!("provider {}", log.provider)))?;
+        Some(provider.resolved_api_key())
+    } else {
+        None
+    };
+    
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "'\\''"));
+    let mut parts = vec![format!("curl -X {} {}", captured.method, quote(&captured.url))];
+    for (name, value) in &captured.headers {
+        let value = match &api_key {
+            Some(key) => value.replace(stats::REDACTED_API_KEY, key),
+            None => value.clone(),
+        };
+        parts.push(format!("-H {}", quote(&format!("{}: {}", name, value))));
+    }
+    if !captured.body.is_empty() {
+        parts.push(format!("--data-raw {}", quote(&captured.body)));
+    }
+    Ok(parts.join(" \\\n  "))
+}
+
+/// 重放请求的结果
+#[derive(serde::Serialize)]
+pub struct ReplayResult {
+    pub status: u16,
+    pub body: String,
+    // 本次重放的请求 ID 及最终处理的供应商 (所有供应商都失败时为最后尝试的供应商)
+    pub request_id: Option<String>,
+    pub provider: Option<String>,
+}
+
+/// 将开启 debug_capture 时记录的客户端请求重新发送到对应网关 (走完整的选择/故障转移流程)
+#[tauri::command]
+pub async fn replay_request(
+    state: State<'_, GatewayStatsState>,
+    servers: State<'_, GatewayServersState>,
+    request_log_id: String,
+) -> GatewayResult<ReplayResult> {
+    let log = state.0.find_recent(&request_log_id)
+        .ok_or_else(|| GatewayError::NotFound(format!("request log {}", request_log_id)))?;
+    let captured = log.captured_request
+        .filter(|c| !c.client_path.is_empty())
+        .ok_or_else(|| GatewayError::NotFound("captured request; enable debug_capture and retry the request".to_string()))?;
+    
+    let api_type = match log.api_type.as_str() {
+        "anthropic" => ApiType::Anthropic,
+        "responses" => ApiType::OpenAIResponses,
+        "chat" => ApiType::OpenAIChat,
+        other => return Err(GatewayError::NotFound(format!("API type {}", other))),
+    };
+    let bound = servers.0.get(&api_type).map(|s| s.clone())
+        .filter(|s| s.running)
+        .ok_or_else(|| GatewayError::Server(format!("the {} gateway is not running", log.api_type)))?;
+    let port = bound.bound_port
+        .ok_or_else(|| GatewayError::Config("replay is only supported for gateways listening on a TCP port".to_string()))?;
+    
+    let method = reqwest::Method::from_bytes(captured.method.as_bytes())
+        .map_err(|e| GatewayError::Upstream(e.to_string()))?;
+    let mut request = reqwest::Client::new()
+        .request(method, format!("http://127.0.0.1:{}{}", port, captured.client_path))
+        .body(captured.client_body);
+    for (name, value) in &captured.client_headers {
+        request = request.header(name, value);
+    }
+    
+    let response = request.send().await?;
+    let status = response.status().as_u16();
+    let request_id = response.headers()
+        .get("x-vbding-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let body = response.text().await?;
+    
+    // 成功日志的 ID 即请求 ID；失败时取该请求最后一次尝试的日志
+    let provider = request_id.as_deref().and_then(|id| {
+        state.0.find_recent(id)
+            .or_else(|| state.0.query_recent(None, None, None, usize::MAX).into_iter().find(|l| l.request_id == id))
+            .map(|l| l.provider)
+    });
+    
+    Ok(ReplayResult { status, body, request_id, provider })
+}
+
+/// 按最近 7 天的费用预测月度支出 (没有统计数据时返回 null)
+#[tauri::command]
+pub async fn project_monthly_cost(state: State<'_, GatewayStatsState>) -> GatewayResult<Option<MonthlyCostProjection>> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(state.0.project_monthly_cost(now))
+}
+
+/// 整理统计数据：按保留策略裁剪旧的小时/天统计并去重
+#[tauri::command]
+pub async fn compact_stats(state: State<'_, GatewayStatsState>) -> GatewayResult<GatewayStats> {
+    state.0.compact();
+    Ok(state.0.get_stats())
+}
+
+/// 导出统计数据到用户指定的文件 (独立于实时统计文件)
+#[tauri::command]
+pub async fn export_gateway_stats(state: State<'_, GatewayStatsState>, path: String) -> GatewayResult<()> {
+    state.0.export_to(std::path::Path::new(&path)).map_err(|e| GatewayError::Io(format!("{:#}", e)))
+}
+
+/// 导入统计文件并与当前统计合并
+#[tauri::command]
+pub async fn import_gateway_stats(state: State<'_, GatewayStatsState>, path: String) -> GatewayResult<GatewayStats> {
+    state.0.import_from(std::path::Path::new(&path)).map_err(|e| GatewayError::Io(format!("{:#}", e)))?;
     Ok(state.0.get_stats())
 }
 
+#[tauri::command]
+pub async fn get_cache_stats(state: State<'_, GatewayCacheState>) -> GatewayResult<CacheStats> {
+    Ok(state.0.stats())
+}
+
+#[tauri::command]
+pub async fn clear_gateway_cache(state: State<'_, GatewayCacheState>) -> GatewayResult<()> {
+    state.0.clear();
+    Ok(())
+}
+
+/// [调试] 模拟供应商状态事件，便于前端在没有真实故障时开发状态动画
+#[tauri::command]
+pub async fn simulate_provider_status<R: Runtime>(
+    app: AppHandle<R>,
+    provider_id: String,
+    status: String,
+    api_type: String
+) -> GatewayResult<()> {
+    if !cfg!(debug_assertions) {
+        return Err(GatewayError::InvalidRequest("simulation commands are only available in debug builds".to_string()));
+    }
+    app.emit("gateway://provider-status", proxy::ProviderStatusEvent {
+        provider_id,
+        status,
+        api_type,
+    }).map_err(|e| GatewayError::Server(e.to_string()))
+}
+
+/// [调试] 记录一条模拟的请求日志
+#[tauri::command]
+pub async fn simulate_request_log(
+    state: State<'_, GatewayStatsState>,
+    provider: String,
+    status: u16,
+    api_type: String
+) -> GatewayResult<RequestLog> {
+    if !cfg!(debug_assertions) {
+        return Err(GatewayError::InvalidRequest("simulation commands are only available in debug builds".to_string()));
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let is_success = (200..300).contains(&status);
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let log = RequestLog {
+        id: request_id.clone(),
+        timestamp: now,
+        provider,
+        model: "simulated".to_string(),
+        status,
+        duration_ms: 500,
+        input_tokens: 100,
+        output_tokens: if is_success { 50 } else { 0 },
+        cost: 0.0,
+        path: "/simulated".to_string(),
+        client_agent: "simulator".to_string(),
+        api_type,
+        cached: false,
+        error_message: if is_success { None } else { Some(format!("HTTP {} - simulated failure", status)) },
+        attempt_index: 0,
+        failed_providers: Vec::new(),
+        request_id,
+        cancelled: false,
+        requested_model: "simulated".to_string(),
+        mapped_model: "simulated".to_string(),
+        captured_request: None,
+        estimated: false,
+    };
+    state.0.record_request(log.clone());
+    Ok(log)
+}
+
+/// 预览协议转换结果：将示例 Anthropic 请求按给定的模型映射转换为 OpenAI 格式
+#[tauri::command]
+pub async fn preview_conversion(
+    body: String,
+    model_mapping: HashMap<String, String>
+) -> GatewayResult<String> {
+    let options = converter::ConversionOptions {
+        model_mapping,
+        ..Default::default()
+    };
+    let request: serde_json::Value = serde_json::from_str(&body)
+        .map_err(|e| converter::ConversionError::InvalidRequest(format!("Failed to parse Anthropic request: {}", e)))?;
+    let converted = converter::anthropic_request_to_openai(&request, &options)?;
+    serde_json::to_string_pretty(&converted)
+        .map_err(|e| converter::ConversionError::Internal(e.to_string()).into())
+}
+
+#[derive(serde::Serialize)]
+pub struct CostEstimate {
+    pub input_tokens: u32,
+    pub est_input_cost: f64,
+    pub est_max_output_cost: f64,
+}
+
+/// 预估请求费用：输入按估算 token 计算，输出按 max_tokens 最坏情况计算
+#[tauri::command]
+pub async fn estimate_request_cost(
+    state: State<'_, GatewayState>,
+    body: String,
+    provider_id: String
+) -> GatewayResult<CostEstimate> {
+    let config = state.0.read().await;
+    let provider = config.providers.iter()
+        .find(|p| p.id == provider_id)
+        .ok_or_else(|| GatewayError::NotFound(format!("provider {}", provider_id)))?;
+    
+    let input_tokens = proxy::estimate_input_tokens(body.as_bytes(), provider, provider.claude_code_proxy);
+    let max_tokens = serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|json| json.get("max_tokens").and_then(|m| m.as_u64()))
+        .unwrap_or(4096)
+        .min(u32::MAX as u64) as u32;
+    
+    Ok(CostEstimate {
+        input_tokens,
+        est_input_cost: proxy::calculate_cost(input_tokens, 0, provider.input_price_per_1k, provider.output_price_per_1k),
+        est_max_output_cost: proxy::calculate_cost(0, max_tokens, provider.input_price_per_1k, provider.output_price_per_1k),
+    })
+}
+
+/// 基准测试：向指定 API 类型的所有已启用供应商发送同一提示词 (按供应商配置进行协议转换)，
+/// 返回按成功优先、总耗时升序排列的对比结果。未指定模型时使用配置的 benchmark_models。
+/// 各供应商并发发送，与网关共用 max_concurrent 限制 (达到上限时排队等待)
+#[tauri::command]
+pub async fn benchmark_providers(
+    state: State<'_, GatewayState>,
+    runtime: State<'_, GatewayProviderRuntime>,
+    prompt: String,
+    api_type: ApiType,
+    model: Option<String>,
+) -> GatewayResult<Vec<proxy::ProviderBenchmark>> {
+    let config = state.0.read().await.clone();
+    let providers = config.get_providers_for_api_type(&api_type);
+    if providers.is_empty() {
+        return Err(GatewayError::NotFound(format!("enabled providers for {:?}", api_type)));
+    }
+    
+    let model = model
+        .or_else(|| config.benchmark_models.get(&api_type).cloned())
+        .filter(|m| !m.trim().is_empty())
+        .ok_or_else(|| GatewayError::InvalidRequest(format!("no model given and no benchmark model configured for {:?}", api_type)))?;
+    let (path, body) = proxy::benchmark_request(&api_type, &prompt, &model);
+    let clients = proxy::UpstreamClients::default();
+    let concurrency = &runtime.0.concurrency;
+    
+    let mut results = futures::future::join_all(providers.iter().map(|provider| {
+        let (clients, path, body, config, api_type) = (&clients, &path, body.clone(), &config, &api_type);
+        async move {
+            let _permit = match concurrency.semaphore(provider) {
+                Some(semaphore) => semaphore.acquire_owned().await.ok(),
+                None => None,
+            };
+            proxy::benchmark_provider(clients, provider, api_type, path, body, config).await
+        }
+    })).await;
+    
+    results.sort_by(|a, b| b.success.cmp(&a.success).then(a.latency_ms.cmp(&b.latency_ms)));
+    Ok(results)
+}
+
 pub fn init<R: Runtime>(app: &AppHandle<R>) {
     // Calculate config path (same logic as Storage)
     let exe_path = std::env::current_exe().expect("Failed to get current exe");
@@ -50,18 +748,126 @@ pub fn init<R: Runtime>(app: &AppHandle<R>) {
 
     // Load config
     let config = GatewayConfig::load(&config_path).unwrap_or_default();
-    let config_state = Arc::new(RwLock::new(config));
+    
+    // Init cache
+    let cache = Arc::new(CacheManager::new(
+        data_dir.clone(),
+        config.cache_max_entries,
+        config.cache_max_total_bytes,
+        config.cache_ttl_seconds,
+        config.cache_persist,
+    ));
     
     // Init stats
-    let stats_manager = Arc::new(StatsManager::new(data_dir));
+    let stats_manager = Arc::new(StatsManager::new(
+        data_dir,
+        config.hourly_retention,
+        config.daily_retention,
+        config.stats_compression,
+    ));
+    let config_state = Arc::new(RwLock::new(config));
+    let servers = Arc::new(DashMap::new());
+    let runtime = proxy::ProviderRuntime::default();
+
+    // 退出信号：通知各网关服务器优雅停止
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
     app.manage(GatewayState(config_state.clone()));
     app.manage(GatewayConfigPath(config_path));
     app.manage(GatewayStatsState(stats_manager.clone()));
+    app.manage(GatewayShutdown(shutdown_tx));
+    app.manage(GatewayCacheState(cache.clone()));
+    app.manage(GatewayServersState(servers.clone()));
+    app.manage(GatewayProviderRuntime(runtime.clone()));
+
+    spawn_cache_eviction(Arc::downgrade(&cache), config_state.clone(), shutdown_rx.clone());
 
     let app_handle = app.clone();
     tauri::async_runtime::spawn(async move {
         // 启动三个独立的网关服务器
-        proxy::start_servers(config_state, stats_manager, app_handle).await;
+        if let Err(e) = proxy::start_servers(config_state, stats_manager, cache, servers, runtime, app_handle, shutdown_rx).await {
+            eprintln!("❌ Failed to start gateway servers: {}", e);
+        }
     });
 }
+
+/// 后台定期清理过期缓存并写盘 (开启 cache_persist 时)，间隔每轮从配置读取 (修改后下一轮生效)
+/// 缓存被释放或收到退出信号时停止
+fn spawn_cache_eviction(
+    cache: std::sync::Weak<CacheManager>,
+    config: Arc<RwLock<GatewayConfig>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let interval = config.read().await.cache_eviction_interval_seconds;
+            // 间隔为 0 时不清理，但仍定期检查配置是否改变
+            let sleep_secs = if interval == 0 { 60 } else { interval };
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(sleep_secs)) => {}
+                _ = shutdown.changed() => return,
+            }
+            if *shutdown.borrow() {
+                return;
+            }
+            let Some(cache) = cache.upgrade() else {
+                return;
+            };
+            if interval > 0 {
+                cache.evict_expired();
+            }
+            // 开启 cache_persist 时定期写盘，异常退出也只会丢失最近一个周期的缓存
+            let _ = tauri::async_runtime::spawn_blocking(move || cache.persist()).await;
+        }
+    });
+}
+
+/// 应用退出时调用：停止网关服务器并将统计数据 (及开启持久化时的缓存) 刷新到磁盘
+pub fn shutdown<R: Runtime>(app: &AppHandle<R>) {
+    if let Some(shutdown) = app.try_state::<GatewayShutdown>() {
+        let _ = shutdown.0.send(true);
+    }
+    if let Some(stats) = app.try_state::<GatewayStatsState>() {
+        stats.0.persist();
+    }
+    if let Some(cache) = app.try_state::<GatewayCacheState>() {
+        cache.0.persist();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    fn provider(id: &str, base_url: &str, api_key: &str) -> config::Provider {
+        serde_json::from_value(json!({
+            "id": id,
+            "name": id,
+            "base_url": base_url,
+            "api_key": api_key,
+            "enabled": true
+        })).unwrap()
+    }
+    
+    #[test]
+    fn import_keeps_existing_key_only_for_the_same_base_url() {
+        let mut config = GatewayConfig {
+            providers: vec![
+                provider("same", "https://same.example.com", "sk-same"),
+                provider("moved", "https://old.example.com", "sk-moved"),
+            ],
+            ..Default::default()
+        };
+        let result = merge_imported_providers(&mut config, vec![
+            provider("same", "https://same.example.com", ""),
+            provider("moved", "https://new.example.com", ""),
+            provider("new", "https://new.example.com", "sk-new"),
+        ]);
+        
+        assert_eq!((result.added, result.updated), (1, 2));
+        assert_eq!(config.providers[0].api_key, "sk-same");
+        assert_eq!(config.providers[1].api_key, "");
+        assert_eq!(result.missing_keys, vec!["moved".to_string()]);
+    }
+}
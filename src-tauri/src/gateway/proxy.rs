@@ -4,27 +4,218 @@ use axum::{
     response::{IntoResponse, Response},
     routing::any,
     Router,
-    http::{StatusCode, HeaderValue},
+    http::{StatusCode, HeaderName, HeaderValue, HeaderMap, Method},
 };
+use futures::future::Either;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use crate::gateway::config::{GatewayConfig, ApiType};
-use crate::gateway::stats::{StatsManager, RequestLog};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{watch, OwnedSemaphorePermit, RwLock, Semaphore};
+use crate::gateway::config::{GatewayConfig, ApiType, Provider, SelectionStrategy, TokenRoutingRule, DEFAULT_CHARS_PER_TOKEN};
+use crate::gateway::stats::{self, StatsManager, RequestLog, CapturedRequest, REDACTED_API_KEY};
 use crate::gateway::cache::CacheManager;
+use crate::gateway::error::{GatewayError, GatewayResult};
 use crate::gateway::converter;
+use crate::gateway::tokenizer;
 use tower_http::cors::CorsLayer;
 use reqwest::Client;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tauri::{AppHandle, Emitter, Runtime};
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 
 pub struct ProxyState<R: Runtime> {
     pub config: Arc<RwLock<GatewayConfig>>,
     pub stats: Arc<StatsManager>,
     pub cache: Arc<CacheManager>,
     pub app: AppHandle<R>,
-    pub health_status: Arc<DashMap<String, u64>>,
+    pub health_status: Arc<DashMap<String, ProviderHealth>>,
     pub api_type: ApiType,
+    pub servers: Arc<DashMap<ApiType, BoundServer>>,
+    // 排空中的供应商 id：不再分配新请求，进行中的请求正常完成
+    pub draining: Arc<DashSet<String>>,
+    pub clients: Arc<UpstreamClients>,
+    pub concurrency: Arc<ProviderConcurrency>,
+}
+
+/// 供应商熔断状态：最近一次失败时间和连续失败次数 (用于指数冷却)
+///
+/// 冷却期内熔断打开 (跳过该供应商)；冷却结束后进入半开状态，只放行一个探测请求：
+/// 探测成功时由调用方移除记录 (熔断关闭)，失败时重新进入冷却
+#[derive(Debug, Clone, Copy)]
+pub struct ProviderHealth {
+    pub last_failure: u64,
+    pub consecutive_failures: u32,
+    // 半开状态下探测请求的发出时间 (None = 尚未探测)
+    pub probe_started: Option<u64>,
+}
+
+impl ProviderHealth {
+    /// 当前冷却时间：base * 2^(连续失败次数 - 1)，不超过 max
+    pub fn cooldown_secs(&self, base: u64, max: u64) -> u64 {
+        let exponent = self.consecutive_failures.saturating_sub(1).min(32);
+        base.saturating_mul(1u64 << exponent).min(max.max(base))
+    }
+    
+    pub fn in_cooldown(&self, now: u64, base: u64, max: u64) -> bool {
+        now.saturating_sub(self.last_failure) < self.cooldown_secs(base, max)
+    }
+    
+    /// 是否不接受新请求：冷却中，或半开状态下已有探测请求在进行
+    /// (探测请求超过一个冷却期仍未结束时视为已丢弃，允许重新探测)
+    pub fn blocks_requests(&self, now: u64, base: u64, max: u64) -> bool {
+        self.in_cooldown(now, base, max)
+            || self.probe_started.is_some_and(|started| now.saturating_sub(started) < self.cooldown_secs(base, max))
+    }
+    
+    /// 提前结束冷却：只清除失败时间和探测标记，保留连续失败次数，
+    /// 再次失败时冷却时间仍按之前的次数继续退避
+    pub fn lift_cooldown(&mut self) {
+        self.last_failure = 0;
+        self.probe_started = None;
+    }
+}
+
+/// 半开状态的供应商即将处理请求：标记探测开始，其他请求在探测结束前继续跳过该供应商
+fn claim_probe(health_status: &DashMap<String, ProviderHealth>, provider: &Provider, now: u64) {
+    if let Some(mut health) = health_status.get_mut(&provider.id) {
+        println!("🔎 Provider {} is half-open, sending a probe request", provider.name);
+        health.probe_started = Some(now);
+    }
+}
+
+/// 探测请求未得出结果 (对冲落败被取消或根本没有发出)：清除探测标记，下一个请求可以重新探测
+fn release_probe(health_status: &DashMap<String, ProviderHealth>, provider: &Provider) {
+    if let Some(mut health) = health_status.get_mut(&provider.id) {
+        health.probe_started = None;
+    }
+}
+
+/// 候选供应商是否全部处于冷却中 (没有熔断记录或正在半开探测的供应商不算)
+fn all_in_cooldown<'a>(
+    health_status: &DashMap<String, ProviderHealth>,
+    mut providers: impl Iterator<Item = &'a Provider>,
+    now: u64,
+    cooldown: u64,
+    max_cooldown: u64,
+) -> bool {
+    providers.all(|p| {
+        health_status.get(&p.id)
+            .is_some_and(|health| health.probe_started.is_none() && health.in_cooldown(now, cooldown, max_cooldown))
+    })
+}
+
+/// 记录供应商失败：刷新失败时间并累加连续失败次数 (成功时由调用方移除记录，冷却时间恢复为基础值)
+fn mark_provider_failure(health_status: &DashMap<String, ProviderHealth>, provider_id: &str, now: u64) {
+    health_status
+        .entry(provider_id.to_string())
+        .and_modify(|health| {
+            health.last_failure = now;
+            health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+            health.probe_started = None;
+        })
+        .or_insert(ProviderHealth { last_failure: now, consecutive_failures: 1, probe_started: None });
+}
+
+/// 最近 demotion_window 次请求的成功率低于阈值时降级供应商并通知前端 (需在记录本次请求之后调用)，
+/// 返回是否降级；降级后滚动窗口重新开始计数，冷却结束后的探测不会因旧的失败记录立即再次降级
+fn demote_if_unreliable<R: Runtime>(state: &ProxyState<R>, provider: &Provider, config: &GatewayConfig, api_type_str: &str) -> bool {
+    if !state.stats.check_demotion(&provider.name, config.demotion_window, config.demotion_min_success_rate) {
+        return false;
+    }
+    println!("📉 Provider {} demoted: success rate below {:.0}% over last {} requests",
+        provider.name, config.demotion_min_success_rate * 100.0, config.demotion_window);
+    let _ = state.app.emit("gateway://provider-status", ProviderStatusEvent {
+        provider_id: provider.id.clone(),
+        status: "demoted".to_string(),
+        api_type: api_type_str.to_string(),
+    });
+    true
+}
+
+/// 记录供应商失败，并把本次冷却期写入统计 (用于计算可用性/SLA)
+fn record_provider_failure<R: Runtime>(state: &ProxyState<R>, provider: &Provider, now: u64, cooldown: u64, max_cooldown: u64) {
+    record_failure_cooldown(&state.health_status, &state.stats, provider, now, cooldown, max_cooldown);
+}
+
+fn record_failure_cooldown(
+    health_status: &DashMap<String, ProviderHealth>,
+    stats: &StatsManager,
+    provider: &Provider,
+    now: u64,
+    cooldown: u64,
+    max_cooldown: u64,
+) {
+    mark_provider_failure(health_status, &provider.id, now);
+    if let Some(health) = health_status.get(&provider.id) {
+        stats.record_cooldown(&provider.name, now, now + health.cooldown_secs(cooldown, max_cooldown));
+    }
+}
+
+/// 供应商并发限制：按供应商 ID 维护信号量 (三个网关共享)，max_concurrent 变化时重建
+#[derive(Default)]
+pub struct ProviderConcurrency {
+    semaphores: DashMap<String, (usize, Arc<Semaphore>)>,
+}
+
+impl ProviderConcurrency {
+    /// 获取供应商的信号量 (未设置 max_concurrent 时返回 None)
+    pub(crate) fn semaphore(&self, provider: &Provider) -> Option<Arc<Semaphore>> {
+        let limit = provider.max_concurrent?;
+        let mut entry = self.semaphores
+            .entry(provider.id.clone())
+            .or_insert_with(|| (limit, Arc::new(Semaphore::new(limit))));
+        if entry.0 != limit {
+            // 上限已修改：新请求使用新的信号量，旧许可在请求结束时自然释放
+            *entry = (limit, Arc::new(Semaphore::new(limit)));
+        }
+        Some(entry.1.clone())
+    }
+}
+
+/// 供应商运行时状态 (熔断记录、排空标记、并发限制)，由三个网关服务器和 Tauri 命令共享
+#[derive(Clone, Default)]
+pub struct ProviderRuntime {
+    pub health_status: Arc<DashMap<String, ProviderHealth>>,
+    pub draining: Arc<DashSet<String>>,
+    pub concurrency: Arc<ProviderConcurrency>,
+}
+
+/// 上游 HTTP 客户端：按供应商的代理配置缓存 (直连的供应商共用一个客户端)，避免每次请求重新创建
+#[derive(Default)]
+pub struct UpstreamClients {
+    clients: DashMap<Option<String>, Client>,
+}
+
+impl UpstreamClients {
+    /// 获取供应商使用的客户端 (配置了 proxy_url 时经该代理连接)
+    pub fn client_for(&self, provider: &Provider) -> GatewayResult<Client> {
+        let proxy_url = provider.proxy_url.as_deref().map(str::trim).filter(|u| !u.is_empty());
+        let key = proxy_url.map(str::to_string);
+        if let Some(client) = self.clients.get(&key) {
+            return Ok(client.clone());
+        }
+        
+        let client = match proxy_url {
+            Some(url) => {
+                let proxy = reqwest::Proxy::all(url)
+                    .map_err(|e| GatewayError::Config(format!("invalid proxy_url for provider '{}': {}", provider.name, e)))?;
+                Client::builder()
+                    .proxy(proxy)
+                    .build()
+                    .map_err(|e| GatewayError::Config(format!("failed to build client for provider '{}': {}", provider.name, e)))?
+            }
+            None => Client::new(),
+        };
+        self.clients.insert(key, client.clone());
+        Ok(client)
+    }
+}
+
+/// 网关服务器实际绑定的端口 (或 Unix 域套接字) 及运行状态
+#[derive(Debug, Clone)]
+pub struct BoundServer {
+    pub bound_port: Option<u16>,
+    pub socket_path: Option<String>,
+    pub running: bool,
 }
 
 impl<R: Runtime> Clone for ProxyState<R> {
@@ -36,30 +227,59 @@ impl<R: Runtime> Clone for ProxyState<R> {
             app: self.app.clone(),
             health_status: self.health_status.clone(),
             api_type: self.api_type.clone(),
+            servers: self.servers.clone(),
+            draining: self.draining.clone(),
+            clients: self.clients.clone(),
+            concurrency: self.concurrency.clone(),
         }
     }
 }
 
 #[derive(Clone, serde::Serialize)]
-struct ProviderStatusEvent {
-    provider_id: String,
-    status: String,
+pub(crate) struct ProviderStatusEvent {
+    pub(crate) provider_id: String,
+    pub(crate) status: String,
+    pub(crate) api_type: String,
+}
+
+/// 流式响应过程中的 token 用量事件
+#[derive(Clone, serde::Serialize)]
+struct TokenUsageEvent {
+    request_id: String,
     api_type: String,
+    input_tokens: u32,
+    output_tokens: u32,
 }
 
-/// 启动三个独立的网关服务器
+// 逐跳响应头：只对单个连接有效，不转发给客户端
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection", "keep-alive", "proxy-authenticate", "proxy-authorization",
+    "te", "trailer", "transfer-encoding", "upgrade",
+];
+
+// 客户端指定供应商标签的请求头 (不转发给上游)
+const PROVIDER_TAG_HEADER: &str = "x-provider-tag";
+
+// 每累计多少个文本增量发送一次 token 用量事件
+const TOKEN_USAGE_EMIT_INTERVAL: u32 = 20;
+
+/// 启动三个独立的网关服务器 (配置校验失败时只记录日志，仍按现有配置启动，避免所有网关都不可用)
 pub async fn start_servers<R: Runtime>(
     config: Arc<RwLock<GatewayConfig>>,
     stats: Arc<StatsManager>,
+    cache: Arc<CacheManager>,
+    servers: Arc<DashMap<ApiType, BoundServer>>,
+    runtime: ProviderRuntime,
     app: AppHandle<R>,
-) {
+    shutdown: watch::Receiver<bool>,
+) -> GatewayResult<()> {
     let cfg = config.read().await;
+    if let Err(e) = cfg.validate() {
+        eprintln!("⚠️ Gateway config is invalid, starting anyway: {}", e);
+    }
     
-    let cache = Arc::new(CacheManager::new(
-        cfg.cache_max_entries,
-        cfg.cache_ttl_seconds,
-    ));
-    let health_status = Arc::new(DashMap::new());
+    let ProviderRuntime { health_status, draining, concurrency } = runtime;
+    let clients = Arc::new(UpstreamClients::default());
     
     let anthropic_port = cfg.anthropic_port;
     let responses_port = cfg.responses_port;
@@ -71,6 +291,8 @@ pub async fn start_servers<R: Runtime>(
     
     drop(cfg);
     
+    spawn_health_checks(config.clone(), stats.clone(), health_status.clone(), clients.clone(), app.clone(), shutdown.clone());
+    
     // 启动 Anthropic 网关 (Claude Code)
     if anthropic_enabled {
         let state = ProxyState {
@@ -80,10 +302,15 @@ pub async fn start_servers<R: Runtime>(
             app: app.clone(),
             health_status: health_status.clone(),
             api_type: ApiType::Anthropic,
+            servers: servers.clone(),
+            draining: draining.clone(),
+            clients: clients.clone(),
+            concurrency: concurrency.clone(),
         };
         
+        let shutdown = shutdown.clone();
         tokio::spawn(async move {
-            start_single_server(anthropic_port, state, "Anthropic").await;
+            supervise_server(anthropic_port, state, "Anthropic", shutdown).await;
         });
     }
     
@@ -96,10 +323,15 @@ pub async fn start_servers<R: Runtime>(
             app: app.clone(),
             health_status: health_status.clone(),
             api_type: ApiType::OpenAIResponses,
+            servers: servers.clone(),
+            draining: draining.clone(),
+            clients: clients.clone(),
+            concurrency: concurrency.clone(),
         };
         
+        let shutdown = shutdown.clone();
         tokio::spawn(async move {
-            start_single_server(responses_port, state, "OpenAI Responses").await;
+            supervise_server(responses_port, state, "OpenAI Responses", shutdown).await;
         });
     }
     
@@ -112,38 +344,387 @@ pub async fn start_servers<R: Runtime>(
             app: app.clone(),
             health_status: health_status.clone(),
             api_type: ApiType::OpenAIChat,
+            servers: servers.clone(),
+            draining: draining.clone(),
+            clients: clients.clone(),
+            concurrency: concurrency.clone(),
         };
         
+        let shutdown = shutdown.clone();
         tokio::spawn(async move {
-            start_single_server(chat_port, state, "OpenAI Chat").await;
+            supervise_server(chat_port, state, "OpenAI Chat", shutdown).await;
+        });
+    }
+    
+    Ok(())
+}
+
+// 单次主动健康检查的超时时间
+const HEALTH_CHECK_TIMEOUT_SECS: u64 = 10;
+
+/// 后台定期主动检查各已启用供应商 (请求 /v1/models)，更新熔断状态和统计中的健康状态，
+/// 并发送 provider-status 事件。间隔每轮从配置读取，为 0 时不检查；收到退出信号时停止
+fn spawn_health_checks<R: Runtime>(
+    config: Arc<RwLock<GatewayConfig>>,
+    stats: Arc<StatsManager>,
+    health_status: Arc<DashMap<String, ProviderHealth>>,
+    clients: Arc<UpstreamClients>,
+    app: AppHandle<R>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        loop {
+            let interval = config.read().await.health_check_interval_seconds;
+            let sleep_secs = if interval == 0 { 60 } else { interval };
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(sleep_secs)) => {}
+                _ = shutdown.changed() => return,
+            }
+            if *shutdown.borrow() {
+                return;
+            }
+            if interval == 0 {
+                continue;
+            }
+            
+            let cfg = config.read().await.clone();
+            let providers: Vec<&Provider> = cfg.providers.iter().filter(|p| p.enabled).collect();
+            let results = futures::future::join_all(
+                providers.iter().map(|provider| check_provider_health(&clients, provider))
+            ).await;
+            
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            for (provider, healthy) in providers.into_iter().zip(results) {
+                if healthy {
+                    if health_status.remove(&provider.id).is_some() {
+                        println!("💚 Provider {} passed health check, closing circuit breaker", provider.name);
+                        stats.end_cooldown(&provider.name, now);
+                    }
+                } else {
+                    println!("💔 Provider {} failed health check", provider.name);
+                    record_failure_cooldown(&health_status, &stats, provider, now,
+                        cfg.circuit_breaker_cooldown_seconds, cfg.circuit_breaker_max_cooldown_seconds);
+                }
+                stats.set_provider_health(&provider.name, healthy);
+                let _ = app.emit("gateway://provider-status", ProviderStatusEvent {
+                    provider_id: provider.id.clone(),
+                    status: if healthy { "success" } else { "error" }.to_string(),
+                    api_type: "health_check".to_string(),
+                });
+            }
+        }
+    });
+}
+
+/// 请求供应商的模型列表检查其是否可用：2xx 视为健康；
+/// 404/405 说明服务可达只是不提供该接口，也视为健康；连接失败、超时和其他错误状态视为不健康
+async fn check_provider_health(clients: &UpstreamClients, provider: &Provider) -> bool {
+    let Ok(client) = clients.client_for(provider) else {
+        return false;
+    };
+    let api_type = provider.api_types.first().cloned().unwrap_or_default();
+    let url = format!("{}/v1/models", provider.base_url.trim_end_matches('/'));
+    let mut request = client.get(&url).timeout(std::time::Duration::from_secs(HEALTH_CHECK_TIMEOUT_SECS));
+    let api_key = provider.resolved_api_key();
+    if !api_key.is_empty() {
+        let (header_name, header_format) = provider.auth_header(&api_type, provider.claude_code_proxy);
+        request = request.header(header_name, header_format.replace("{key}", &api_key));
+        if api_type == ApiType::Anthropic && !provider.claude_code_proxy {
+            request = request.header("anthropic-version", "2023-06-01");
+        }
+    }
+    
+    match request.send().await {
+        Ok(resp) => {
+            let status = resp.status();
+            status.is_success() || status == StatusCode::NOT_FOUND || status == StatusCode::METHOD_NOT_ALLOWED
+        }
+        Err(_) => false,
+    }
+}
+
+/// 网关服务器状态事件 (意外退出、重启中、重试耗尽)
+#[derive(Clone, serde::Serialize)]
+struct ServerStatusEvent {
+    api_type: String,
+    status: String,
+    attempt: u32,
+    error: Option<String>,
+}
+
+// 服务器意外退出后的最大重启次数
+const MAX_SERVER_RESTARTS: u32 = 5;
+// 重启退避 (秒)：1, 2, 4, 8 ... 最多 30
+const SERVER_RESTART_MAX_BACKOFF_SECS: u64 = 30;
+// 服务器稳定运行超过该时间后重置重启计数
+const SERVER_STABLE_SECS: u64 = 60;
+
+/// 监督单个网关服务器：意外退出 (绑定失败或运行错误) 时按退避重启，
+/// 收到退出信号或网关被禁用时停止，重试耗尽后通知前端
+async fn supervise_server<R: Runtime>(port: u16, state: ProxyState<R>, name: &str, mut shutdown: watch::Receiver<bool>) {
+    let app = state.app.clone();
+    let config = state.config.clone();
+    let api_type = state.api_type.clone();
+    let api_type_str = api_type_to_string(&api_type);
+    let mut restarts = 0u32;
+    
+    loop {
+        let started_at = std::time::Instant::now();
+        let error = match start_single_server(port, state.clone(), name, shutdown.clone()).await {
+            Ok(()) => return,
+            Err(e) => e,
+        };
+        if *shutdown.borrow() {
+            return;
+        }
+        
+        if started_at.elapsed().as_secs() >= SERVER_STABLE_SECS {
+            restarts = 0;
+        }
+        if restarts >= MAX_SERVER_RESTARTS {
+            eprintln!("❌ {} Gateway failed {} times, giving up", name, restarts + 1);
+            let _ = app.emit("gateway://server-status", ServerStatusEvent {
+                api_type: api_type_str,
+                status: "failed".to_string(),
+                attempt: restarts,
+                error: Some(error.to_string()),
+            });
+            return;
+        }
+        restarts += 1;
+        
+        let backoff = (1u64 << (restarts - 1)).min(SERVER_RESTART_MAX_BACKOFF_SECS);
+        println!("🔁 {} Gateway exited unexpectedly, restarting in {}s (attempt {}/{})", name, backoff, restarts, MAX_SERVER_RESTARTS);
+        let _ = app.emit("gateway://server-status", ServerStatusEvent {
+            api_type: api_type_str.clone(),
+            status: "restarting".to_string(),
+            attempt: restarts,
+            error: Some(error.to_string()),
         });
+        
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(backoff)) => {}
+            _ = shutdown.changed() => return,
+        }
+        
+        if !config.read().await.is_api_type_enabled(&api_type) {
+            println!("🛑 {} Gateway disabled, not restarting", name);
+            return;
+        }
     }
 }
 
-async fn start_single_server<R: Runtime>(port: u16, state: ProxyState<R>, name: &str) {
+/// 运行单个网关服务器，正常退出 (收到退出信号) 返回 Ok，绑定失败或运行错误返回 Err
+async fn start_single_server<R: Runtime>(port: u16, state: ProxyState<R>, name: &str, shutdown: watch::Receiver<bool>) -> GatewayResult<()> {
+    let servers = state.servers.clone();
+    let api_type = state.api_type.clone();
+    let socket_path = state.config.read().await.socket_path_for(&api_type).map(str::to_string);
     let app_router = Router::new()
+        .route("/_gateway/health", axum::routing::get(gateway_health::<R>))
+        .route("/_gateway/metrics", axum::routing::get(gateway_metrics::<R>))
         .route("/*path", any(handle_request::<R>))
         .layer(CorsLayer::permissive())
         .with_state(state);
+    
+    // 配置了 Unix 域套接字时不再监听 TCP 端口
+    if let Some(socket_path) = socket_path {
+        return serve_unix_socket(app_router, &socket_path, name, &servers, api_type, shutdown).await;
+    }
 
     let addr = format!("0.0.0.0:{}", port);
-    println!("🚀 {} Gateway listening on {}", name, addr);
     
     match tokio::net::TcpListener::bind(&addr).await {
         Ok(listener) => {
-            if let Err(e) = axum::serve(listener, app_router).await {
-                eprintln!("❌ {} Server error: {}", name, e);
+            // 记录实际绑定的端口 (可能与配置不同，例如配置为 0 时由系统分配)
+            let bound_port = listener.local_addr().ok().map(|a| a.port());
+            println!("🚀 {} Gateway listening on 0.0.0.0:{}", name, bound_port.unwrap_or(port));
+            servers.insert(api_type.clone(), BoundServer { bound_port, socket_path: None, running: true });
+            
+            let result = axum::serve(listener, app_router).with_graceful_shutdown(shutdown_signal(shutdown)).await;
+            servers.insert(api_type, BoundServer { bound_port, socket_path: None, running: false });
+            match result {
+                Ok(()) => {
+                    println!("🛑 {} Gateway stopped", name);
+                    Ok(())
+                }
+                Err(e) => {
+                    eprintln!("❌ {} Server error: {}", name, e);
+                    Err(GatewayError::Server(e.to_string()))
+                }
             }
         }
         Err(e) => {
             eprintln!("❌ Failed to bind {} to {}: {}", name, addr, e);
+            servers.insert(api_type, BoundServer { bound_port: None, socket_path: None, running: false });
+            Err(GatewayError::Bind(format!("{}: {}", addr, e)))
+        }
+    }
+}
+
+/// 等待退出信号 (或发送端被释放)
+async fn shutdown_signal(mut shutdown: watch::Receiver<bool>) {
+    while !*shutdown.borrow_and_update() {
+        if shutdown.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// 在 Unix 域套接字上运行网关 (axum::serve 只支持 TCP，这里手动接受连接并交给 hyper 处理)
+#[cfg(unix)]
+async fn serve_unix_socket(
+    app_router: Router,
+    socket_path: &str,
+    name: &str,
+    servers: &DashMap<ApiType, BoundServer>,
+    api_type: ApiType,
+    shutdown: watch::Receiver<bool>,
+) -> GatewayResult<()> {
+    // 清理上次运行遗留的套接字文件，否则 bind 会失败；路径上若是普通文件或目录则拒绝绑定，避免误删
+    if let Err(e) = remove_stale_socket(socket_path) {
+        eprintln!("❌ Failed to bind {} to unix:{}: {}", name, socket_path, e);
+        servers.insert(api_type, BoundServer { bound_port: None, socket_path: None, running: false });
+        return Err(e);
+    }
+    let listener = match tokio::net::UnixListener::bind(socket_path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("❌ Failed to bind {} to unix:{}: {}", name, socket_path, e);
+            servers.insert(api_type, BoundServer { bound_port: None, socket_path: None, running: false });
+            return Err(GatewayError::Bind(format!("unix:{}: {}", socket_path, e)));
         }
+    };
+    // 套接字仅允许当前用户访问
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Err(e) = std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600)) {
+            eprintln!("❌ Failed to restrict permissions on unix:{}: {}", socket_path, e);
+            servers.insert(api_type, BoundServer { bound_port: None, socket_path: None, running: false });
+            let _ = std::fs::remove_file(socket_path);
+            return Err(GatewayError::Bind(format!("unix:{}: {}", socket_path, e)));
+        }
+    }
+    println!("🚀 {} Gateway listening on unix:{}", name, socket_path);
+    servers.insert(api_type.clone(), BoundServer { bound_port: None, socket_path: Some(socket_path.to_string()), running: true });
+    
+    let graceful = shutdown_signal(shutdown);
+    tokio::pin!(graceful);
+    let result = loop {
+        tokio::select! {
+            _ = &mut graceful => break Ok(()),
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    let service = hyper_util::service::TowerToHyperService::new(app_router.clone());
+                    tokio::spawn(async move {
+                        let io = hyper_util::rt::TokioIo::new(stream);
+                        if let Err(e) = hyper::server::conn::http1::Builder::new().serve_connection(io, service).await {
+                            eprintln!("❌ Unix socket connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => break Err(e),
+            },
+        }
+    };
+    
+    servers.insert(api_type, BoundServer { bound_port: None, socket_path: Some(socket_path.to_string()), running: false });
+    let _ = std::fs::remove_file(socket_path);
+    match result {
+        Ok(()) => {
+            println!("🛑 {} Gateway stopped", name);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("❌ {} Server error: {}", name, e);
+            Err(GatewayError::Server(e.to_string()))
+        }
+    }
+}
+
+/// 删除遗留的套接字文件；路径不存在时直接返回，存在但不是套接字时报错
+#[cfg(unix)]
+fn remove_stale_socket(socket_path: &str) -> GatewayResult<()> {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::symlink_metadata(socket_path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(socket_path)
+            .map_err(|e| GatewayError::Bind(format!("unix:{}: {}", socket_path, e))),
+        Ok(_) => Err(GatewayError::Bind(format!("unix:{}: path exists and is not a socket", socket_path))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(GatewayError::Bind(format!("unix:{}: {}", socket_path, e))),
     }
 }
 
+#[cfg(not(unix))]
+async fn serve_unix_socket(
+    _app_router: Router,
+    socket_path: &str,
+    name: &str,
+    servers: &DashMap<ApiType, BoundServer>,
+    api_type: ApiType,
+    _shutdown: watch::Receiver<bool>,
+) -> GatewayResult<()> {
+    eprintln!("❌ {} Gateway: Unix domain sockets are not supported on this platform ({})", name, socket_path);
+    servers.insert(api_type, BoundServer { bound_port: None, socket_path: None, running: false });
+    Err(GatewayError::Config("Unix domain sockets are not supported on this platform".to_string()))
+}
+
+/// GET /_gateway/health：存活/就绪探针 (不转发上游)，返回网关状态和各供应商的熔断状态
+async fn gateway_health<R: Runtime>(State(state): State<ProxyState<R>>) -> Response {
+    let config = state.config.read().await;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let cooldown = config.circuit_breaker_cooldown_seconds;
+    let max_cooldown = config.circuit_breaker_max_cooldown_seconds;
+    
+    let providers: Vec<serde_json::Value> = config.get_providers_for_api_type(&state.api_type)
+        .into_iter()
+        .map(|provider| {
+            let health = state.health_status.get(&provider.id).map(|h| *h);
+            serde_json::json!({
+                "id": provider.id,
+                "name": provider.name,
+                "is_healthy": health.is_none_or(|h| !h.blocks_requests(now, cooldown, max_cooldown)),
+                "last_failure": health.map(|h| h.last_failure),
+                "consecutive_failures": health.map(|h| h.consecutive_failures).unwrap_or(0),
+                "draining": state.draining.contains(&provider.id),
+            })
+        })
+        .collect();
+    let healthy_count = providers.iter().filter(|p| p["is_healthy"] == true).count();
+    
+    axum::Json(serde_json::json!({
+        "api_type": api_type_to_string(&state.api_type),
+        "enabled": config.is_api_type_enabled(&state.api_type),
+        "provider_count": providers.len(),
+        "healthy_provider_count": healthy_count,
+        "providers": providers,
+    })).into_response()
+}
+
+/// GET /_gateway/metrics：以 Prometheus 文本格式输出统计 (不转发上游)
+async fn gateway_metrics<R: Runtime>(State(state): State<ProxyState<R>>) -> Response {
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        state.stats.get_stats().to_prometheus(),
+    ).into_response()
+}
+
 async fn handle_request<R: Runtime>(
     State(state): State<ProxyState<R>>,
     req: Request<Body>,
+) -> Response {
+    // 请求 ID：在所有响应 (成功、缓存命中、错误) 中返回，便于与请求日志关联
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let mut response = proxy_request(state, req, &request_id).await;
+    if let Ok(val) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("x-vbding-request-id", val);
+    }
+    response
+}
+
+async fn proxy_request<R: Runtime>(
+    state: ProxyState<R>,
+    req: Request<Body>,
+    request_id: &str,
 ) -> Response {
     let start_time = SystemTime::now();
     let config = state.config.read().await;
@@ -172,11 +753,106 @@ async fn handle_request<R: Runtime>(
         Ok(b) => b,
         Err(_) => return (StatusCode::BAD_REQUEST, "Failed to read body").into_response(),
     };
+    
+    // 严格校验模式：格式错误的请求直接返回 400，避免转换后在所有供应商之间故障转移
+    if config.strict_validation && state.api_type == ApiType::Anthropic
+        && method == Method::POST && path.starts_with("/v1/messages") && !path.starts_with("/v1/messages/count_tokens") {
+        if let Err(msg) = converter::validate_anthropic_request(&body_bytes) {
+            println!("❌ Invalid request rejected by strict validation: {}", msg);
+            return (StatusCode::BAD_REQUEST, format!("Invalid request: {}", msg)).into_response();
+        }
+    }
+
+    // 计算 input tokens (按默认比例估算用于路由；记录日志时按各供应商的比例重新估算)
+    let input_text = input_text(&body_bytes);
+    let input_chars = input_text.chars().count();
+    let input_tokens = estimate_tokens_from_chars(input_chars, DEFAULT_CHARS_PER_TOKEN);
+
+    // 获取支持当前 API 类型的供应商
+    let mut providers = config.get_providers_for_api_type(&state.api_type);
+    if config.reliability_ordering {
+        apply_reliability_ordering(&mut providers, &state.stats);
+    }
+    apply_selection_strategy(&mut providers, &config.selection_strategy, &state.stats);
+    
+    // 跨协议兜底供应商：仅在可转换的路径上启用，排在同类型供应商之后
+    // 目前只支持 Anthropic /v1/messages -> OpenAI Chat 的转换
+    let is_messages_path = path.starts_with("/v1/messages");
+    let mut fallback_providers = if state.api_type == ApiType::Anthropic && is_messages_path {
+        config.get_fallback_providers_for_api_type(&state.api_type)
+    } else {
+        Vec::new()
+    };
+    
+    // 按输入 token 数路由：规则中的供应商排在最前 (exclusive 时只使用这些供应商)，并可改写模型名
+    let body_bytes = match config.token_routing_rule_for(input_tokens, &state.api_type) {
+        Some(rule) => {
+            println!("🧭 [{}] Request with ~{} input tokens matched a token routing rule", api_type_to_string(&state.api_type), input_tokens);
+            apply_token_routing(&mut providers, rule);
+            if rule.exclusive {
+                fallback_providers.retain(|p| rule.provider_ids.contains(&p.id));
+            }
+            match rule.model.as_deref() {
+                Some(model) => rewrite_request_model(body_bytes, model),
+                None => body_bytes,
+            }
+        }
+        None => body_bytes,
+    };
+    
+    // 排空中的供应商不再接收新请求
+    providers.retain(|p| !state.draining.contains(&p.id));
+    fallback_providers.retain(|p| !state.draining.contains(&p.id));
+    
+    // x-provider-tag：只在带有该标签的供应商中选择
+    let provider_tag = headers.get(PROVIDER_TAG_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty());
+    if let Some(tag) = &provider_tag {
+        providers.retain(|p| p.tags.contains(tag));
+        fallback_providers.retain(|p| p.tags.contains(tag));
+        if providers.is_empty() && fallback_providers.is_empty() {
+            return (StatusCode::SERVICE_UNAVAILABLE, format!("No active providers with tag '{}' for this API type", tag)).into_response();
+        }
+    }
+    
+    if providers.is_empty() && fallback_providers.is_empty() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "No active providers for this API type").into_response();
+    }
+    
+    let candidates: Vec<_> = providers.iter().map(|p| (*p, false))
+        .chain(fallback_providers.iter().map(|p| (*p, true)))
+        .collect();
+    
+    // 检查是否需要协议转换 (Claude Code 代理模式 / 跨协议兜底 / Responses 代理模式)
+    // 只对 /v1/messages 和 /v1/responses 路径应用转换，其他路径直接透传
+    let is_responses_path = path == "/v1/responses";
+    let needs_conversion = |provider: &Provider, is_cross_fallback: bool| match state.api_type {
+        ApiType::Anthropic => (provider.claude_code_proxy || is_cross_fallback) && is_messages_path,
+        ApiType::OpenAIResponses => provider.responses_proxy && is_responses_path,
+        ApiType::OpenAIChat => false,
+    };
+    let requested_model = request_model(&body_bytes).unwrap_or_default();
+    let client_auth = client_auth_scope(&headers);
+    // 全局系统提示词前缀/后缀会改写 Anthropic 网关发往上游的请求，修改后不能命中旧的缓存
+    let system_affixes = if state.api_type == ApiType::Anthropic {
+        format!(
+            "{}\n{}",
+            config.system_prompt_prefix.as_deref().unwrap_or(""),
+            config.system_prompt_suffix.as_deref().unwrap_or(""),
+        )
+    } else {
+        String::new()
+    };
 
-    // 检查缓存
+    // 检查缓存：缓存 Key 包含供应商命名空间，按候选顺序查找第一个命中的供应商缓存
     if config.cache_enabled {
-        let cache_key = CacheManager::generate_key(&path, &body_bytes);
-        if let Some(cached) = state.cache.get(&cache_key) {
+        let cached = candidates.iter().find_map(|&(provider, is_cross_fallback)| {
+            let namespace = cache_namespace(provider, &requested_model, needs_conversion(provider, is_cross_fallback), &client_auth, &system_affixes);
+            state.cache.get(&CacheManager::generate_key(&namespace, &path, &body_bytes))
+        });
+        if let Some(cached) = cached {
             state.stats.record_cache_hit();
             
             let mut builder = Response::builder().status(cached.status);
@@ -192,49 +868,81 @@ async fn handle_request<R: Runtime>(
         state.stats.record_cache_miss();
     }
 
-    // 计算 input tokens
-    let input_tokens = calculate_input_tokens(&body_bytes);
-
-    let client = Client::new();
-    
-    // 获取支持当前 API 类型的供应商
-    let providers = config.get_providers_for_api_type(&state.api_type);
-    
-    if providers.is_empty() {
-        return (StatusCode::SERVICE_UNAVAILABLE, "No active providers for this API type").into_response();
-    }
-
     let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
     let cooldown = config.circuit_breaker_cooldown_seconds;
+    let max_cooldown = config.circuit_breaker_max_cooldown_seconds;
     let api_type_str = api_type_to_string(&state.api_type);
 
     // 检查是否所有供应商都在冷却中，如果是则自动解除所有冷却
-    let all_in_cooldown = providers.iter().all(|p| {
-        if let Some(last_failure) = state.health_status.get(&p.id) {
-            now - *last_failure < cooldown
-        } else {
-            false
-        }
-    });
+    // (正在半开探测的供应商不算：等待探测结果，而不是把流量全部放回仍可能故障的供应商)
+    let all_in_cooldown = all_in_cooldown(&state.health_status, candidates.iter().map(|(p, _)| *p), now, cooldown, max_cooldown);
     
-    if all_in_cooldown && !providers.is_empty() {
+    if all_in_cooldown && !candidates.is_empty() {
         println!("⚡ All providers in cooldown, resetting all cooldowns...");
-        for p in &providers {
-            state.health_status.remove(&p.id);
-            // 同时重置统计中的健康状态
-            state.stats.reset_provider_health(&p.name);
+        for (p, _) in &candidates {
+            if let Some(mut health) = state.health_status.get_mut(&p.id) {
+                health.lift_cooldown();
+            }
+            state.stats.end_cooldown(&p.name, now);
         }
     }
 
-    for provider in providers {
+    let in_cooldown = |provider_id: &String| {
+        state.health_status.get(provider_id)
+            .map(|health| health.blocks_requests(now, cooldown, max_cooldown))
+            .unwrap_or(false)
+    };
+    let incoming = IncomingRequest {
+        method,
+        headers,
+        path: path.clone(),
+        query,
+        body: body_bytes.clone(),
+    };
+    let wants_stream = request_wants_stream(&body_bytes);
+
+    let mut attempts = 0usize;
+    let mut failed_providers: Vec<String> = Vec::new();
+    // 已在对冲请求中尝试过的供应商
+    let mut skip_ids: std::collections::HashSet<String> = std::collections::HashSet::new();
+    let mut hedged = false;
+    // 是否有供应商因并发上限被跳过
+    let mut concurrency_limited = false;
+    // 所有失败的尝试是否都是上游 429，以及其中最短的重试等待时间 (秒)
+    let mut only_rate_limited = true;
+    let mut rate_limit_retry_after: Option<u64> = None;
+    for (index, &(provider, is_cross_fallback)) in candidates.iter().enumerate() {
         // Circuit Breaker Check
-        if let Some(last_failure) = state.health_status.get(&provider.id) {
-            if now - *last_failure < cooldown {
-                // 静默跳过，不输出日志避免刷屏
-                continue;
-            }
+        if in_cooldown(&provider.id) {
+            // 静默跳过，不输出日志避免刷屏
+            continue;
+        }
+        if skip_ids.contains(&provider.id) {
+            continue;
         }
 
+        // 达到最大尝试次数后放弃
+        if config.max_failover_attempts > 0 && attempts >= config.max_failover_attempts {
+            println!("⚠️ Reached max failover attempts ({}), giving up", config.max_failover_attempts);
+            break;
+        }
+        
+        // 并发限制：许可随响应一起释放 (流式响应在流结束时释放)
+        let permit = match state.concurrency.semaphore(provider) {
+            Some(semaphore) if config.queue_at_concurrency_limit => semaphore.acquire_owned().await.ok(),
+            Some(semaphore) => match semaphore.try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    println!("⏳ Provider {} at concurrency limit, trying next provider", provider.name);
+                    concurrency_limited = true;
+                    continue;
+                }
+            },
+            None => None,
+        };
+        attempts += 1;
+        claim_probe(&state.health_status, provider, now);
+
         // Emit Pending Event
         let _ = state.app.emit("gateway://provider-status", ProviderStatusEvent {
             provider_id: provider.id.clone(),
@@ -242,115 +950,200 @@ async fn handle_request<R: Runtime>(
             api_type: api_type_str.clone(),
         });
 
-        // 检查是否需要协议转换 (Claude Code 代理模式)
-        // 只对 /v1/messages 路径应用转换，其他路径直接透传
-        let is_messages_path = path.starts_with("/v1/messages");
-        let use_proxy_conversion = provider.claude_code_proxy && state.api_type == ApiType::Anthropic && is_messages_path;
+        let use_proxy_conversion = needs_conversion(provider, is_cross_fallback);
+        if is_cross_fallback {
+            println!("🛟 [{}] Trying cross-protocol fallback provider: {}", api_type_str, provider.name);
+        }
         
-        // 转换请求体和 URL (如果需要)
-        let (request_body, target_path) = if use_proxy_conversion {
-            println!("🔄 [{}] Using Claude Code proxy mode for provider: {}", api_type_str, provider.name);
-            match converter::anthropic_to_openai(&body_bytes, &provider.model_mapping) {
-                Ok(converted) => (converted, "/v1/chat/completions".to_string()),
-                Err(e) => {
-                    println!("❌ Failed to convert request: {}", e);
-                    continue;
-                }
+        let client = match state.clients.client_for(provider) {
+            Ok(client) => client,
+            Err(e) => {
+                println!("❌ {}", e);
+                failed_providers.push(provider.name.clone());
+                continue;
             }
-        } else {
-            (body_bytes.to_vec(), path.clone())
         };
-
-        // Construct target URL
-        let base = provider.base_url.trim_end_matches('/');
-        let url = format!("{}{}{}", base, target_path, query);
-        
-        println!("🔄 [{}] Forwarding to: {}", api_type_str, url);
-
-        let mut new_req = client.request(method.clone(), &url);
-        
-        // Forward headers (排除某些头)
-        for (key, value) in &headers {
-            let key_str = key.as_str();
-            // 代理模式下不转发 Anthropic 特有的头
-            if key_str == "host" || key_str == "authorization" || key_str == "content-length" {
-                continue;
+        let upstream = match build_upstream_request(&client, &incoming, provider, &state.api_type, use_proxy_conversion, &config) {
+            Ok(upstream) => upstream,
+            Err(converter::ConversionError::InvalidRequest(msg)) => {
+                // 请求本身有误，换供应商也不会成功，直接返回 400
+                println!("❌ Invalid request, not failing over: {}", msg);
+                return (StatusCode::BAD_REQUEST, msg).into_response();
             }
-            if use_proxy_conversion && (key_str == "x-api-key" || key_str == "anthropic-version" || key_str == "anthropic-beta") {
+            Err(e) => {
+                println!("❌ Failed to convert request: {}", e);
+                failed_providers.push(provider.name.clone());
                 continue;
             }
-            new_req = new_req.header(key, value);
-        }
-        
-        // Add Provider Auth
-        if !provider.api_key.is_empty() {
-            if use_proxy_conversion {
-                // 代理模式：使用 OpenAI 格式的认证
-                let auth_val = format!("Bearer {}", provider.api_key);
-                if let Ok(val) = HeaderValue::from_str(&auth_val) {
-                    new_req = new_req.header("Authorization", val);
-                }
-            } else {
-                match state.api_type {
-                    ApiType::Anthropic => {
-                        if let Ok(val) = HeaderValue::from_str(&provider.api_key) {
-                            new_req = new_req.header("x-api-key", val);
-                            new_req = new_req.header("anthropic-version", "2023-06-01");
-                        }
-                    }
-                    ApiType::OpenAIResponses | ApiType::OpenAIChat => {
-                        let auth_val = format!("Bearer {}", provider.api_key);
-                        if let Ok(val) = HeaderValue::from_str(&auth_val) {
-                            new_req = new_req.header("Authorization", val);
-                        }
-                    }
-                }
-            }
-        }
-        
-        // 设置正确的 Content-Type
-        new_req = new_req.header("Content-Type", "application/json");
-        new_req = new_req.body(request_body.clone());
+        };
 
-        match new_req.send().await {
-            Ok(resp) => {
-                let status = resp.status();
-                
-                let should_fallback = status.is_server_error() || 
-                                      status == StatusCode::UNAUTHORIZED || 
-                                      status == StatusCode::PAYMENT_REQUIRED || 
-                                      status == StatusCode::FORBIDDEN || 
-                                      status == StatusCode::GONE ||
-                                      status == StatusCode::TOO_MANY_REQUESTS;
-
-                if should_fallback && config.fallback_enabled {
-                    // 尝试读取错误响应体以获取更多信息
-                    let error_body = match resp.text().await {
-                        Ok(text) => {
-                            if text.len() > 500 {
-                                format!("{}...(truncated)", &text[..500])
-                            } else {
-                                text
-                            }
-                        }
-                        Err(_) => "(unable to read error body)".to_string()
+        // 对冲请求：非流式请求同时发往下一个健康供应商 (延迟 hedge_delay_ms 后发出)，取最先成功的响应
+        // 两个请求都计入尝试次数，剩余尝试次数不足时不对冲
+        let attempts_left = config.max_failover_attempts == 0 || attempts < config.max_failover_attempts;
+        let hedge_partner = if config.hedge && !hedged && !wants_stream && attempts_left {
+            candidates[index + 1..].iter()
+                .find(|(p, _)| !skip_ids.contains(&p.id) && !in_cooldown(&p.id))
+                .and_then(|&(partner, partner_is_fallback)| {
+                    // 对冲请求不排队：伙伴供应商已达并发上限时不对冲
+                    let partner_permit = match state.concurrency.semaphore(partner) {
+                        Some(semaphore) => Some(semaphore.try_acquire_owned().ok()?),
+                        None => None,
                     };
-                    
-                    println!("⚠️ Provider {} failed:", provider.name);
-                    println!("   URL: {}", url);
-                    println!("   Status: {}", status);
-                    println!("   Response: {}", error_body);
-                    println!("   Trying next provider...");
-                    
-                    let _ = state.app.emit("gateway://provider-status", ProviderStatusEvent {
-                        provider_id: provider.id.clone(),
-                        status: "error".to_string(),
-                        api_type: api_type_str.clone(),
-                    });
-
-                    state.health_status.insert(provider.id.clone(), now);
+                    let partner_conversion = needs_conversion(partner, partner_is_fallback);
+                    let partner_client = state.clients.client_for(partner).ok()?;
+                    build_upstream_request(&partner_client, &incoming, partner, &state.api_type, partner_conversion, &config)
+                        .ok()
+                        .map(|upstream| (partner, upstream, partner_permit))
+                })
+        } else {
+            None
+        };
 
-                    let duration = SystemTime::now().duration_since(start_time).unwrap_or_default().as_millis() as u64;
+        let (provider, url, use_proxy_conversion, captured, permit, attempt_index, send_result) = match hedge_partner {
+            Some((partner, partner_upstream, partner_permit)) => {
+                hedged = true;
+                let primary_attempt = (attempts - 1) as u32;
+                attempts += 1;
+                let partner_attempt = (attempts - 1) as u32;
+                claim_probe(&state.health_status, partner, now);
+                let _ = state.app.emit("gateway://provider-status", ProviderStatusEvent {
+                    provider_id: partner.id.clone(),
+                    status: "pending".to_string(),
+                    api_type: api_type_str.clone(),
+                });
+                println!("🏁 [{}] Hedging request between {} and {}", api_type_str, provider.name, partner.name);
+                
+                let delay = std::time::Duration::from_millis(config.hedge_delay_ms);
+                let outcome = race_hedged(upstream.builder, partner_upstream.builder, delay).await;
+                let (winner, winner_upstream, loser, loser_upstream) = if outcome.secondary_won {
+                    (partner, partner_upstream.url, provider, upstream.url)
+                } else {
+                    (provider, upstream.url, partner, partner_upstream.url)
+                };
+                let (winner_attempt, loser_attempt) = if outcome.secondary_won {
+                    (partner_attempt, primary_attempt)
+                } else {
+                    (primary_attempt, partner_attempt)
+                };
+                let winner_conversion = if outcome.secondary_won { partner_upstream.use_proxy_conversion } else { upstream.use_proxy_conversion };
+                let loser_conversion = if outcome.secondary_won { upstream.use_proxy_conversion } else { partner_upstream.use_proxy_conversion };
+                let (winner_captured, loser_captured) = if outcome.secondary_won {
+                    (partner_upstream.captured, upstream.captured)
+                } else {
+                    (upstream.captured, partner_upstream.captured)
+                };
+                // 落败一方的并发许可立即释放
+                let winner_permit = if outcome.secondary_won { partner_permit } else { permit };
+                // 落败一方若是半开探测，探测没有得出结果 (失败时下面重新记录熔断)
+                release_probe(&state.health_status, loser);
+                
+                // 记录落败的一方：被取消或同样失败 (延迟期间胜负已分、第二个请求没有发出时不记录)
+                let (loser_status, loser_error, cancelled) = match &outcome.loser_result {
+                    None => (499, "Cancelled: hedged request lost the race".to_string(), true),
+                    Some(Ok(resp)) => (resp.status().as_u16(), format!("HTTP {} (hedged attempt)", resp.status()), false),
+                    Some(Err(e)) => (502, format!("Connection failed: {}", e), false),
+                };
+                if outcome.loser_sent {
+                    println!("🏁 [{}] Hedged request: {} won, {} {}", api_type_str, winner.name, loser.name,
+                        if cancelled { "cancelled" } else { "failed" });
+                    println!("   Loser URL: {}", loser_upstream);
+                    if !cancelled {
+                        // 落败一方同样失败：不再对其故障转移，需要故障转移的错误 (5xx、连接失败等) 计入熔断
+                        skip_ids.insert(loser.id.clone());
+                        failed_providers.push(loser.name.clone());
+                        let provider_failed = match &outcome.loser_result {
+                            Some(Ok(resp)) => should_fail_over(resp.status()),
+                            _ => true,
+                        };
+                        if provider_failed {
+                            record_provider_failure(&state, loser, now, cooldown, max_cooldown);
+                        }
+                    }
+                    let duration = SystemTime::now().duration_since(start_time).unwrap_or_default().as_millis() as u64;
+                    let loser_input_tokens = count_input_tokens(loser, &input_text, &mapped_model(loser, &requested_model, loser_conversion));
+                    // 上游返回了错误响应时按供应商配置计费；被取消或连接失败的请求不计费
+                    let loser_billed = matches!(outcome.loser_result, Some(Ok(_)));
+                    let log = RequestLog {
+                        id: uuid::Uuid::new_v4().to_string(),
+                        timestamp: now,
+                        provider: loser.name.clone(),
+                        model: "unknown".to_string(),
+                        status: loser_status,
+                        duration_ms: duration,
+                        input_tokens: loser_input_tokens,
+                        output_tokens: 0,
+                        cost: if loser_billed { failed_attempt_cost(loser, loser_input_tokens) } else { 0.0 },
+                        path: path.clone(),
+                        client_agent: user_agent.clone(),
+                        api_type: api_type_str.clone(),
+                        cached: false,
+                        error_message: Some(loser_error),
+                        attempt_index: loser_attempt,
+                        failed_providers: failed_providers.clone(),
+                        request_id: request_id.to_string(),
+                        cancelled,
+                        requested_model: requested_model.clone(),
+                        mapped_model: mapped_model(loser, &requested_model, loser_conversion),
+                        captured_request: loser_captured,
+                        estimated: false,
+                    };
+                    state.stats.record_request(log);
+                    let _ = state.app.emit("gateway://provider-status", ProviderStatusEvent {
+                        provider_id: loser.id.clone(),
+                        status: if cancelled { "cancelled".to_string() } else { "error".to_string() },
+                        api_type: api_type_str.clone(),
+                    });
+                } else {
+                    // 第二个请求还没发出就已分出胜负，不算一次尝试
+                    println!("🏁 [{}] Hedged request: {} answered before {} was sent", api_type_str, winner.name, loser.name);
+                    attempts -= 1;
+                }
+                
+                (winner, winner_upstream, winner_conversion, winner_captured, winner_permit, winner_attempt, outcome.result)
+            }
+            None => {
+                let UpstreamRequest { builder, url, use_proxy_conversion, captured } = upstream;
+                let result = send_with_retry(builder, config.connection_retry_count, &provider.name).await;
+                (provider, url, use_proxy_conversion, captured, permit, (attempts - 1) as u32, result)
+            }
+        };
+        let input_tokens = count_input_tokens(provider, &input_text, &mapped_model(provider, &requested_model, use_proxy_conversion));
+
+        match send_result {
+            Ok(resp) => {
+                let status = resp.status();
+                
+                if should_fail_over(status) && config.fallback_enabled {
+                    if status == StatusCode::TOO_MANY_REQUESTS {
+                        let retry_after = upstream_retry_after_secs(resp.headers()).unwrap_or(cooldown);
+                        rate_limit_retry_after = Some(rate_limit_retry_after.map_or(retry_after, |r| r.min(retry_after)));
+                    } else {
+                        only_rate_limited = false;
+                    }
+                    
+                    // 尝试读取错误响应体以获取更多信息 (按配置脱敏或不保存)
+                    let error_body = match resp.bytes().await {
+                        Ok(bytes) => error_body_for_log(&bytes, &config),
+                        Err(_) => Some("(unable to read error body)".to_string())
+                    };
+                    
+                    println!("⚠️ Provider {} failed:", provider.name);
+                    println!("   URL: {}", url);
+                    println!("   Status: {}", status);
+                    if let Some(error_body) = &error_body {
+                        println!("   Response: {}", error_body);
+                    }
+                    println!("   Trying next provider...");
+                    
+                    let _ = state.app.emit("gateway://provider-status", ProviderStatusEvent {
+                        provider_id: provider.id.clone(),
+                        status: "error".to_string(),
+                        api_type: api_type_str.clone(),
+                    });
+
+                    record_provider_failure(&state, provider, now, cooldown, max_cooldown);
+
+                    let duration = SystemTime::now().duration_since(start_time).unwrap_or_default().as_millis() as u64;
                     let log = RequestLog {
                         id: uuid::Uuid::new_v4().to_string(),
                         timestamp: now,
@@ -360,14 +1153,28 @@ async fn handle_request<R: Runtime>(
                         duration_ms: duration,
                         input_tokens,
                         output_tokens: 0,
-                        cost: 0.0,
+                        cost: failed_attempt_cost(provider, input_tokens),
                         path: path.clone(),
                         client_agent: user_agent.clone(),
                         api_type: api_type_str.clone(),
                         cached: false,
-                        error_message: Some(format!("HTTP {} - {}", status, error_body)),
+                        error_message: Some(match &error_body {
+                            Some(error_body) => format!("HTTP {} - {}", status, error_body),
+                            None => format!("HTTP {}", status),
+                        }),
+                        attempt_index,
+                        failed_providers: failed_providers.clone(),
+                        request_id: request_id.to_string(),
+                        cancelled: false,
+                        requested_model: requested_model.clone(),
+                        mapped_model: mapped_model(provider, &requested_model, use_proxy_conversion),
+                        captured_request: captured.clone(),
+                        estimated: false,
                     };
                     state.stats.record_request(log);
+                    // 失败本身已进入冷却，这里只更新降级状态
+                    demote_if_unreliable(&state, provider, &config, &api_type_str);
+                    failed_providers.push(provider.name.clone());
 
                     continue;
                 }
@@ -378,34 +1185,53 @@ async fn handle_request<R: Runtime>(
                     api_type: api_type_str.clone(),
                 });
 
-                state.health_status.remove(&provider.id);
-
                 let duration = SystemTime::now().duration_since(start_time).unwrap_or_default().as_millis() as u64;
-                let output_tokens = 0; // TODO: parse from response
-                let cost = calculate_cost(input_tokens, output_tokens, provider.input_price_per_1k, provider.output_price_per_1k);
+                // 输出 token 在读取响应体后回填：非流式解析响应中的 usage，流式由 StreamUsage 在流结束时处理
+                let cost = calculate_cost(input_tokens, 0, provider.input_price_per_1k, provider.output_price_per_1k);
 
                 let log = RequestLog {
-                    id: uuid::Uuid::new_v4().to_string(),
+                    id: request_id.to_string(),
                     timestamp: now,
                     provider: provider.name.clone(),
                     model: "unknown".to_string(),
                     status: status.as_u16(),
                     duration_ms: duration,
                     input_tokens,
-                    output_tokens,
+                    output_tokens: 0,
                     cost,
                     path: path.clone(),
                     client_agent: user_agent.clone(),
                     api_type: api_type_str.clone(),
                     cached: false,
                     error_message: None,
+                    attempt_index,
+                    failed_providers: failed_providers.clone(),
+                    request_id: request_id.to_string(),
+                    cancelled: false,
+                    requested_model: requested_model.clone(),
+                    mapped_model: mapped_model(provider, &requested_model, use_proxy_conversion),
+                    captured_request: captured.clone(),
+                    estimated: false,
                 };
                 
                 state.stats.record_request(log);
+                
+                // 本次成功，但滚动成功率过低 (例如间歇性失败)：仍然冷却该供应商
+                if demote_if_unreliable(&state, provider, &config, &api_type_str) {
+                    record_provider_failure(&state, provider, now, cooldown, max_cooldown);
+                } else if state.health_status.remove(&provider.id).is_some() {
+                    state.stats.end_cooldown(&provider.name, now);
+                }
 
+                // 按配置过滤转发给客户端的响应头 (逐跳头总是移除)
+                let forward_header = |name: &axum::http::HeaderName| {
+                    !HOP_BY_HOP_HEADERS.contains(&name.as_str()) && config.forwards_response_header(name.as_str())
+                };
+                
                 // 收集响应头用于缓存
                 let response_headers: Vec<(String, String)> = resp.headers()
                     .iter()
+                    .filter(|(k, _)| forward_header(k))
                     .filter_map(|(k, v)| {
                         v.to_str().ok().map(|v| (k.to_string(), v.to_string()))
                     })
@@ -414,23 +1240,104 @@ async fn handle_request<R: Runtime>(
                 let mut builder = Response::builder().status(status);
                 
                 if let Some(headers_mut) = builder.headers_mut() {
-                    for (k, v) in resp.headers() {
+                    for (k, v) in resp.headers().iter().filter(|(k, _)| forward_header(k)) {
                         headers_mut.insert(k, v.clone());
                     }
+                    // 代理转换模式下上游不返回 Anthropic 限流头，按上游限流信息合成
+                    if use_proxy_conversion && state.api_type == ApiType::Anthropic {
+                        synthesize_anthropic_ratelimit_headers(resp.headers(), status, headers_mut);
+                    }
                 }
                 
                 // 对于非流式响应，尝试缓存
                 let content_type = resp.headers()
                     .get("content-type")
                     .and_then(|v| v.to_str().ok())
-                    .unwrap_or("");
+                    .unwrap_or("")
+                    .to_string();
+                let is_streaming = is_streaming_response(resp.headers(), provider);
                 
-                if config.cache_enabled && !content_type.contains("stream") && status.is_success() {
+                // 客户端请求流式，但上游返回了完整 JSON (上游忽略 stream，或供应商禁用了流式)：转换后回放为 SSE
+                let replay_as_sse = use_proxy_conversion || provider.disable_streaming;
+                if replay_as_sse && wants_stream
+                    && !provider.force_streaming && content_type.contains("json") && status.is_success() {
+                    println!("🔁 [{}] Provider {} returned non-stream response, replaying as SSE", api_type_str, provider.name);
+                    let model_name = request_model(&body_bytes).unwrap_or_else(|| "unknown".to_string());
+                    let bytes = resp.bytes().await;
+                    if let Ok(bytes) = &bytes {
+                        backfill_response_usage(&state.stats, request_id, provider, input_tokens, bytes);
+                    }
+                    let events = match bytes {
+                        Ok(bytes) => non_stream_sse_events(&state.api_type, use_proxy_conversion, provider.responses_upstream, &bytes, &model_name, provider.response_content_path()),
+                        Err(e) => Err(format!("Failed to read response body: {}", e)),
+                    };
+                    return match events {
+                        Ok(events) => {
+                            let sse: String = events.iter().map(|e| format!("{}\n\n", e)).collect();
+                            if let Some(headers_mut) = builder.headers_mut() {
+                                headers_mut.remove(axum::http::header::CONTENT_LENGTH);
+                                headers_mut.insert(
+                                    axum::http::header::CONTENT_TYPE,
+                                    HeaderValue::from_static("text/event-stream; charset=utf-8")
+                                );
+                            }
+                            builder.body(Body::from(sse)).unwrap_or_default()
+                        }
+                        Err(e) => {
+                            println!("❌ Failed to convert non-stream response: {}", e);
+                            (StatusCode::BAD_GATEWAY, e).into_response()
+                        }
+                    };
+                }
+                
+                // 代理转换模式的非流式响应：将 OpenAI Chat JSON 转换为客户端协议 (Anthropic message / Responses) 后返回
+                // (缓存中也保存转换后的内容)
+                if use_proxy_conversion && !is_streaming && content_type.contains("json") && status.is_success() {
+                    let model_name = request_model(&body_bytes).unwrap_or_else(|| "unknown".to_string());
+                    let converted = match resp.bytes().await {
+                        Ok(bytes) => {
+                            backfill_response_usage(&state.stats, request_id, provider, input_tokens, &bytes);
+                            match state.api_type {
+                                ApiType::OpenAIResponses => converter::chat_response_to_responses(&bytes, &model_name, provider.response_content_path()),
+                                _ if provider.responses_upstream => converter::responses_response_to_anthropic(&bytes, &model_name),
+                                _ => converter::openai_response_to_anthropic(&bytes, &model_name, provider.response_content_path()),
+                            }
+                        }
+                        Err(e) => Err(format!("Failed to read response body: {}", e)),
+                    };
+                    let converted = match converted {
+                        Ok(converted) => converted,
+                        Err(e) => {
+                            println!("❌ Failed to convert non-stream response: {}", e);
+                            return (StatusCode::BAD_GATEWAY, e).into_response();
+                        }
+                    };
+                    if let Some(headers_mut) = builder.headers_mut() {
+                        headers_mut.remove(axum::http::header::CONTENT_LENGTH);
+                        headers_mut.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                    }
+                    if config.cache_enabled {
+                        let cached_headers: Vec<(String, String)> = response_headers.into_iter()
+                            .filter(|(k, _)| !k.eq_ignore_ascii_case("content-length") && !k.eq_ignore_ascii_case("content-type"))
+                            .chain(std::iter::once(("content-type".to_string(), "application/json".to_string())))
+                            .collect();
+                        let namespace = cache_namespace(provider, &requested_model, use_proxy_conversion, &client_auth, &system_affixes);
+                        let cache_key = CacheManager::generate_key(&namespace, &path, &body_bytes);
+                        let ttl = config.cache_ttl_for(&path, &state.api_type);
+                        state.cache.set(cache_key, converted.clone(), status.as_u16(), cached_headers, ttl);
+                    }
+                    return builder.body(Body::from(converted)).unwrap_or_default();
+                }
+                
+                if config.cache_enabled && !is_streaming && status.is_success() {
                     // 缓冲响应体用于缓存
                     match resp.bytes().await {
                         Ok(bytes) => {
-                            let cache_key = CacheManager::generate_key(&path, &body_bytes);
-                            state.cache.set(cache_key, bytes.to_vec(), status.as_u16(), response_headers);
+                            backfill_response_usage(&state.stats, request_id, provider, input_tokens, &bytes);
+                            let namespace = cache_namespace(provider, &requested_model, use_proxy_conversion, &client_auth, &system_affixes);
+                            let cache_key = CacheManager::generate_key(&namespace, &path, &body_bytes);
+                            let ttl = config.cache_ttl_for(&path, &state.api_type);
+                            state.cache.set(cache_key, bytes.to_vec(), status.as_u16(), response_headers, ttl);
                             return builder.body(Body::from(bytes)).unwrap_or_default();
                         }
                         Err(_) => {
@@ -441,15 +1348,42 @@ async fn handle_request<R: Runtime>(
                 } else {
                     // 流式响应处理
                     if use_proxy_conversion {
-                        // Claude Code 代理模式：需要将 OpenAI SSE 转换为 Anthropic SSE
-                        let message_id = format!("msg_{}", uuid::Uuid::new_v4().to_string().replace("-", "")[..24].to_string());
-                        let model_name = "claude-3-5-sonnet-20241022".to_string();
+                        // 代理转换模式：需要将 OpenAI SSE 转换为 Anthropic SSE (Claude Code) 或 Responses SSE (CodeX)
+                        // 上游为 Responses API 时将 Responses SSE 转换为 Anthropic SSE
+                        let content_path = provider.response_content_path();
+                        let mut sse_converter = if state.api_type == ApiType::OpenAIResponses {
+                            let model_name = request_model(&body_bytes).unwrap_or_else(|| "unknown".to_string());
+                            let response_id = format!("resp_{}", uuid::Uuid::new_v4().simple());
+                            ProxySseConverter::Responses(converter::ChatToResponsesSseConverter::new(&response_id, &model_name)
+                                .with_content_path(content_path))
+                        } else if provider.responses_upstream {
+                            let message_id = format!("msg_{}", &uuid::Uuid::new_v4().simple().to_string()[..24]);
+                            let model_name = request_model(&body_bytes).unwrap_or_else(|| "unknown".to_string());
+                            ProxySseConverter::AnthropicFromResponses(converter::ResponsesSseConverter::new(&message_id, &model_name))
+                        } else {
+                            let message_id = format!("msg_{}", uuid::Uuid::new_v4().to_string().replace("-", "")[..24].to_string());
+                            let model_name = "claude-3-5-sonnet-20241022".to_string();
+                            ProxySseConverter::Anthropic(converter::OpenAISseConverter::new(&message_id, &model_name)
+                                .with_content_path(content_path))
+                        };
                         
                         let stream = resp.bytes_stream();
+                        let app = state.app.clone();
+                        let api_type_str = api_type_str.clone();
+                        let request_id = request_id.to_string();
+                        let mut usage = StreamUsage::new(&state.stats, &request_id, provider, input_tokens, config.estimate_streaming_output_tokens);
+                        let chars_per_token = provider.effective_chars_per_token();
                         let converted_stream = async_stream::stream! {
                             let mut buffer = String::new();
-                            let mut is_first = true;
-                            let mut stream_ended = false;
+                            let mut delta_count = 0u32;
+                            let emit_usage = |output_chars: usize| {
+                                let _ = app.emit("gateway://token-usage", TokenUsageEvent {
+                                    request_id: request_id.clone(),
+                                    api_type: api_type_str.clone(),
+                                    input_tokens,
+                                    output_tokens: estimate_tokens_from_chars(output_chars, chars_per_token),
+                                });
+                            };
                             
                             tokio::pin!(stream);
                             
@@ -469,21 +1403,18 @@ async fn handle_request<R: Runtime>(
                                                 continue;
                                             }
                                             
-                                            // 转换 OpenAI SSE 到 Anthropic SSE
-                                            let converted_events = converter::openai_sse_to_anthropic(line, &message_id, &model_name, is_first);
-                                            
-                                            // 只有在有实际事件输出时才标记为非首次
-                                            if !converted_events.is_empty() && is_first {
-                                                is_first = false;
+                                            // 累计输出文本，定期发送实时用量
+                                            if usage.observe(line) {
+                                                delta_count += 1;
+                                                if delta_count >= TOKEN_USAGE_EMIT_INTERVAL {
+                                                    delta_count = 0;
+                                                    emit_usage(usage.output_chars);
+                                                }
                                             }
                                             
-                                            for event in &converted_events {
+                                            // 转换 OpenAI SSE 到客户端协议的 SSE
+                                            for event in sse_converter.convert_line(line) {
                                                 yield Ok::<_, std::io::Error>(bytes::Bytes::from(format!("{}\n\n", event)));
-                                                
-                                                // 检查是否是结束事件
-                                                if event.contains("message_stop") {
-                                                    stream_ended = true;
-                                                }
                                             }
                                         }
                                     }
@@ -496,30 +1427,26 @@ async fn handle_request<R: Runtime>(
                             
                             // 处理 buffer 中剩余的数据
                             if !buffer.trim().is_empty() {
-                                let converted_events = converter::openai_sse_to_anthropic(buffer.trim(), &message_id, &model_name, is_first);
-                                for event in &converted_events {
+                                usage.observe(buffer.trim());
+                                for event in sse_converter.convert_line(buffer.trim()) {
                                     yield Ok::<_, std::io::Error>(bytes::Bytes::from(format!("{}\n\n", event)));
-                                    if event.contains("message_stop") {
-                                        stream_ended = true;
-                                    }
                                 }
                             }
                             
-                            // 如果流结束但没有收到正常的结束事件，发送结束序列
-                            if !stream_ended {
-                                yield Ok::<_, std::io::Error>(bytes::Bytes::from(format!(
-                                    "event: content_block_stop\ndata: {{\"type\":\"content_block_stop\",\"index\":0}}\n\n"
-                                )));
-                                yield Ok::<_, std::io::Error>(bytes::Bytes::from(format!(
-                                    "event: message_delta\ndata: {{\"type\":\"message_delta\",\"delta\":{{\"stop_reason\":\"end_turn\",\"stop_sequence\":null}},\"usage\":{{\"output_tokens\":0}}}}\n\n"
-                                )));
-                                yield Ok::<_, std::io::Error>(bytes::Bytes::from(format!(
-                                    "event: message_stop\ndata: {{\"type\":\"message_stop\"}}\n\n"
-                                )));
+                            // 流结束时发送最终用量，并回填请求日志
+                            emit_usage(usage.output_chars);
+                            usage.finish();
+                            
+                            // 如果流结束但没有收到正常的结束事件，发送结束序列 (必要时补发 message_start / response.created)
+                            for event in sse_converter.finish() {
+                                yield Ok::<_, std::io::Error>(bytes::Bytes::from(format!("{}\n\n", event)));
+                            }
+                            if let Some(len) = sse_converter.interrupted_tool_call() {
+                                eprintln!("Upstream stream ended in the middle of a tool call ({} bytes of arguments)", len);
                             }
                         };
                         
-                        // 设置 Anthropic SSE content-type
+                        // 设置 SSE content-type
                         if let Some(headers_mut) = builder.headers_mut() {
                             headers_mut.insert(
                                 axum::http::header::CONTENT_TYPE,
@@ -527,16 +1454,60 @@ async fn handle_request<R: Runtime>(
                             );
                         }
                         
-                        let body = Body::from_stream(converted_stream);
+                        let body = Body::from_stream(hold_permit(converted_stream, permit));
                         return builder.body(body).unwrap_or_default();
                     } else {
                         // 非代理模式：直接透传
-                        let body = Body::from_stream(resp.bytes_stream());
+                        if !is_streaming {
+                            // JSON 响应先缓冲以读取 usage，其他内容直接流式透传
+                            if content_type.contains("json") {
+                                return match resp.bytes().await {
+                                    Ok(bytes) => {
+                                        backfill_response_usage(&state.stats, request_id, provider, input_tokens, &bytes);
+                                        builder.body(Body::from(bytes)).unwrap_or_default()
+                                    }
+                                    Err(e) => {
+                                        println!("❌ Failed to read response body: {}", e);
+                                        (StatusCode::BAD_GATEWAY, format!("Failed to read response body: {}", e)).into_response()
+                                    }
+                                };
+                            }
+                            let body = Body::from_stream(hold_permit(resp.bytes_stream(), permit));
+                            return builder.body(body).unwrap_or_default();
+                        }
+                        
+                        // 流式透传：原样转发，同时扫描 SSE 行统计输出用量
+                        let stream = resp.bytes_stream();
+                        let mut usage = StreamUsage::new(&state.stats, request_id, provider, input_tokens, config.estimate_streaming_output_tokens);
+                        let passthrough_stream = async_stream::stream! {
+                            let mut buffer = String::new();
+                            tokio::pin!(stream);
+                            while let Some(chunk_result) = futures::StreamExt::next(&mut stream).await {
+                                match chunk_result {
+                                    Ok(chunk) => {
+                                        buffer.push_str(&String::from_utf8_lossy(&chunk));
+                                        while let Some(pos) = buffer.find('\n') {
+                                            usage.observe(buffer[..pos].trim());
+                                            buffer.drain(..=pos);
+                                        }
+                                        yield Ok::<_, std::io::Error>(chunk);
+                                    }
+                                    Err(e) => {
+                                        eprintln!("Stream error: {}", e);
+                                        break;
+                                    }
+                                }
+                            }
+                            usage.observe(buffer.trim());
+                            usage.finish();
+                        };
+                        let body = Body::from_stream(hold_permit(passthrough_stream, permit));
                         return builder.body(body).unwrap_or_default();
                     }
                 }
             }
             Err(e) => {
+                only_rate_limited = false;
                 println!("❌ Provider {} connection failed:", provider.name);
                 println!("   URL: {}", url);
                 println!("   Error: {}", e);
@@ -548,7 +1519,7 @@ async fn handle_request<R: Runtime>(
                     api_type: api_type_str.clone(),
                 });
 
-                state.health_status.insert(provider.id.clone(), now);
+                record_provider_failure(&state, provider, now, cooldown, max_cooldown);
 
                 let duration = SystemTime::now().duration_since(start_time).unwrap_or_default().as_millis() as u64;
                 let log = RequestLog {
@@ -566,8 +1537,18 @@ async fn handle_request<R: Runtime>(
                     api_type: api_type_str.clone(),
                     cached: false,
                     error_message: Some(format!("Connection failed: {}", e)),
+                    attempt_index,
+                    failed_providers: failed_providers.clone(),
+                    request_id: request_id.to_string(),
+                    cancelled: false,
+                    requested_model: requested_model.clone(),
+                    mapped_model: mapped_model(provider, &requested_model, use_proxy_conversion),
+                    captured_request: captured.clone(),
+                    estimated: false,
                 };
                 state.stats.record_request(log);
+                demote_if_unreliable(&state, provider, &config, &api_type_str);
+                failed_providers.push(provider.name.clone());
 
                 if !config.fallback_enabled {
                     return (StatusCode::BAD_GATEWAY, format!("Provider {} failed: {}", provider.name, e)).into_response();
@@ -576,34 +1557,739 @@ async fn handle_request<R: Runtime>(
         }
     }
 
+    if attempts == 0 && concurrency_limited {
+        println!("⏳ All available providers are at their concurrency limit for {}", path);
+        return (StatusCode::TOO_MANY_REQUESTS, "All providers are at their concurrency limit").into_response();
+    }
+    // 所有尝试都被上游限流：返回 429 和重试时间，让客户端自行退避
+    if let (true, Some(retry_after)) = (only_rate_limited, rate_limit_retry_after) {
+        println!("⏳ All providers rate limited for {}, retry after {}s", path, retry_after);
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, "All providers are rate limited").into_response();
+        let headers = response.headers_mut();
+        headers.insert(axum::http::header::RETRY_AFTER, HeaderValue::from(retry_after));
+        if state.api_type == ApiType::Anthropic {
+            headers.insert("anthropic-ratelimit-requests-remaining", HeaderValue::from_static("0"));
+            if let Ok(val) = HeaderValue::from_str(&reset_timestamp(retry_after)) {
+                headers.insert("anthropic-ratelimit-requests-reset", val);
+            }
+        }
+        return response;
+    }
     println!("❌ All providers failed for {}", path);
     (StatusCode::BAD_GATEWAY, "All providers failed").into_response()
 }
 
-fn calculate_input_tokens(body: &[u8]) -> u32 {
+/// 从上游响应头读取重试等待时间 (秒)：retry-after，其次是 OpenAI 的 x-ratelimit-reset-requests
+fn upstream_retry_after_secs(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    header("retry-after")
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .or_else(|| header("x-ratelimit-reset-requests").and_then(parse_openai_duration).map(|d| d.as_secs_f64().ceil() as u64))
+}
+
+/// 解析 OpenAI 限流头中的时长 (如 "1s"、"6m0s"、"20ms"、"1h2m3.5s")
+fn parse_openai_duration(value: &str) -> Option<std::time::Duration> {
+    let mut total = 0.0;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+        let unit_len = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let secs = match &rest[..unit_len] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += number * secs;
+        rest = &rest[unit_len..];
+    }
+    Some(std::time::Duration::from_secs_f64(total))
+}
+
+/// 距现在 secs 秒后的 RFC 3339 时间 (Anthropic 限流头的 reset 格式)
+fn reset_timestamp(secs: u64) -> String {
+    (chrono::Utc::now() + chrono::Duration::seconds(secs as i64)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+/// 将上游 (OpenAI 格式) 的限流头转换为 Anthropic 限流头
+/// 这些值是网关根据上游信息推算的近似值，仅用于让客户端 (如 Claude Code) 感知背压并自行节流：
+/// - x-ratelimit-{limit,remaining,reset}-{requests,tokens} -> anthropic-ratelimit-{requests,tokens}-{limit,remaining,reset}
+/// - 上游返回 429 时补充 requests-remaining = 0，并在缺少 retry-after 时按 reset 时长推算
+fn synthesize_anthropic_ratelimit_headers(upstream: &reqwest::header::HeaderMap, status: StatusCode, headers: &mut HeaderMap) {
+    let header = |name: &str| upstream.get(name).and_then(|v| v.to_str().ok()).map(|v| v.trim().to_string());
+    for kind in ["requests", "tokens"] {
+        for field in ["limit", "remaining"] {
+            if let Some(val) = header(&format!("x-ratelimit-{}-{}", field, kind)).and_then(|v| HeaderValue::from_str(&v).ok()) {
+                if let Ok(name) = axum::http::HeaderName::try_from(format!("anthropic-ratelimit-{}-{}", kind, field)) {
+                    headers.insert(name, val);
+                }
+            }
+        }
+        let reset = header(&format!("x-ratelimit-reset-{}", kind))
+            .and_then(|v| parse_openai_duration(&v))
+            .and_then(|d| HeaderValue::from_str(&reset_timestamp(d.as_secs_f64().ceil() as u64)).ok());
+        if let Some(val) = reset {
+            if let Ok(name) = axum::http::HeaderName::try_from(format!("anthropic-ratelimit-{}-reset", kind)) {
+                headers.insert(name, val);
+            }
+        }
+    }
+    
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        headers.insert("anthropic-ratelimit-requests-remaining", HeaderValue::from_static("0"));
+        if !headers.contains_key(axum::http::header::RETRY_AFTER) {
+            if let Some(retry_after) = upstream_retry_after_secs(upstream) {
+                headers.insert(axum::http::header::RETRY_AFTER, HeaderValue::from(retry_after));
+            }
+        }
+    }
+}
+
+/// 包装响应体流，在流结束 (或客户端断开导致流被丢弃) 时才释放并发许可
+fn hold_permit<S: futures::Stream>(stream: S, permit: Option<OwnedSemaphorePermit>) -> impl futures::Stream<Item = S::Item> {
+    futures::StreamExt::map(stream, move |item| {
+        let _permit = &permit;
+        item
+    })
+}
+
+/// 按字符数 (不是 UTF-8 字节数) 估算 token 数
+fn estimate_tokens_from_chars(chars: usize, chars_per_token: f64) -> u32 {
+    (chars as f64 / chars_per_token) as u32
+}
+
+/// 流式响应的输出用量：累计输出文本并记录上游报告的 usage，流结束时回填请求日志
+struct StreamUsage {
+    stats: Arc<StatsManager>,
+    log_id: String,
+    input_tokens: u32,
+    input_price: f64,
+    output_price: f64,
+    estimate: bool,
+    chars_per_token: f64,
+    output_chars: usize,
+    reported_output_tokens: Option<u32>,
+}
+
+impl StreamUsage {
+    fn new(stats: &Arc<StatsManager>, log_id: &str, provider: &Provider, input_tokens: u32, estimate: bool) -> Self {
+        Self {
+            stats: stats.clone(),
+            log_id: log_id.to_string(),
+            input_tokens,
+            input_price: provider.input_price_per_1k,
+            output_price: provider.output_price_per_1k,
+            estimate,
+            chars_per_token: provider.effective_chars_per_token(),
+            output_chars: 0,
+            reported_output_tokens: None,
+        }
+    }
+    
+    /// 处理一行 SSE，返回该行是否包含输出文本
+    fn observe(&mut self, line: &str) -> bool {
+        if let Some(tokens) = converter::sse_output_tokens(line) {
+            self.reported_output_tokens = Some(tokens);
+        }
+        match converter::sse_delta_text(line) {
+            Some(text) => {
+                self.output_chars += text.chars().count();
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// 流结束：回填上游报告的输出 token 数；未返回 usage 时按输出文本估算
+    fn finish(&self) {
+        let (output_tokens, estimated) = match self.reported_output_tokens {
+            Some(tokens) => (tokens, false),
+            None if self.estimate => (estimate_tokens_from_chars(self.output_chars, self.chars_per_token), true),
+            None => return,
+        };
+        let cost = calculate_cost(self.input_tokens, output_tokens, self.input_price, self.output_price);
+        self.stats.backfill_output_usage(&self.log_id, output_tokens, cost, estimated);
+    }
+}
+
+/// 非流式响应：按响应体中的 usage 回填请求日志的输出 token 数和费用
+fn backfill_response_usage(stats: &StatsManager, log_id: &str, provider: &Provider, input_tokens: u32, body: &[u8]) {
+    if let Some(output_tokens) = converter::response_output_tokens(body) {
+        let cost = calculate_cost(input_tokens, output_tokens, provider.input_price_per_1k, provider.output_price_per_1k);
+        stats.backfill_output_usage(log_id, output_tokens, cost, false);
+    }
+}
+
+/// 客户端请求的原始信息 (用于为各供应商构建上游请求)
+struct IncomingRequest {
+    method: Method,
+    headers: HeaderMap,
+    path: String,
+    query: String,
+    body: bytes::Bytes,
+}
+
+/// 代理转换模式的流式转换器：上游 Chat Completions SSE (或 Responses SSE) -> 客户端协议的 SSE
+/// 在整个流中维护内容块/输出项等状态
+enum ProxySseConverter {
+    Anthropic(converter::OpenAISseConverter),
+    Responses(converter::ChatToResponsesSseConverter),
+    // 上游为 Responses API (responses_upstream)：Responses SSE -> Anthropic SSE
+    AnthropicFromResponses(converter::ResponsesSseConverter),
+}
+
+impl ProxySseConverter {
+    fn convert_line(&mut self, line: &str) -> Vec<String> {
+        match self {
+            ProxySseConverter::Anthropic(converter) => converter.convert_line(line),
+            ProxySseConverter::Responses(converter) => converter.convert_line(line),
+            ProxySseConverter::AnthropicFromResponses(converter) => converter.convert_line(line),
+        }
+    }
+    
+    fn finish(&mut self) -> Vec<String> {
+        match self {
+            ProxySseConverter::Anthropic(converter) => converter.finish(),
+            ProxySseConverter::Responses(converter) => converter.finish(),
+            ProxySseConverter::AnthropicFromResponses(converter) => converter.finish(),
+        }
+    }
+    
+    /// 流在工具调用中途中断时已收到的参数字节数
+    fn interrupted_tool_call(&self) -> Option<usize> {
+        match self {
+            ProxySseConverter::Anthropic(converter) => converter.interrupted_tool_call(),
+            ProxySseConverter::Responses(converter) => converter.interrupted_tool_call(),
+            ProxySseConverter::AnthropicFromResponses(converter) => converter.interrupted_tool_call(),
+        }
+    }
+}
+
+/// 已构建好的上游请求
+struct UpstreamRequest {
+    builder: reqwest::RequestBuilder,
+    url: String,
+    use_proxy_conversion: bool,
+    // 开启 debug_capture 时记录的请求 (写入请求日志)
+    captured: Option<CapturedRequest>,
+}
+
+/// 为指定供应商构建上游请求：协议转换、目标 URL、转发请求头和认证
+fn build_upstream_request(
+    client: &Client,
+    incoming: &IncomingRequest,
+    provider: &Provider,
+    api_type: &ApiType,
+    use_proxy_conversion: bool,
+    config: &GatewayConfig,
+) -> Result<UpstreamRequest, converter::ConversionError> {
+    let api_type_str = api_type_to_string(api_type);
+    let capture = config.debug_capture;
+    
+    // 转换请求体和 URL (如果需要)
+    let (request_body, target_path) = if use_proxy_conversion && *api_type == ApiType::OpenAIResponses {
+        println!("🔄 [{}] Using Responses proxy mode for provider: {}", api_type_str, provider.name);
+        let converted = converter::responses_to_chat(&incoming.body, &provider.conversion_options())?;
+        (converted, "/v1/chat/completions".to_string())
+    } else if use_proxy_conversion {
+        println!("🔄 [{}] Using Claude Code proxy mode for provider: {}", api_type_str, provider.name);
+        let mut options = provider.conversion_options();
+        options.system_prompt_prefix = config.system_prompt_prefix.clone();
+        options.system_prompt_suffix = config.system_prompt_suffix.clone();
+        let (converted, target_path) = if provider.responses_upstream {
+            (converter::anthropic_to_responses(&incoming.body, &options)?, "/v1/responses")
+        } else {
+            (converter::anthropic_to_openai(&incoming.body, &options)?, "/v1/chat/completions")
+        };
+        if converted.dropped_stop_sequences > 0 {
+            println!("⚠️ [{}] Dropped {} stop_sequences beyond the limit of provider: {}", api_type_str, converted.dropped_stop_sequences, provider.name);
+        }
+        (converted.body, target_path.to_string())
+    } else if *api_type == ApiType::Anthropic {
+        (prepare_native_anthropic_body(&incoming.body, config), incoming.path.clone())
+    } else {
+        (incoming.body.to_vec(), incoming.path.clone())
+    };
+    
+    // 供应商禁用了流式：改写为非流式请求，响应由调用方缓冲后回放
+    let request_body = if provider.disable_streaming {
+        disable_stream(request_body)
+    } else {
+        request_body
+    };
+
+    // Construct target URL
+    let base = provider.base_url.trim_end_matches('/');
+    let url = format!("{}{}{}", base, target_path, incoming.query);
+    
+    println!("🔄 [{}] Forwarding to: {}", api_type_str, url);
+
+    let mut new_req = client.request(incoming.method.clone(), &url);
+    // 调试捕获时记录实际发出的请求头 (认证头脱敏)
+    let mut captured_headers: Vec<(String, String)> = Vec::new();
+    
+    // Forward headers (排除某些头)
+    for (key, value) in &incoming.headers {
+        let key_str = key.as_str();
+        // 代理模式下不转发 Anthropic 特有的头
+        if key_str == "host" || key_str == "authorization" || key_str == "content-length" || key_str == PROVIDER_TAG_HEADER {
+            continue;
+        }
+        if use_proxy_conversion && (key_str == "x-api-key" || key_str == "anthropic-version" || key_str == "anthropic-beta") {
+            continue;
+        }
+        // 供应商自定义了认证头时，不转发客户端的同名头
+        if provider.auth_header_name.as_deref().is_some_and(|name| key_str.eq_ignore_ascii_case(name.trim())) {
+            continue;
+        }
+        if capture {
+            let value = if key_str == "x-api-key" {
+                REDACTED_API_KEY.to_string()
+            } else {
+                value.to_str().unwrap_or_default().to_string()
+            };
+            captured_headers.push((key_str.to_string(), value));
+        }
+        new_req = new_req.header(key, value);
+    }
+    
+    // Add Provider Auth
+    let api_key = provider.resolved_api_key();
+    if !api_key.is_empty() {
+        // 认证头名称和格式：供应商自定义，或按协议默认 (代理模式使用 OpenAI 格式的认证)
+        let (header_name, header_format) = provider.auth_header(api_type, use_proxy_conversion);
+        let auth_val = header_format.replace("{key}", &api_key);
+        if let (Ok(name), Ok(val)) = (HeaderName::from_bytes(header_name.as_bytes()), HeaderValue::from_str(&auth_val)) {
+            new_req = new_req.header(name, val);
+            captured_headers.push((header_name.to_string(), header_format.replace("{key}", REDACTED_API_KEY)));
+        }
+        if *api_type == ApiType::Anthropic && !use_proxy_conversion {
+            new_req = new_req.header("anthropic-version", "2023-06-01");
+            captured_headers.push(("anthropic-version".to_string(), "2023-06-01".to_string()));
+        }
+    }
+    
+    // 设置正确的 Content-Type
+    new_req = new_req.header("Content-Type", "application/json");
+    let captured = capture.then(|| {
+        captured_headers.push(("Content-Type".to_string(), "application/json".to_string()));
+        CapturedRequest {
+            method: incoming.method.to_string(),
+            url: url.clone(),
+            headers: captured_headers,
+            body: String::from_utf8_lossy(&request_body).into_owned(),
+            client_path: format!("{}{}", incoming.path, incoming.query),
+            client_headers: incoming.headers
+                .iter()
+                .filter(|(k, _)| !matches!(k.as_str(), "host" | "authorization" | "x-api-key" | "content-length"))
+                .filter_map(|(k, v)| v.to_str().ok().map(|v| (k.to_string(), v.to_string())))
+                .collect(),
+            client_body: String::from_utf8_lossy(&incoming.body).into_owned(),
+        }
+    });
+    new_req = new_req.body(request_body);
+
+    Ok(UpstreamRequest {
+        builder: new_req,
+        url,
+        use_proxy_conversion,
+        captured,
+    })
+}
+
+/// 发送上游请求，连接错误 (DNS/TLS/拒绝连接，请求尚未到达上游) 时立即重试同一供应商
+/// HTTP 状态错误不重试，由调用方按故障转移处理
+async fn send_with_retry(
+    builder: reqwest::RequestBuilder,
+    retries: u32,
+    provider_name: &str,
+) -> reqwest::Result<reqwest::Response> {
+    let mut builder = builder;
+    let mut remaining = retries;
+    loop {
+        // 请求体为内存数据，总能克隆；克隆失败时不重试
+        let retry_builder = if remaining > 0 { builder.try_clone() } else { None };
+        match builder.send().await {
+            Err(e) if e.is_connect() && retry_builder.is_some() => {
+                println!("🔁 Connection to {} failed ({}), retrying...", provider_name, e);
+                remaining -= 1;
+                builder = retry_builder.unwrap();
+            }
+            result => return result,
+        }
+    }
+}
+
+/// 上游响应状态是否应触发故障转移 (并计入熔断)：5xx、认证/计费失败、资源已下线和限流
+fn should_fail_over(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::UNAUTHORIZED
+        || status == StatusCode::PAYMENT_REQUIRED
+        || status == StatusCode::FORBIDDEN
+        || status == StatusCode::GONE
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// 对冲请求结果
+struct HedgeOutcome {
+    /// 是否由第二个供应商胜出
+    secondary_won: bool,
+    /// 胜出方的结果
+    result: reqwest::Result<reqwest::Response>,
+    /// 落败方的结果 (None 表示被取消)
+    loser_result: Option<reqwest::Result<reqwest::Response>>,
+    /// 落败方的请求是否已发出 (第二个请求在延迟期间被取消时为 false)
+    loser_sent: bool,
+}
+
+/// 同时发送两个请求 (第二个延迟 delay 后发出)，返回最先成功的一个，另一个被取消
+/// 两者都失败时返回主请求的结果
+async fn race_hedged(
+    primary: reqwest::RequestBuilder,
+    secondary: reqwest::RequestBuilder,
+    delay: std::time::Duration,
+) -> HedgeOutcome {
+    let is_success = |r: &reqwest::Result<reqwest::Response>| {
+        r.as_ref().map(|resp| resp.status().is_success()).unwrap_or(false)
+    };
+    
+    let secondary_sent = AtomicBool::new(false);
+    let primary = Box::pin(primary.send());
+    let secondary = Box::pin(async {
+        tokio::time::sleep(delay).await;
+        secondary_sent.store(true, Ordering::Relaxed);
+        secondary.send().await
+    });
+    
+    let first = futures::future::select(primary, secondary).await;
+    match first {
+        Either::Left((primary_result, secondary)) => {
+            if is_success(&primary_result) {
+                // 丢弃 secondary future 即取消请求
+                drop(secondary);
+                let loser_sent = secondary_sent.load(Ordering::Relaxed);
+                return HedgeOutcome { secondary_won: false, result: primary_result, loser_result: None, loser_sent };
+            }
+            let secondary_result = secondary.await;
+            if is_success(&secondary_result) {
+                HedgeOutcome { secondary_won: true, result: secondary_result, loser_result: Some(primary_result), loser_sent: true }
+            } else {
+                HedgeOutcome { secondary_won: false, result: primary_result, loser_result: Some(secondary_result), loser_sent: true }
+            }
+        }
+        Either::Right((secondary_result, primary)) => {
+            if is_success(&secondary_result) {
+                return HedgeOutcome { secondary_won: true, result: secondary_result, loser_result: None, loser_sent: true };
+            }
+            let primary_result = primary.await;
+            HedgeOutcome {
+                secondary_won: false,
+                result: primary_result,
+                loser_result: Some(secondary_result),
+                loser_sent: true,
+            }
+        }
+    }
+}
+
+/// 按可靠性评分降序排序 (评分按 0.1 分档，同档保持配置顺序，避免微小差异导致顺序抖动)
+/// 没有统计记录的供应商视为满分
+fn apply_reliability_ordering(providers: &mut [&Provider], stats: &StatsManager) {
+    let scores = stats.provider_reliability();
+    providers.sort_by_key(|p| {
+        let score = scores.get(&p.name).copied().unwrap_or(1.0);
+        std::cmp::Reverse((score * 10.0).round() as u32)
+    });
+}
+
+// 延迟感知策略中，成功样本少于该数量的供应商使用中性默认延迟
+const LATENCY_MIN_SAMPLES: u64 = 5;
+
+/// 按选择策略对供应商排序
+fn apply_selection_strategy(providers: &mut [&Provider], strategy: &SelectionStrategy, stats: &StatsManager) {
+    match strategy {
+        SelectionStrategy::Ordered => {}
+        SelectionStrategy::LatencyAware => {
+            let latencies = stats.provider_latencies();
+            let sampled = |p: &Provider| {
+                latencies.get(&p.name)
+                    .filter(|(_, samples)| *samples >= LATENCY_MIN_SAMPLES)
+                    .map(|(p95, _)| *p95)
+            };
+            
+            // 样本不足的供应商使用已知 p95 的中位数，保证它们仍能获得流量以建立基线
+            let mut known: Vec<u64> = providers.iter().filter_map(|p| sampled(p)).collect();
+            known.sort_unstable();
+            let neutral = known.get(known.len() / 2).copied().unwrap_or(0);
+            
+            // 稳定排序：延迟相同时保持配置顺序
+            providers.sort_by_key(|p| sampled(p).unwrap_or(neutral));
+        }
+        SelectionStrategy::Weighted => {
+            // 加权随机排列 (Efraimidis-Spirakis)：key = u^(1/w)，按 key 降序，权重越高越可能排在前面
+            let factors = stats.provider_weight_factors();
+            let mut keyed: Vec<(f64, &Provider)> = providers.iter().map(|p| {
+                let weight = p.weight as f64 * factors.get(&p.name).copied().unwrap_or(1.0);
+                let key = if weight > 0.0 { fastrand::f64().powf(1.0 / weight) } else { 0.0 };
+                (key, *p)
+            }).collect();
+            keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+            for (slot, (_, provider)) in providers.iter_mut().zip(keyed) {
+                *slot = provider;
+            }
+        }
+        SelectionStrategy::Priority => {
+            // 稳定排序：权重相同时保持配置顺序，故障转移依次尝试下一个权重最高的供应商
+            providers.sort_by_key(|p| std::cmp::Reverse(p.weight));
+        }
+    }
+}
+
+/// 判断上游响应是否为流式 (流式响应不能被缓冲和缓存)
+/// - content-type 为 text/event-stream
+/// - Transfer-Encoding: chunked 且没有 Content-Length
+/// - 供应商配置了 force_streaming
+fn is_streaming_response(headers: &reqwest::header::HeaderMap, provider: &Provider) -> bool {
+    if provider.force_streaming {
+        return true;
+    }
+    
+    let content_type = headers.get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_lowercase();
+    if content_type.contains("text/event-stream") {
+        return true;
+    }
+    
+    let is_chunked = headers.get(reqwest::header::TRANSFER_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_lowercase().contains("chunked"))
+        .unwrap_or(false);
+    is_chunked && !headers.contains_key(reqwest::header::CONTENT_LENGTH)
+}
+
+/// 原生 Anthropic 请求：移除历史中没有签名的 thinking 块 (来自代理转换模式的响应)，并按配置注入全局 system 前缀/后缀
+/// 不需要修改或请求体不是消息格式的 JSON 对象时原样返回
+fn prepare_native_anthropic_body(body: &[u8], config: &GatewayConfig) -> Vec<u8> {
+    let Ok(mut json @ serde_json::Value::Object(_)) = serde_json::from_slice::<serde_json::Value>(body) else {
+        return body.to_vec();
+    };
+    if !json.get("messages").is_some_and(|m| m.is_array()) {
+        return body.to_vec();
+    }
+    
+    let stripped = converter::strip_unsigned_thinking(&mut json);
+    if stripped > 0 {
+        println!("🧹 Removed {} unsigned thinking block(s) before forwarding to a native Anthropic provider", stripped);
+    }
+    let has_affixes = config.system_prompt_prefix.is_some() || config.system_prompt_suffix.is_some();
+    if has_affixes {
+        converter::apply_system_prompt_affixes(&mut json, config.system_prompt_prefix.as_deref(), config.system_prompt_suffix.as_deref());
+    }
+    if stripped == 0 && !has_affixes {
+        return body.to_vec();
+    }
+    serde_json::to_vec(&json).unwrap_or_else(|_| body.to_vec())
+}
+
+/// 将请求体中的 stream 改为 false (并移除仅流式可用的 stream_options)
+/// 请求体不是 JSON 对象时原样返回
+fn disable_stream(body: Vec<u8>) -> Vec<u8> {
+    let Ok(serde_json::Value::Object(mut obj)) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        return body;
+    };
+    if !obj.contains_key("stream") {
+        return body;
+    }
+    obj.insert("stream".to_string(), serde_json::Value::Bool(false));
+    obj.remove("stream_options");
+    serde_json::to_vec(&obj).unwrap_or(body)
+}
+
+/// 将上游的完整 JSON 响应转换为客户端协议的 SSE 事件序列 (客户端请求流式、上游返回非流式时回放)
+/// 代理转换模式下上游响应为 Chat Completions (Anthropic 客户端且 responses_upstream 时为 Responses)，先转换为客户端协议；
+/// 否则上游响应即为客户端协议，按协议拆分为 Anthropic 事件、chat.completion.chunk 或 Responses 事件
+fn non_stream_sse_events(
+    api_type: &ApiType,
+    use_proxy_conversion: bool,
+    responses_upstream: bool,
+    body: &[u8],
+    model: &str,
+    content_path: Option<&str>,
+) -> Result<Vec<String>, String> {
+    match api_type {
+        ApiType::OpenAIResponses if use_proxy_conversion => converter::chat_response_to_responses_sse(body, model, content_path),
+        _ if use_proxy_conversion && responses_upstream => converter::responses_response_to_anthropic(body, model)
+            .and_then(|anthropic| converter::anthropic_response_to_sse(&anthropic)),
+        _ if use_proxy_conversion => converter::openai_response_to_anthropic(body, model, content_path)
+            .and_then(|anthropic| converter::anthropic_response_to_sse(&anthropic)),
+        ApiType::Anthropic => converter::anthropic_response_to_sse(body),
+        ApiType::OpenAIChat => converter::chat_response_to_chat_sse(body),
+        ApiType::OpenAIResponses => converter::responses_response_to_sse(body),
+    }
+}
+
+// 错误日志中保留的响应体最大字节数
+const ERROR_BODY_MAX_BYTES: usize = 500;
+
+/// 将上游错误响应体转换为日志文本：非 UTF-8 内容按有损方式解码并注明，
+/// 超长时在字符边界处截断
+fn describe_error_body(bytes: &[u8]) -> String {
+    let (text, lossy) = match std::str::from_utf8(bytes) {
+        Ok(text) => (std::borrow::Cow::Borrowed(text), false),
+        Err(_) => (String::from_utf8_lossy(bytes), true),
+    };
+    
+    let mut result = if text.len() > ERROR_BODY_MAX_BYTES {
+        let mut end = ERROR_BODY_MAX_BYTES;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}...(truncated)", &text[..end])
+    } else {
+        text.into_owned()
+    };
+    if lossy {
+        result.push_str(" (body was not valid UTF-8)");
+    }
+    result
+}
+
+/// 按配置生成写入请求日志的错误响应体：不保存时返回 None，开启脱敏时只保留长度和哈希
+fn error_body_for_log(bytes: &[u8], config: &GatewayConfig) -> Option<String> {
+    if !config.store_error_bodies {
+        None
+    } else if config.redact_error_bodies {
+        Some(stats::redact_error_body(bytes))
+    } else {
+        Some(describe_error_body(bytes))
+    }
+}
+
+/// 客户端请求体中是否要求流式响应
+fn request_wants_stream(body: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|json| json.get("stream").and_then(|s| s.as_bool()))
+        .unwrap_or(false)
+}
+
+/// 客户端请求体中的模型名
+fn request_model(body: &[u8]) -> Option<String> {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()?
+        .get("model")?
+        .as_str()
+        .map(|m| m.to_string())
+}
+
+/// 按路由规则调整供应商顺序：规则中列出的供应商按列表顺序排在最前，其余保持原顺序
+/// (exclusive 时移除未列出的供应商)
+fn apply_token_routing(providers: &mut Vec<&Provider>, rule: &TokenRoutingRule) {
+    if rule.exclusive {
+        providers.retain(|p| rule.provider_ids.contains(&p.id));
+    }
+    providers.sort_by_key(|p| rule.provider_ids.iter().position(|id| *id == p.id).unwrap_or(usize::MAX));
+}
+
+/// 改写请求体中的 model 字段 (请求体不是 JSON 对象时原样返回)
+fn rewrite_request_model(body: bytes::Bytes, model: &str) -> bytes::Bytes {
+    let Ok(serde_json::Value::Object(mut obj)) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        return body;
+    };
+    obj.insert("model".to_string(), serde_json::Value::String(model.to_string()));
+    serde_json::to_vec(&obj).map(bytes::Bytes::from).unwrap_or(body)
+}
+
+/// 供应商实际使用的模型名 (仅协议转换时应用模型映射)
+fn mapped_model(provider: &Provider, requested_model: &str, use_proxy_conversion: bool) -> String {
+    if use_proxy_conversion {
+        if let Some(mapped) = provider.model_mapping.get(requested_model) {
+            return mapped.clone();
+        }
+    }
+    requested_model.to_string()
+}
+
+/// 客户端的认证范围：客户端携带的 x-api-key / Authorization 值 (没有时为空)。
+/// 只参与缓存 Key 的哈希计算，使用不同密钥的客户端互不共享缓存
+fn client_auth_scope(headers: &HeaderMap) -> String {
+    ["x-api-key", "authorization"]
+        .iter()
+        .filter_map(|name| headers.get(*name).and_then(|v| v.to_str().ok()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 供应商的缓存命名空间：供应商 id + 实际发往上游的模型 + 用户设置的 cache_namespace + 客户端认证范围
+/// + 全局系统提示词前缀/后缀。修改模型映射、cache_namespace 或前缀/后缀都会使旧缓存自然失效
+fn cache_namespace(provider: &Provider, requested_model: &str, use_proxy_conversion: bool, client_auth: &str, system_affixes: &str) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        provider.id,
+        mapped_model(provider, requested_model, use_proxy_conversion),
+        provider.cache_namespace.as_deref().unwrap_or(""),
+        client_auth,
+        system_affixes,
+    )
+}
+
+/// 估算请求在指定供应商上的输入 token 数 (模型取请求中的模型经供应商映射后的名称)
+pub(crate) fn estimate_input_tokens(body: &[u8], provider: &Provider, use_proxy_conversion: bool) -> u32 {
+    let model = mapped_model(provider, &request_model(body).unwrap_or_default(), use_proxy_conversion);
+    count_input_tokens(provider, &input_text(body), &model)
+}
+
+/// 输入 token 数：供应商配置了 tokenizer 且模型有已知编码时精确计数，否则按字符比例估算
+fn count_input_tokens(provider: &Provider, text: &str, model: &str) -> u32 {
+    provider.tokenizer.as_deref()
+        .filter(|t| !t.is_empty())
+        .and_then(|tokenizer| tokenizer::count_tokens(text, tokenizer, model))
+        .unwrap_or_else(|| estimate_tokens_from_chars(text.chars().count(), provider.effective_chars_per_token()))
+}
+
+/// 提取请求中的消息文本 (字符数按 Unicode 字符计，中日韩文本不会因多字节编码被高估)；
+/// 不是消息格式的请求按整个请求体计
+fn input_text(body: &[u8]) -> String {
     if let Ok(json) = serde_json::from_slice::<serde_json::Value>(body) {
         if let Some(messages) = json.get("messages").and_then(|m| m.as_array()) {
-            let mut char_count = 0;
+            let mut text = String::new();
             for msg in messages {
                 if let Some(content) = msg.get("content") {
                     if let Some(s) = content.as_str() {
-                        char_count += s.len();
+                        text.push_str(s);
                     } else if let Some(arr) = content.as_array() {
                         for part in arr {
-                            if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
-                                char_count += text.len();
+                            if let Some(part_text) = part.get("text").and_then(|t| t.as_str()) {
+                                text.push_str(part_text);
                             }
                         }
                     }
                 }
             }
-            return (char_count as f64 / 4.0) as u32;
+            return text;
         }
     }
-    (body.len() as f64 / 4.0) as u32
+    String::from_utf8_lossy(body).into_owned()
 }
 
-fn calculate_cost(input_tokens: u32, output_tokens: u32, input_price: f64, output_price: f64) -> f64 {
+/// 失败尝试的费用：供应商对错误响应也计费时按输入 token 计算，否则为 0
+fn failed_attempt_cost(provider: &Provider, input_tokens: u32) -> f64 {
+    if provider.bill_failed_requests {
+        calculate_cost(input_tokens, 0, provider.input_price_per_1k, provider.output_price_per_1k)
+    } else {
+        0.0
+    }
+}
+
+pub(crate) fn calculate_cost(input_tokens: u32, output_tokens: u32, input_price: f64, output_price: f64) -> f64 {
     (input_tokens as f64 / 1000.0 * input_price) + (output_tokens as f64 / 1000.0 * output_price)
 }
 
@@ -614,3 +2300,504 @@ fn api_type_to_string(api_type: &ApiType) -> String {
         ApiType::OpenAIChat => "chat".to_string(),
     }
 }
+
+// 基准测试的最大输出 token 数与单个供应商的超时时间
+const BENCHMARK_MAX_TOKENS: u64 = 256;
+const BENCHMARK_TIMEOUT_SECS: u64 = 120;
+
+/// 单个供应商的基准测试结果
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ProviderBenchmark {
+    pub provider_id: String,
+    pub provider: String,
+    pub success: bool,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    // 总耗时与首个输出文本到达的耗时 (非流式响应没有 TTFT)
+    pub latency_ms: u64,
+    pub ttft_ms: Option<u64>,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    // 上游未返回 usage 时输出 token 按文本估算
+    pub output_estimated: bool,
+    pub cost: f64,
+}
+
+/// 构造基准测试请求 (客户端视角的路径和请求体)，按 API 类型使用对应的请求格式
+pub(crate) fn benchmark_request(api_type: &ApiType, prompt: &str, model: &str) -> (String, bytes::Bytes) {
+    let (path, body) = match api_type {
+        ApiType::Anthropic => ("/v1/messages", serde_json::json!({
+            "model": model,
+            "max_tokens": BENCHMARK_MAX_TOKENS,
+            "stream": true,
+            "messages": [{"role": "user", "content": prompt}],
+        })),
+        ApiType::OpenAIChat => ("/v1/chat/completions", serde_json::json!({
+            "model": model,
+            "max_tokens": BENCHMARK_MAX_TOKENS,
+            "stream": true,
+            "stream_options": {"include_usage": true},
+            "messages": [{"role": "user", "content": prompt}],
+        })),
+        ApiType::OpenAIResponses => ("/v1/responses", serde_json::json!({
+            "model": model,
+            "max_output_tokens": BENCHMARK_MAX_TOKENS,
+            "stream": true,
+            "input": prompt,
+        })),
+    };
+    (path.to_string(), bytes::Bytes::from(body.to_string()))
+}
+
+/// 向单个供应商发送基准测试请求 (与网关相同的协议转换和认证)，统计延迟、TTFT、token 和费用
+pub(crate) async fn benchmark_provider(
+    clients: &UpstreamClients,
+    provider: &Provider,
+    api_type: &ApiType,
+    path: &str,
+    body: bytes::Bytes,
+    config: &GatewayConfig,
+) -> ProviderBenchmark {
+    let use_proxy_conversion = provider.proxy_conversion_for(api_type);
+    let incoming = IncomingRequest {
+        method: Method::POST,
+        headers: HeaderMap::new(),
+        path: path.to_string(),
+        query: String::new(),
+        body,
+    };
+    let input_tokens = estimate_input_tokens(&incoming.body, provider, use_proxy_conversion);
+    let mut result = ProviderBenchmark {
+        provider_id: provider.id.clone(),
+        provider: provider.name.clone(),
+        success: false,
+        status: None,
+        error: None,
+        latency_ms: 0,
+        ttft_ms: None,
+        input_tokens,
+        output_tokens: 0,
+        output_estimated: false,
+        cost: 0.0,
+    };
+    
+    let client = match clients.client_for(provider) {
+        Ok(client) => client,
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    };
+    let start = std::time::Instant::now();
+    let elapsed_ms = || start.elapsed().as_millis() as u64;
+    let upstream = match build_upstream_request(&client, &incoming, provider, api_type, use_proxy_conversion, config) {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    };
+    let response = match upstream.builder
+        .timeout(std::time::Duration::from_secs(BENCHMARK_TIMEOUT_SECS))
+        .send()
+        .await
+    {
+        Ok(response) => response,
+        Err(e) => {
+            result.latency_ms = elapsed_ms();
+            result.error = Some(GatewayError::from(e).to_string());
+            return result;
+        }
+    };
+    
+    let status = response.status();
+    result.status = Some(status.as_u16());
+    let streaming = is_streaming_response(response.headers(), provider);
+    
+    let mut output_chars = 0usize;
+    let mut reported_output_tokens = None;
+    if !status.is_success() || !streaming {
+        match response.bytes().await {
+            Ok(bytes) if status.is_success() => {
+                reported_output_tokens = converter::response_output_tokens(&bytes);
+            }
+            Ok(bytes) => result.error = Some(format!("HTTP {}: {}", status.as_u16(), describe_error_body(&bytes))),
+            Err(e) => result.error = Some(e.to_string()),
+        }
+    } else {
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+        let mut observe = |line: &str, result: &mut ProviderBenchmark| {
+            if let Some(tokens) = converter::sse_output_tokens(line) {
+                reported_output_tokens = Some(tokens);
+            }
+            if let Some(text) = converter::sse_delta_text(line) {
+                result.ttft_ms.get_or_insert_with(elapsed_ms);
+                output_chars += text.chars().count();
+            }
+        };
+        while let Some(chunk_result) = futures::StreamExt::next(&mut stream).await {
+            match chunk_result {
+                Ok(chunk) => {
+                    buffer.push_str(&String::from_utf8_lossy(&chunk));
+                    while let Some(pos) = buffer.find('\n') {
+                        observe(buffer[..pos].trim(), &mut result);
+                        buffer.drain(..=pos);
+                    }
+                }
+                Err(e) => {
+                    result.error = Some(format!("Stream error: {}", e));
+                    break;
+                }
+            }
+        }
+        observe(buffer.trim(), &mut result);
+    }
+    
+    result.latency_ms = elapsed_ms();
+    result.success = result.error.is_none();
+    if result.success {
+        let (output_tokens, estimated) = match reported_output_tokens {
+            Some(tokens) => (tokens, false),
+            None => (estimate_tokens_from_chars(output_chars, provider.effective_chars_per_token()), true),
+        };
+        result.output_tokens = output_tokens;
+        result.output_estimated = estimated;
+        result.cost = calculate_cost(input_tokens, output_tokens, provider.input_price_per_1k, provider.output_price_per_1k);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    
+    fn event_data(event: &str) -> Value {
+        let data = event.lines()
+            .find_map(|line| line.strip_prefix("data: "))
+            .expect("event has no data line");
+        serde_json::from_str(data).expect("event data is not JSON")
+    }
+    
+    fn event_types(events: &[String]) -> Vec<String> {
+        events.iter()
+            .map(|e| event_data(e)["type"].as_str().unwrap_or("").to_string())
+            .collect()
+    }
+    
+    #[test]
+    fn replays_non_stream_chat_response_as_anthropic_sse() {
+        let text = "x".repeat(100);
+        let body = json!({
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": text}, "finish_reason": "stop"}],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 7}
+        });
+        let events = non_stream_sse_events(&ApiType::Anthropic, true, false, body.to_string().as_bytes(), "claude", None).unwrap();
+        
+        assert_eq!(event_types(&events), [
+            "message_start",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]);
+        assert_eq!(event_data(&events[0])["message"]["usage"]["input_tokens"], 12);
+        let replayed: String = events[2..4].iter()
+            .map(|e| event_data(e)["delta"]["text"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(replayed, text);
+        let delta = event_data(&events[5]);
+        assert_eq!(delta["delta"]["stop_reason"], "end_turn");
+        assert_eq!(delta["usage"]["output_tokens"], 7);
+    }
+    
+    #[test]
+    fn replays_native_anthropic_response_as_sse() {
+        let body = json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude",
+            "content": [{"type": "text", "text": "hi"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 3, "output_tokens": 1}
+        });
+        let events = non_stream_sse_events(&ApiType::Anthropic, false, false, body.to_string().as_bytes(), "claude", None).unwrap();
+        assert_eq!(event_types(&events).first().map(String::as_str), Some("message_start"));
+        assert_eq!(event_types(&events).last().map(String::as_str), Some("message_stop"));
+        assert_eq!(event_data(&events[2])["delta"]["text"], "hi");
+    }
+    
+    #[test]
+    fn disable_stream_buffers_then_replays_for_each_gateway() {
+        // 发往上游的请求改为非流式
+        let request = json!({"model": "m", "stream": true, "stream_options": {"include_usage": true}, "messages": []});
+        let upstream: Value = serde_json::from_slice(&disable_stream(request.to_string().into_bytes())).unwrap();
+        assert_eq!(upstream["stream"], false);
+        assert!(upstream.get("stream_options").is_none());
+        
+        // Chat 网关：完整的 chat.completion 回放为 chat.completion.chunk
+        let chat = json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "hi"}, "finish_reason": "stop"}]
+        });
+        let events = non_stream_sse_events(&ApiType::OpenAIChat, false, false, chat.to_string().as_bytes(), "gpt-4o", None).unwrap();
+        assert_eq!(events.last().map(String::as_str), Some("data: [DONE]"));
+        assert_eq!(event_data(&events[0])["object"], "chat.completion.chunk");
+        assert_eq!(event_data(&events[1])["choices"][0]["delta"]["content"], "hi");
+        
+        // Responses 网关：原生 Responses JSON 回放为 Responses 事件
+        let responses = json!({
+            "id": "resp_1",
+            "status": "completed",
+            "output": [{"type": "message", "id": "msg_1", "role": "assistant", "content": [{"type": "output_text", "text": "hi"}]}]
+        });
+        let events = non_stream_sse_events(&ApiType::OpenAIResponses, false, false, responses.to_string().as_bytes(), "gpt-4o", None).unwrap();
+        assert_eq!(event_types(&events).first().map(String::as_str), Some("response.created"));
+        assert_eq!(event_types(&events).last().map(String::as_str), Some("response.completed"));
+        assert!(events.iter().any(|e| event_data(e)["delta"] == "hi"));
+    }
+    
+    #[test]
+    fn native_anthropic_body_drops_unsigned_thinking() {
+        // 代理转换模式返回的 thinking 块 (signature 为空) 被客户端回传，改发给原生 Anthropic 供应商
+        let reply = json!({
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "answer", "reasoning_content": "thoughts"}, "finish_reason": "stop"}]
+        });
+        let converted = converter::openai_response_value_to_anthropic(&reply, "claude", None);
+        let request = json!({
+            "model": "claude",
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": converted["content"]},
+                {"role": "user", "content": "more"}
+            ]
+        });
+        let body: Value = serde_json::from_slice(&prepare_native_anthropic_body(request.to_string().as_bytes(), &GatewayConfig::default())).unwrap();
+        assert_eq!(body["messages"][1]["content"], json!([{"type": "text", "text": "answer"}]));
+        
+        // 不需要修改的请求原样转发
+        let untouched = br#"{"model":"claude","messages":[{"role":"user","content":"hi"}]}"#;
+        assert_eq!(prepare_native_anthropic_body(untouched, &GatewayConfig::default()), untouched.to_vec());
+    }
+    
+    #[test]
+    fn non_stream_replay_rejects_invalid_json() {
+        assert!(non_stream_sse_events(&ApiType::Anthropic, true, false, b"not json", "claude", None).is_err());
+    }
+    
+    #[test]
+    fn anthropic_requests_target_responses_upstream_when_configured() {
+        let mut provider = provider("codex");
+        provider.claude_code_proxy = true;
+        provider.responses_upstream = true;
+        let incoming = IncomingRequest {
+            method: Method::POST,
+            headers: HeaderMap::new(),
+            path: "/v1/messages".to_string(),
+            query: String::new(),
+            body: bytes::Bytes::from(json!({
+                "model": "claude",
+                "max_tokens": 100,
+                "stream": true,
+                "messages": [{"role": "user", "content": "hi"}]
+            }).to_string()),
+        };
+        let upstream = build_upstream_request(&Client::new(), &incoming, &provider, &ApiType::Anthropic, true, &GatewayConfig::default()).unwrap();
+        assert_eq!(upstream.url, "https://codex.example.com/v1/responses");
+        let request = upstream.builder.build().unwrap();
+        let body: Value = serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body["input"], json!([{"type": "message", "role": "user", "content": "hi"}]));
+        assert_eq!(body["max_output_tokens"], 100);
+        
+        // 上游忽略 stream 返回完整的 Responses JSON 时，按 Anthropic SSE 回放
+        let response = json!({
+            "status": "completed",
+            "output": [{"type": "message", "role": "assistant", "content": [{"type": "output_text", "text": "hello"}]}],
+            "usage": {"input_tokens": 5, "output_tokens": 2}
+        });
+        let events = non_stream_sse_events(&ApiType::Anthropic, true, true, response.to_string().as_bytes(), "claude", None).unwrap();
+        assert_eq!(event_data(&events[2])["delta"]["text"], "hello");
+        assert_eq!(event_data(&events[events.len() - 2])["usage"]["output_tokens"], 2);
+    }
+    
+    fn provider(id: &str) -> Provider {
+        serde_json::from_value(json!({
+            "id": id,
+            "name": id,
+            "base_url": format!("https://{}.example.com", id),
+            "api_key": "sk-test",
+            "enabled": true
+        })).unwrap()
+    }
+    
+    fn response_headers(headers: &[(&str, &str)]) -> reqwest::header::HeaderMap {
+        headers.iter()
+            .map(|(name, value)| (
+                reqwest::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                reqwest::header::HeaderValue::from_str(value).unwrap(),
+            ))
+            .collect()
+    }
+    
+    #[test]
+    fn detects_event_stream_content_type() {
+        let headers = response_headers(&[("content-type", "Text/Event-Stream; charset=utf-8")]);
+        assert!(is_streaming_response(&headers, &provider("p")));
+    }
+    
+    #[test]
+    fn detects_chunked_response_without_content_length() {
+        let chunked = response_headers(&[("content-type", "application/json"), ("transfer-encoding", "chunked")]);
+        assert!(is_streaming_response(&chunked, &provider("p")));
+        
+        let with_length = response_headers(&[
+            ("content-type", "application/json"),
+            ("transfer-encoding", "chunked"),
+            ("content-length", "42"),
+        ]);
+        assert!(!is_streaming_response(&with_length, &provider("p")));
+    }
+    
+    #[test]
+    fn force_streaming_overrides_content_type() {
+        let headers = response_headers(&[("content-type", "application/x-custom"), ("content-length", "42")]);
+        let mut forced = provider("p");
+        assert!(!is_streaming_response(&headers, &forced));
+        forced.force_streaming = true;
+        assert!(is_streaming_response(&headers, &forced));
+    }
+    
+    #[test]
+    fn plain_json_response_is_not_streaming() {
+        let headers = response_headers(&[("content-type", "application/json"), ("content-length", "42")]);
+        assert!(!is_streaming_response(&headers, &provider("p")));
+        assert!(!is_streaming_response(&response_headers(&[]), &provider("p")));
+    }
+    
+    /// 启动本地上游：/ok 返回 200，/fail 返回 500
+    async fn spawn_upstream() -> String {
+        let app = Router::new()
+            .route("/ok", any(|| async { "ok" }))
+            .route("/fail", any(|| async { StatusCode::INTERNAL_SERVER_ERROR }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+    
+    #[tokio::test]
+    async fn hedge_secondary_is_not_sent_when_primary_answers_first() {
+        let base = spawn_upstream().await;
+        let client = Client::new();
+        let outcome = race_hedged(
+            client.get(format!("{}/ok", base)),
+            client.get(format!("{}/ok", base)),
+            std::time::Duration::from_secs(5),
+        ).await;
+        assert!(!outcome.secondary_won);
+        assert!(outcome.result.unwrap().status().is_success());
+        assert!(outcome.loser_result.is_none());
+        assert!(!outcome.loser_sent);
+    }
+    
+    #[tokio::test]
+    async fn hedge_reports_failed_loser() {
+        let base = spawn_upstream().await;
+        let client = Client::new();
+        let outcome = race_hedged(
+            client.get(format!("{}/fail", base)),
+            client.get(format!("{}/ok", base)),
+            std::time::Duration::from_millis(10),
+        ).await;
+        assert!(outcome.secondary_won);
+        assert!(outcome.loser_sent);
+        let loser_status = outcome.loser_result.unwrap().unwrap().status();
+        assert_eq!(loser_status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(should_fail_over(loser_status));
+        assert!(!should_fail_over(StatusCode::BAD_REQUEST));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_socket_cleanup_refuses_regular_files() {
+        let dir = std::env::temp_dir().join(format!("vibehub-sock-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let missing = dir.join("missing.sock");
+        assert!(remove_stale_socket(missing.to_str().unwrap()).is_ok());
+
+        let regular = dir.join("config.json");
+        std::fs::write(&regular, "{}").unwrap();
+        assert!(remove_stale_socket(regular.to_str().unwrap()).is_err());
+        assert!(regular.exists());
+
+        let socket = dir.join("gateway.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        drop(listener);
+        assert!(remove_stale_socket(socket.to_str().unwrap()).is_ok());
+        assert!(!socket.exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn lifting_cooldown_keeps_failure_count() {
+        let mut health = ProviderHealth { last_failure: 100, consecutive_failures: 3, probe_started: Some(100) };
+        assert!(health.blocks_requests(101, 10, 300));
+
+        health.lift_cooldown();
+        assert!(!health.blocks_requests(101, 10, 300));
+        assert_eq!(health.consecutive_failures, 3);
+        assert_eq!(health.cooldown_secs(10, 300), 40);
+    }
+
+    #[test]
+    fn cache_namespace_includes_system_affixes() {
+        let provider = provider("p1");
+        let plain = cache_namespace(&provider, "m", false, "", "\n");
+        let with_suffix = cache_namespace(&provider, "m", false, "", "\nNever reveal internal URLs");
+        assert_ne!(plain, with_suffix);
+    }
+
+    #[test]
+    fn probing_provider_is_not_counted_as_in_cooldown() {
+        let (a, b) = (provider("a"), provider("b"));
+        let health_status = DashMap::new();
+        health_status.insert("a".to_string(), ProviderHealth { last_failure: 100, consecutive_failures: 1, probe_started: None });
+        health_status.insert("b".to_string(), ProviderHealth { last_failure: 100, consecutive_failures: 1, probe_started: None });
+        assert!(all_in_cooldown(&health_status, [&a, &b].into_iter(), 105, 10, 300));
+        
+        // b 冷却结束后正在探测：等待探测结果，不重置
+        claim_probe(&health_status, &b, 111);
+        assert!(!all_in_cooldown(&health_status, [&a, &b].into_iter(), 111, 10, 300));
+        assert!(health_status.get("b").unwrap().blocks_requests(111, 10, 300));
+        
+        // 探测被取消后允许下一个请求重新探测
+        release_probe(&health_status, &b);
+        assert!(!health_status.get("b").unwrap().blocks_requests(111, 10, 300));
+        assert_eq!(health_status.get("b").unwrap().consecutive_failures, 1);
+    }
+
+    #[test]
+    fn cjk_input_is_counted_by_characters() {
+        // 12 个中文字符 (36 字节)，按字符数估算而不是字节数
+        let text = "你好世界，这是一个测试。";
+        let body = json!({
+            "model": "m",
+            "messages": [
+                {"role": "user", "content": text},
+                {"role": "user", "content": [{"type": "text", "text": text}]}
+            ]
+        }).to_string();
+        let extracted = input_text(body.as_bytes());
+        assert_eq!(extracted, format!("{}{}", text, text));
+        assert_eq!(extracted.chars().count(), 24);
+        assert_eq!(estimate_tokens_from_chars(extracted.chars().count(), 4.0), 6);
+        assert_eq!(estimate_input_tokens(body.as_bytes(), &provider("p1"), false), 6);
+    }
+}
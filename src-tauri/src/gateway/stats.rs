@@ -1,8 +1,13 @@
 use serde::{Deserialize, Serialize};
 use std::collections::{VecDeque, HashMap};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RequestLog {
@@ -24,9 +29,58 @@ pub struct RequestLog {
     #[serde(default)]
     pub cached: bool,
     #[serde(default)]
-    pub error_message: Option<String>,  // 完整错误信息
+    pub error_message: Option<String>,  // 错误信息 (上游错误响应体可能回显提示词，受 redact_error_bodies / store_error_bodies 控制)
+    #[serde(default)]
+    pub attempt_index: u32,  // 本次请求中的第几次尝试 (从 0 开始)
+    #[serde(default)]
+    pub failed_providers: Vec<String>,  // 此前已失败的供应商
+    #[serde(default)]
+    pub request_id: String,  // 所属客户端请求 ID (同一请求的多次尝试共享，与 x-vbding-request-id 一致)
+    #[serde(default)]
+    pub cancelled: bool,  // 对冲请求中落败被取消的尝试 (不计入供应商健康统计)
+    #[serde(default)]
+    pub requested_model: String,  // 客户端请求的模型
+    #[serde(default)]
+    pub mapped_model: String,  // 经模型映射后实际发往上游的模型 (未映射时与 requested_model 相同)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub captured_request: Option<CapturedRequest>,  // 开启 debug_capture 时记录的上游请求 (认证头已脱敏)
+    #[serde(default)]
+    pub estimated: bool,  // output_tokens/cost 为按流式文本估算的近似值 (上游未返回 usage)
 }
 
+/// 调试捕获的上游请求 (用于复现请求，如导出为 curl 命令或通过网关重放)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapturedRequest {
+    pub method: String,
+    pub url: String,
+    // 认证头的密钥部分替换为 REDACTED_API_KEY
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    // 客户端发给网关的原始请求 (路径含查询参数，请求头不含认证信息)，用于重放
+    #[serde(default)]
+    pub client_path: String,
+    #[serde(default)]
+    pub client_headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub client_body: String,
+}
+
+/// 捕获请求中 API Key 的占位符
+pub const REDACTED_API_KEY: &str = "<redacted>";
+
+/// 脱敏上游错误响应体：只保留长度和 SHA256 前缀，便于比对相同错误而不保存原文
+pub fn redact_error_body(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+    format!("<redacted {} bytes, sha256:{}>", body.len(), hex)
+}
+
+fn default_reliability_score() -> f64 { 1.0 }
+fn default_weight_factor() -> f64 { 1.0 }
+
+// 动态权重系数的下限 (避免供应商完全得不到流量而无法恢复) 及每次成功的恢复量
+const MIN_WEIGHT_FACTOR: f64 = 0.05;
+const WEIGHT_RECOVERY_STEP: f64 = 0.1;
 fn default_path() -> String { "/".to_string() }
 fn default_agent() -> String { "unknown".to_string() }
 
@@ -61,10 +115,20 @@ pub struct ProviderStats {
     pub last_error_message: Option<String>,
     pub consecutive_failures: u32,
     pub is_healthy: bool,
+    // 可靠性评分 (请求结果的指数加权平均，0.0 - 1.0)，随统计持久化，用于重启后的初始排序
+    #[serde(default = "default_reliability_score")]
+    pub reliability_score: f64,
+    // 动态权重系数：失败时减半，成功时逐步恢复 (有效权重 = Provider.weight * 系数)，用于加权选择
+    #[serde(default = "default_weight_factor")]
+    pub weight_factor: f64,
     
     // 延迟样本 (用于计算分位数，保留最近100个)
     #[serde(skip)]
     latency_samples: VecDeque<u64>,
+    
+    // 最近请求结果 (true = 成功，用于计算滚动成功率，保留最近100个)
+    #[serde(skip)]
+    recent_outcomes: VecDeque<bool>,
 }
 
 impl ProviderStats {
@@ -73,7 +137,10 @@ impl ProviderStats {
             provider_id: id,
             provider_name: name,
             is_healthy: true,
+            reliability_score: default_reliability_score(),
+            weight_factor: default_weight_factor(),
             latency_samples: VecDeque::with_capacity(100),
+            recent_outcomes: VecDeque::with_capacity(100),
             ..Default::default()
         }
     }
@@ -81,11 +148,21 @@ impl ProviderStats {
     pub fn record_request(&mut self, success: bool, latency_ms: u64, input_tokens: u32, output_tokens: u32, cost: f64, timestamp: u64, error_msg: Option<String>) {
         self.total_requests += 1;
         
+        // 新结果权重 0.2
+        let outcome = if success { 1.0 } else { 0.0 };
+        self.reliability_score = self.reliability_score * 0.8 + outcome * 0.2;
+        
+        self.recent_outcomes.push_back(success);
+        if self.recent_outcomes.len() > 100 {
+            self.recent_outcomes.pop_front();
+        }
+        
         if success {
             self.successful_requests += 1;
             self.last_success_at = Some(timestamp);
             self.consecutive_failures = 0;
             self.is_healthy = true;
+            self.weight_factor = (self.weight_factor + WEIGHT_RECOVERY_STEP).min(1.0);
             
             // 更新延迟统计
             self.latency_samples.push_back(latency_ms);
@@ -98,6 +175,7 @@ impl ProviderStats {
             self.last_failure_at = Some(timestamp);
             self.last_error_message = error_msg;
             self.consecutive_failures += 1;
+            self.weight_factor = (self.weight_factor * 0.5).max(MIN_WEIGHT_FACTOR);
             
             // 连续失败3次标记为不健康
             if self.consecutive_failures >= 3 {
@@ -110,6 +188,11 @@ impl ProviderStats {
         self.total_cost += cost;
     }
     
+    /// 有效权重 = 配置权重 * 动态权重系数
+    pub fn effective_weight(&self, base_weight: u32) -> f64 {
+        base_weight as f64 * self.weight_factor
+    }
+    
     fn update_latency_stats(&mut self) {
         if self.latency_samples.is_empty() {
             return;
@@ -122,9 +205,60 @@ impl ProviderStats {
         self.min_latency_ms = sorted[0];
         self.max_latency_ms = sorted[len - 1];
         self.avg_latency_ms = sorted.iter().sum::<u64>() as f64 / len as f64;
-        self.p50_latency_ms = sorted[len / 2];
-        self.p95_latency_ms = sorted[(len as f64 * 0.95) as usize];
-        self.p99_latency_ms = sorted[(len as f64 * 0.99).min(len as f64 - 1.0) as usize];
+        // 分位数索引统一限制在 len - 1 以内，样本很少时不会越界
+        let percentile = |p: f64| sorted[((len as f64 * p) as usize).min(len - 1)];
+        self.p50_latency_ms = percentile(0.50);
+        self.p95_latency_ms = percentile(0.95);
+        self.p99_latency_ms = percentile(0.99);
+    }
+    
+    /// 合并另一份供应商统计 (计数求和，延迟取样本更多的一方)
+    fn merge(&mut self, other: ProviderStats) {
+        let total_success = self.successful_requests + other.successful_requests;
+        if total_success > 0 {
+            self.avg_latency_ms = (self.avg_latency_ms * self.successful_requests as f64
+                + other.avg_latency_ms * other.successful_requests as f64) / total_success as f64;
+        }
+        if other.successful_requests > 0 {
+            self.min_latency_ms = if self.successful_requests > 0 {
+                self.min_latency_ms.min(other.min_latency_ms)
+            } else {
+                other.min_latency_ms
+            };
+            self.max_latency_ms = self.max_latency_ms.max(other.max_latency_ms);
+        }
+        if other.successful_requests > self.successful_requests {
+            self.p50_latency_ms = other.p50_latency_ms;
+            self.p95_latency_ms = other.p95_latency_ms;
+            self.p99_latency_ms = other.p99_latency_ms;
+        }
+        
+        let total_requests = self.total_requests + other.total_requests;
+        if total_requests > 0 {
+            self.reliability_score = (self.reliability_score * self.total_requests as f64
+                + other.reliability_score * other.total_requests as f64) / total_requests as f64;
+        }
+        
+        self.total_requests += other.total_requests;
+        self.successful_requests += other.successful_requests;
+        self.failed_requests += other.failed_requests;
+        self.total_input_tokens += other.total_input_tokens;
+        self.total_output_tokens += other.total_output_tokens;
+        self.total_cost += other.total_cost;
+        self.last_success_at = self.last_success_at.max(other.last_success_at);
+        if other.last_failure_at > self.last_failure_at {
+            self.last_failure_at = other.last_failure_at;
+            self.last_error_message = other.last_error_message;
+        }
+    }
+    
+    /// 最近 window 次请求的成功率 (0.0 - 1.0)，样本不足 window 个时返回 None
+    pub fn rolling_success_rate(&self, window: usize) -> Option<f64> {
+        if window == 0 || self.recent_outcomes.len() < window {
+            return None;
+        }
+        let successes = self.recent_outcomes.iter().rev().take(window).filter(|s| **s).count();
+        Some(successes as f64 / window as f64)
     }
     
     pub fn success_rate(&self) -> f64 {
@@ -142,6 +276,64 @@ pub struct HourlyStat {
     pub input_tokens: u32,
     pub output_tokens: u32,
     pub cost: f64,
+    // 按供应商名称拆分的费用 (用于按供应商预测支出)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub provider_costs: HashMap<String, f64>,
+    // 按供应商名称拆分的请求数和失败数 (不含被取消的对冲请求，用于计算 SLA)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub provider_requests: HashMap<String, u32>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub provider_failures: HashMap<String, u32>,
+}
+
+/// 供应商健康状态变化记录：一段冷却期 (进入冷却到恢复)，用于计算可用性
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CooldownPeriod {
+    pub provider: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+// 保留的冷却期记录条数
+const MAX_COOLDOWN_PERIODS: usize = 1000;
+
+/// 供应商在一段时间内的 SLA
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderSla {
+    pub provider_id: String,
+    pub provider_name: String,
+    pub period_start: u64,
+    pub period_end: u64,
+    pub total_requests: u64,
+    pub failed_requests: u64,
+    // 没有请求时为 None
+    pub success_rate: Option<f64>,
+    // 不在冷却中的时间占比 (0 - 1)
+    pub availability: f64,
+    pub cooldown_secs: u64,
+    // 按小时 (或按天，周期超出小时统计保留范围时) 的错误率趋势
+    pub error_rate_trend: Vec<ErrorRatePoint>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorRatePoint {
+    pub timestamp: u64,
+    pub requests: u32,
+    pub error_rate: f64,
+}
+
+/// 月度支出预测 (按最近 7 天的日统计外推到 30 天)
+#[derive(Debug, Clone, Serialize)]
+pub struct MonthlyCostProjection {
+    pub total: f64,
+    pub providers: HashMap<String, f64>,
+    // 采样窗口 (秒级时间戳) 及窗口内的实际费用
+    pub window_start: u64,
+    pub window_end: u64,
+    pub window_days: f64,
+    pub window_cost: f64,
+    // 采样不足一天，预测值仅供参考
+    pub low_confidence: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -151,6 +343,9 @@ pub struct GatewayStats {
     pub total_input_tokens: u64,
     pub total_output_tokens: u64,
     pub total_cost: f64,
+    // total_cost 中来自失败尝试的部分 (供应商对错误响应计费时)
+    #[serde(default)]
+    pub cost_from_failures: f64,
     pub cache_hits: u64,
     pub cache_misses: u64,
     
@@ -165,42 +360,416 @@ pub struct GatewayStats {
     
     pub recent_requests: VecDeque<RequestLog>,
     pub hourly_activity: Vec<HourlyStat>,
+    // 按天统计 (timestamp 为 UTC 当天零点)
+    #[serde(default)]
+    pub daily_activity: Vec<HourlyStat>,
+    
+    // 供应商冷却期记录 (按开始时间排序)
+    #[serde(default)]
+    pub cooldown_periods: Vec<CooldownPeriod>,
+    
+    // 按供应商标签汇总 (查询时根据当前配置计算，不持久化)
+    #[serde(default, skip_deserializing)]
+    pub provider_tag_stats: HashMap<String, ProviderTagStats>,
+}
+
+/// 按供应商标签汇总的统计
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProviderTagStats {
+    pub providers: Vec<String>,
+    pub total_requests: u64,
+    pub successful_requests: u64,
+    pub failed_requests: u64,
+    pub total_input_tokens: u64,
+    pub total_output_tokens: u64,
+    pub total_cost: f64,
+}
+
+/// Prometheus 标签值转义 (反斜杠、双引号、换行)
+fn prometheus_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl GatewayStats {
+    /// 以 Prometheus 文本格式 (0.0.4) 输出统计：全局/各 API 类型/各供应商的计数器，
+    /// 以及供应商健康状态和延迟分位数等指标
+    pub fn to_prometheus(&self) -> String {
+        use std::fmt::Write as _;
+        
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                if labels.is_empty() {
+                    let _ = writeln!(out, "{} {}", name, value);
+                } else {
+                    let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+                }
+            }
+        };
+        let total = |value: String| vec![(String::new(), value)];
+        
+        metric("gateway_requests_total", "counter", "Requests handled by the gateway, by API type", &[
+            ("api_type=\"anthropic\"".to_string(), self.anthropic_requests.to_string()),
+            ("api_type=\"responses\"".to_string(), self.responses_requests.to_string()),
+            ("api_type=\"chat\"".to_string(), self.chat_requests.to_string()),
+        ]);
+        metric("gateway_cache_hits_total", "counter", "Response cache hits", &total(self.cache_hits.to_string()));
+        metric("gateway_cache_misses_total", "counter", "Response cache misses", &total(self.cache_misses.to_string()));
+        metric("gateway_input_tokens_total", "counter", "Input tokens across all requests", &total(self.total_input_tokens.to_string()));
+        metric("gateway_output_tokens_total", "counter", "Output tokens across all requests", &total(self.total_output_tokens.to_string()));
+        metric("gateway_cost_usd_total", "counter", "Estimated cost in USD across all requests", &total(self.total_cost.to_string()));
+        metric("gateway_failed_cost_usd_total", "counter", "Estimated cost in USD of failed upstream attempts", &total(self.cost_from_failures.to_string()));
+        
+        let mut providers: Vec<(&String, &ProviderStats)> = self.provider_stats.iter().collect();
+        providers.sort_by(|a, b| a.0.cmp(b.0));
+        let per_provider = |value: &dyn Fn(&ProviderStats) -> String| -> Vec<(String, String)> {
+            providers.iter()
+                .map(|(name, ps)| (format!("provider=\"{}\"", prometheus_label(name)), value(ps)))
+                .collect()
+        };
+        
+        let outcomes: Vec<(String, String)> = providers.iter()
+            .flat_map(|(name, ps)| {
+                let label = prometheus_label(name);
+                [
+                    (format!("provider=\"{}\",outcome=\"success\"", label), ps.successful_requests.to_string()),
+                    (format!("provider=\"{}\",outcome=\"failure\"", label), ps.failed_requests.to_string()),
+                ]
+            })
+            .collect();
+        metric("gateway_provider_requests_total", "counter", "Upstream attempts per provider, by outcome", &outcomes);
+        metric("gateway_provider_cost_usd_total", "counter", "Estimated cost in USD per provider", &per_provider(&|ps| ps.total_cost.to_string()));
+        metric("gateway_provider_healthy", "gauge", "Whether the provider is currently considered healthy (1) or not (0)",
+            &per_provider(&|ps| u8::from(ps.is_healthy).to_string()));
+        
+        let latencies: Vec<(String, String)> = providers.iter()
+            .flat_map(|(name, ps)| {
+                let label = prometheus_label(name);
+                [("0.5", ps.p50_latency_ms), ("0.95", ps.p95_latency_ms), ("0.99", ps.p99_latency_ms)]
+                    .map(|(quantile, value)| (format!("provider=\"{}\",quantile=\"{}\"", label, quantile), value.to_string()))
+            })
+            .collect();
+        metric("gateway_provider_latency_ms", "gauge", "Provider latency percentiles over recent successful requests", &latencies);
+        
+        out
+    }
+    
+    /// 按供应商标签汇总供应商统计 (provider_tags: 供应商名称 -> 标签)
+    pub fn aggregate_by_provider_tag(&mut self, provider_tags: &HashMap<String, Vec<String>>) {
+        let mut result: HashMap<String, ProviderTagStats> = HashMap::new();
+        for (name, ps) in &self.provider_stats {
+            for tag in provider_tags.get(name).into_iter().flatten() {
+                let entry = result.entry(tag.clone()).or_default();
+                entry.providers.push(name.clone());
+                entry.total_requests += ps.total_requests;
+                entry.successful_requests += ps.successful_requests;
+                entry.failed_requests += ps.failed_requests;
+                entry.total_input_tokens += ps.total_input_tokens;
+                entry.total_output_tokens += ps.total_output_tokens;
+                entry.total_cost += ps.total_cost;
+            }
+        }
+        self.provider_tag_stats = result;
+    }
+    
+    /// 合并另一份统计数据 (用于汇总多台机器的统计)
+    pub fn merge(&mut self, other: GatewayStats) {
+        self.total_requests += other.total_requests;
+        self.total_input_tokens += other.total_input_tokens;
+        self.total_output_tokens += other.total_output_tokens;
+        self.total_cost += other.total_cost;
+        self.cost_from_failures += other.cost_from_failures;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        self.anthropic_requests += other.anthropic_requests;
+        self.responses_requests += other.responses_requests;
+        self.chat_requests += other.chat_requests;
+        
+        for (name, provider_stats) in other.provider_stats {
+            match self.provider_stats.get_mut(&name) {
+                Some(existing) => existing.merge(provider_stats),
+                None => {
+                    self.provider_stats.insert(name, provider_stats);
+                }
+            }
+        }
+        
+        // 最近请求：按时间倒序合并，去重后保留 50 条
+        let mut recent: Vec<RequestLog> = self.recent_requests.drain(..).chain(other.recent_requests).collect();
+        recent.sort_by_key(|log| std::cmp::Reverse(log.timestamp));
+        let mut seen = std::collections::HashSet::new();
+        recent.retain(|log| seen.insert(log.id.clone()));
+        recent.truncate(50);
+        self.recent_requests = recent.into();
+        
+        // 小时/天统计：同一时间段求和
+        Self::merge_buckets(&mut self.hourly_activity, other.hourly_activity);
+        Self::merge_buckets(&mut self.daily_activity, other.daily_activity);
+        
+        self.cooldown_periods.extend(other.cooldown_periods);
+        self.cooldown_periods.sort_by_key(|p| p.start);
+        Self::trim_cooldown_periods(&mut self.cooldown_periods);
+    }
+    
+    fn trim_cooldown_periods(periods: &mut Vec<CooldownPeriod>) {
+        if periods.len() > MAX_COOLDOWN_PERIODS {
+            let excess = periods.len() - MAX_COOLDOWN_PERIODS;
+            periods.drain(..excess);
+        }
+    }
+    
+    /// 按保留数量裁剪小时/天统计，只保留最近的桶
+    pub fn trim_buckets(&mut self, hourly_retention: usize, daily_retention: usize) {
+        Self::trim_bucket_list(&mut self.hourly_activity, hourly_retention);
+        Self::trim_bucket_list(&mut self.daily_activity, daily_retention);
+    }
+    
+    /// 整理统计数据：合并重复时间段的桶、去重最近请求、裁剪过期的桶
+    pub fn compact(&mut self, hourly_retention: usize, daily_retention: usize) {
+        let hourly = std::mem::take(&mut self.hourly_activity);
+        Self::merge_buckets(&mut self.hourly_activity, hourly);
+        let daily = std::mem::take(&mut self.daily_activity);
+        Self::merge_buckets(&mut self.daily_activity, daily);
+        
+        let mut seen = std::collections::HashSet::new();
+        self.recent_requests.retain(|log| seen.insert(log.id.clone()));
+        
+        self.trim_buckets(hourly_retention, daily_retention);
+    }
+    
+    fn merge_buckets(target: &mut Vec<HourlyStat>, source: Vec<HourlyStat>) {
+        for bucket in source {
+            match target.iter_mut().find(|b| b.timestamp == bucket.timestamp) {
+                Some(existing) => {
+                    existing.requests += bucket.requests;
+                    existing.input_tokens += bucket.input_tokens;
+                    existing.output_tokens += bucket.output_tokens;
+                    existing.cost += bucket.cost;
+                    for (provider, cost) in bucket.provider_costs {
+                        *existing.provider_costs.entry(provider).or_insert(0.0) += cost;
+                    }
+                    for (provider, count) in bucket.provider_requests {
+                        *existing.provider_requests.entry(provider).or_insert(0) += count;
+                    }
+                    for (provider, count) in bucket.provider_failures {
+                        *existing.provider_failures.entry(provider).or_insert(0) += count;
+                    }
+                }
+                None => target.push(bucket),
+            }
+        }
+        target.sort_by_key(|b| b.timestamp);
+    }
+    
+    fn trim_bucket_list(buckets: &mut Vec<HourlyStat>, retention: usize) {
+        if buckets.len() > retention {
+            let excess = buckets.len() - retention;
+            buckets.drain(..excess);
+        }
+    }
+    
+    /// 将一次请求计入对应时间段的桶 (bucket_secs 为桶长度)
+    fn add_to_bucket(buckets: &mut Vec<HourlyStat>, bucket_secs: u64, log: &RequestLog) {
+        let bucket_timestamp = (log.timestamp / bucket_secs) * bucket_secs;
+        let bucket = match buckets.last_mut() {
+            Some(last) if last.timestamp == bucket_timestamp => last,
+            _ => {
+                buckets.push(HourlyStat { timestamp: bucket_timestamp, ..Default::default() });
+                buckets.last_mut().unwrap()
+            }
+        };
+        bucket.requests += 1;
+        bucket.input_tokens += log.input_tokens;
+        bucket.output_tokens += log.output_tokens;
+        bucket.cost += log.cost;
+        *bucket.provider_costs.entry(log.provider.clone()).or_insert(0.0) += log.cost;
+        if !log.cancelled {
+            *bucket.provider_requests.entry(log.provider.clone()).or_insert(0) += 1;
+            if !(200..300).contains(&log.status) {
+                *bucket.provider_failures.entry(log.provider.clone()).or_insert(0) += 1;
+            }
+        }
+    }
 }
 
 pub struct StatsManager {
     stats: Arc<Mutex<GatewayStats>>,
     file_path: PathBuf,
+    // 压缩存储时的文件路径 (gateway_stats.json.gz)
+    gz_path: PathBuf,
+    compress: AtomicBool,
+    // 统计数据版本号 (每次修改递增)，用于丢弃过期的快照写入
+    version: AtomicU64,
+    // 磁盘写入锁，记录最后一次写入的版本号
+    persisted_version: Mutex<u64>,
+    // 小时/天统计保留的桶数量
+    hourly_retention: AtomicUsize,
+    daily_retention: AtomicUsize,
 }
 
 impl StatsManager {
-    pub fn new(app_dir: PathBuf) -> Self {
+    pub fn new(app_dir: PathBuf, hourly_retention: usize, daily_retention: usize, compress: bool) -> Self {
         let file_path = app_dir.join("gateway_stats.json");
-        let stats = if file_path.exists() {
-            fs::read_to_string(&file_path)
-                .ok()
-                .and_then(|s| serde_json::from_str(&s).ok())
-                .unwrap_or_default()
-        } else {
-            GatewayStats::default()
-        };
+        let gz_path = app_dir.join("gateway_stats.json.gz");
+        
+        // 优先读取当前格式的文件，不存在时回退到另一种格式 (兼容旧的 .json)
+        let (preferred, fallback) = if compress { (&gz_path, &file_path) } else { (&file_path, &gz_path) };
+        let mut stats = Self::load_file(preferred)
+            .or_else(|| Self::load_file(fallback))
+            .unwrap_or_default();
+        stats.trim_buckets(hourly_retention, daily_retention);
 
         Self {
             stats: Arc::new(Mutex::new(stats)),
             file_path,
+            gz_path,
+            compress: AtomicBool::new(compress),
+            version: AtomicU64::new(0),
+            persisted_version: Mutex::new(0),
+            hourly_retention: AtomicUsize::new(hourly_retention),
+            daily_retention: AtomicUsize::new(daily_retention),
+        }
+    }
+    
+    /// 读取统计文件 (.gz 结尾时先解压)
+    fn load_file(path: &Path) -> Option<GatewayStats> {
+        let bytes = fs::read(path).ok()?;
+        if path.extension().is_some_and(|ext| ext == "gz") {
+            let mut json = Vec::new();
+            GzDecoder::new(bytes.as_slice()).read_to_end(&mut json).ok()?;
+            serde_json::from_slice(&json).ok()
+        } else {
+            serde_json::from_slice(&bytes).ok()
         }
     }
+    
+    /// 切换统计文件的 gzip 压缩 (下次写盘时生效)
+    pub fn set_compression(&self, compress: bool) {
+        self.compress.store(compress, Ordering::SeqCst);
+    }
+    
+    /// 更新保留策略 (配置变更时调用)，下次记录请求时生效
+    pub fn set_retention(&self, hourly_retention: usize, daily_retention: usize) {
+        self.hourly_retention.store(hourly_retention, Ordering::SeqCst);
+        self.daily_retention.store(daily_retention, Ordering::SeqCst);
+    }
+    
+    fn retention(&self) -> (usize, usize) {
+        (
+            self.hourly_retention.load(Ordering::SeqCst),
+            self.daily_retention.load(Ordering::SeqCst),
+        )
+    }
 
     pub fn get_stats(&self) -> GatewayStats {
         self.stats.lock().unwrap().clone()
     }
 
     pub fn record_request(&self, log: RequestLog) {
-        let mut stats = self.stats.lock().unwrap();
+        let (snapshot, version) = {
+            let (hourly_retention, daily_retention) = self.retention();
+            let mut stats = self.stats.lock().unwrap();
+            Self::apply_request(&mut stats, log);
+            stats.trim_buckets(hourly_retention, daily_retention);
+            (stats.clone(), self.version.fetch_add(1, Ordering::SeqCst) + 1)
+        };
+        
+        // 在统计锁之外序列化和写盘，避免多个网关互相阻塞
+        self.write_snapshot(&snapshot, version);
+    }
+    
+    /// 将当前统计立即写入磁盘 (退出时调用)
+    pub fn persist(&self) {
+        let (snapshot, version) = {
+            let stats = self.stats.lock().unwrap();
+            (stats.clone(), self.version.load(Ordering::SeqCst))
+        };
+        self.write_snapshot(&snapshot, version);
+    }
+    
+    /// 将当前统计快照导出到指定文件 (先写临时文件再重命名，保证原子性)
+    pub fn export_to(&self, path: &Path) -> Result<()> {
+        let snapshot = self.get_stats();
+        let json = serde_json::to_string_pretty(&snapshot).context("Failed to serialize stats")?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, json).context("Failed to write stats export")?;
+        fs::rename(&tmp_path, path).context("Failed to finalize stats export")?;
+        Ok(())
+    }
+    
+    /// 从文件导入统计并与当前统计合并 (计数求和)
+    pub fn import_from(&self, path: &Path) -> Result<()> {
+        let content = fs::read_to_string(path).context("Failed to read stats file")?;
+        let imported: GatewayStats = serde_json::from_str(&content).context("Failed to parse stats file")?;
+        {
+            let (hourly_retention, daily_retention) = self.retention();
+            let mut stats = self.stats.lock().unwrap();
+            stats.merge(imported);
+            stats.trim_buckets(hourly_retention, daily_retention);
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+        self.persist();
+        Ok(())
+    }
+    
+    /// 整理统计数据 (裁剪旧桶并去重) 并立即写盘
+    pub fn compact(&self) {
+        {
+            let (hourly_retention, daily_retention) = self.retention();
+            let mut stats = self.stats.lock().unwrap();
+            stats.compact(hourly_retention, daily_retention);
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+        self.persist();
+    }
+    
+    fn write_snapshot(&self, snapshot: &GatewayStats, version: u64) {
+        let mut persisted_version = self.persisted_version.lock().unwrap();
+        // 已有更新的快照写入，跳过过期数据
+        if version < *persisted_version {
+            return;
+        }
+        let compress = self.compress.load(Ordering::SeqCst);
+        let (path, stale_path) = if compress { (&self.gz_path, &self.file_path) } else { (&self.file_path, &self.gz_path) };
+        match Self::encode(snapshot, compress) {
+            Ok(data) => {
+                if let Err(e) = fs::write(path, data) {
+                    eprintln!("Failed to save stats: {}", e);
+                } else {
+                    *persisted_version = version;
+                    // 切换格式后移除另一种格式的旧文件，避免下次启动读到过期数据
+                    if stale_path.exists() {
+                        let _ = fs::remove_file(stale_path);
+                    }
+                }
+            }
+            Err(e) => eprintln!("Failed to serialize stats: {}", e),
+        }
+    }
+    
+    /// 序列化统计：压缩时使用紧凑 JSON + gzip，否则为格式化 JSON
+    fn encode(snapshot: &GatewayStats, compress: bool) -> Result<Vec<u8>> {
+        if compress {
+            let json = serde_json::to_vec(snapshot)?;
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&json)?;
+            Ok(encoder.finish()?)
+        } else {
+            Ok(serde_json::to_vec_pretty(snapshot)?)
+        }
+    }
+    
+    fn apply_request(stats: &mut GatewayStats, log: RequestLog) {
         
         stats.total_requests += 1;
         stats.total_input_tokens += log.input_tokens as u64;
         stats.total_output_tokens += log.output_tokens as u64;
         stats.total_cost += log.cost;
+        if !(200..300).contains(&log.status) {
+            stats.cost_from_failures += log.cost;
+        }
         
         // 按 API 类型统计
         match log.api_type.as_str() {
@@ -210,21 +779,23 @@ impl StatsManager {
             _ => {}
         }
         
-        // 更新供应商统计
-        let is_success = log.status >= 200 && log.status < 300;
-        let provider_stats = stats.provider_stats
-            .entry(log.provider.clone())
-            .or_insert_with(|| ProviderStats::new(log.provider.clone(), log.provider.clone()));
-        
-        provider_stats.record_request(
-            is_success,
-            log.duration_ms,
-            log.input_tokens,
-            log.output_tokens,
-            log.cost,
-            log.timestamp,
-            if is_success { None } else { log.error_message.clone().or_else(|| Some(format!("HTTP {}", log.status))) }
-        );
+        // 更新供应商统计 (被取消的对冲请求不影响供应商健康状态)
+        if !log.cancelled {
+            let is_success = log.status >= 200 && log.status < 300;
+            let provider_stats = stats.provider_stats
+                .entry(log.provider.clone())
+                .or_insert_with(|| ProviderStats::new(log.provider.clone(), log.provider.clone()));
+            
+            provider_stats.record_request(
+                is_success,
+                log.duration_ms,
+                log.input_tokens,
+                log.output_tokens,
+                log.cost,
+                log.timestamp,
+                if is_success { None } else { log.error_message.clone().or_else(|| Some(format!("HTTP {}", log.status))) }
+            );
+        }
         
         // 更新 recent_requests
         stats.recent_requests.push_front(log.clone());
@@ -232,44 +803,107 @@ impl StatsManager {
             stats.recent_requests.pop_back();
         }
 
-        // 更新 hourly_activity
-        let hour_timestamp = (log.timestamp / 3600) * 3600;
-        if let Some(last) = stats.hourly_activity.last_mut() {
-            if last.timestamp == hour_timestamp {
-                last.requests += 1;
-                last.input_tokens += log.input_tokens;
-                last.output_tokens += log.output_tokens;
-                last.cost += log.cost;
-            } else {
-                stats.hourly_activity.push(HourlyStat {
-                    timestamp: hour_timestamp,
-                    requests: 1,
-                    input_tokens: log.input_tokens,
-                    output_tokens: log.output_tokens,
-                    cost: log.cost,
-                });
+        // 更新小时/天统计 (保留数量由调用方裁剪)
+        GatewayStats::add_to_bucket(&mut stats.hourly_activity, 3600, &log);
+        GatewayStats::add_to_bucket(&mut stats.daily_activity, 86400, &log);
+    }
+    
+    /// 按条件筛选最近请求 (最新的在前)
+    /// status_class 为状态码类别，2 / 200 均表示 2xx
+    pub fn query_recent(
+        &self,
+        api_type: Option<&str>,
+        status_class: Option<u16>,
+        provider: Option<&str>,
+        limit: usize,
+    ) -> Vec<RequestLog> {
+        let status_class = status_class.map(|c| if c >= 100 { c / 100 } else { c });
+        let stats = self.stats.lock().unwrap();
+        stats.recent_requests
+            .iter()
+            .filter(|log| api_type.is_none_or(|t| log.api_type == t))
+            .filter(|log| status_class.is_none_or(|c| log.status / 100 == c))
+            .filter(|log| provider.is_none_or(|p| log.provider == p))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+    
+    /// 流结束后回填请求的输出 token 数和费用 (同步更新全局、供应商和小时/天统计)
+    /// 只更新内存中的统计，由下一次写盘 (下一个请求或退出时的 persist) 一并保存
+    pub fn backfill_output_usage(&self, log_id: &str, output_tokens: u32, cost: f64, estimated: bool) {
+        {
+            let mut guard = self.stats.lock().unwrap();
+            let stats = &mut *guard;
+            let Some(log) = stats.recent_requests.iter_mut().find(|log| log.id == log_id) else {
+                return;
+            };
+            let token_delta = output_tokens as i64 - log.output_tokens as i64;
+            let cost_delta = cost - log.cost;
+            log.output_tokens = output_tokens;
+            log.cost = cost;
+            log.estimated = estimated;
+            let (provider, timestamp, cancelled) = (log.provider.clone(), log.timestamp, log.cancelled);
+            
+            let apply = |total: u64| (total as i64 + token_delta).max(0) as u64;
+            stats.total_output_tokens = apply(stats.total_output_tokens);
+            stats.total_cost += cost_delta;
+            if !cancelled {
+                if let Some(provider_stats) = stats.provider_stats.get_mut(&provider) {
+                    provider_stats.total_output_tokens = apply(provider_stats.total_output_tokens);
+                    provider_stats.total_cost += cost_delta;
+                }
             }
-        } else {
-            stats.hourly_activity.push(HourlyStat {
-                timestamp: hour_timestamp,
-                requests: 1,
-                input_tokens: log.input_tokens,
-                output_tokens: log.output_tokens,
-                cost: log.cost,
-            });
+            for (buckets, bucket_secs) in [(&mut stats.hourly_activity, 3600), (&mut stats.daily_activity, 86400)] {
+                let bucket_timestamp = (timestamp / bucket_secs) * bucket_secs;
+                if let Some(bucket) = buckets.iter_mut().rev().find(|b| b.timestamp == bucket_timestamp) {
+                    bucket.output_tokens = apply(bucket.output_tokens as u64) as u32;
+                    bucket.cost += cost_delta;
+                    *bucket.provider_costs.entry(provider.clone()).or_insert(0.0) += cost_delta;
+                }
+            }
+            self.version.fetch_add(1, Ordering::SeqCst);
         }
+    }
+    
+    /// 按最近 7 天的日统计外推 30 天的支出 (总计及各供应商)，没有任何日统计时返回 None
+    pub fn project_monthly_cost(&self, now: u64) -> Option<MonthlyCostProjection> {
+        const DAY_SECS: u64 = 86400;
+        let stats = self.stats.lock().unwrap();
         
-        // 保留最近24小时
-        if stats.hourly_activity.len() > 24 {
-            stats.hourly_activity.remove(0);
-        }
-
-        // 持久化
-        if let Ok(json) = serde_json::to_string_pretty(&*stats) {
-            if let Err(e) = fs::write(&self.file_path, json) {
-                eprintln!("Failed to save stats: {}", e);
+        // 最近 7 个自然日 (含今天) 的日统计
+        let cutoff = (now / DAY_SECS).saturating_sub(6) * DAY_SECS;
+        let buckets: Vec<&HourlyStat> = stats.daily_activity.iter().filter(|b| b.timestamp >= cutoff).collect();
+        let window_start = buckets.iter().map(|b| b.timestamp).min()?;
+        
+        let mut window_cost = 0.0;
+        let mut provider_costs: HashMap<String, f64> = HashMap::new();
+        for bucket in &buckets {
+            window_cost += bucket.cost;
+            for (provider, cost) in &bucket.provider_costs {
+                *provider_costs.entry(provider.clone()).or_insert(0.0) += cost;
             }
         }
+        
+        // 窗口至少按 1 小时计算，避免刚开始统计时外推出极端值
+        let window_secs = now.saturating_sub(window_start).max(3600);
+        let window_days = window_secs as f64 / DAY_SECS as f64;
+        let scale = 30.0 / window_days;
+        Some(MonthlyCostProjection {
+            total: window_cost * scale,
+            providers: provider_costs.into_iter().map(|(name, cost)| (name, cost * scale)).collect(),
+            window_start,
+            window_end: now,
+            window_days,
+            window_cost,
+            low_confidence: window_secs < DAY_SECS,
+        })
+    }
+    
+    /// 按 ID 查找最近请求
+    pub fn find_recent(&self, id: &str) -> Option<RequestLog> {
+        let stats = self.stats.lock().unwrap();
+        stats.recent_requests.iter().find(|log| log.id == id).cloned()
     }
     
     pub fn record_cache_hit(&self) {
@@ -282,12 +916,191 @@ impl StatsManager {
         stats.cache_misses += 1;
     }
     
+    /// 获取各供应商的 p95 延迟及成功样本数 (按供应商名称)
+    pub fn provider_latencies(&self) -> HashMap<String, (u64, u64)> {
+        let stats = self.stats.lock().unwrap();
+        stats.provider_stats
+            .iter()
+            .map(|(name, ps)| (name.clone(), (ps.p95_latency_ms, ps.successful_requests)))
+            .collect()
+    }
+    
+    /// 获取各供应商的可靠性评分 (按供应商名称)
+    pub fn provider_reliability(&self) -> HashMap<String, f64> {
+        let stats = self.stats.lock().unwrap();
+        stats.provider_stats
+            .iter()
+            .map(|(name, ps)| (name.clone(), ps.reliability_score))
+            .collect()
+    }
+    
+    /// 获取各供应商的动态权重系数 (按供应商名称)
+    pub fn provider_weight_factors(&self) -> HashMap<String, f64> {
+        let stats = self.stats.lock().unwrap();
+        stats.provider_stats
+            .iter()
+            .map(|(name, ps)| (name.clone(), ps.weight_factor))
+            .collect()
+    }
+    
+    /// 根据滚动成功率判断是否需要降级 (成功率低于阈值时标记为不健康并返回 true)
+    pub fn check_demotion(&self, provider_name: &str, window: usize, min_success_rate: f64) -> bool {
+        let mut stats = self.stats.lock().unwrap();
+        let Some(provider_stats) = stats.provider_stats.get_mut(provider_name) else {
+            return false;
+        };
+        match provider_stats.rolling_success_rate(window) {
+            Some(rate) if rate < min_success_rate => {
+                provider_stats.is_healthy = false;
+                // 重新开始滚动窗口：否则冷却后的探测即使成功，窗口内仍是旧的失败记录，会立即再次降级
+                provider_stats.recent_outcomes.clear();
+                true
+            }
+            _ => false,
+        }
+    }
+    
+    /// 记录供应商进入冷却 (与同一供应商上一段冷却重叠时合并)
+    pub fn record_cooldown(&self, provider_name: &str, start: u64, end: u64) {
+        let mut stats = self.stats.lock().unwrap();
+        let last = stats.cooldown_periods.iter_mut().rev().find(|p| p.provider == provider_name);
+        match last {
+            Some(period) if start <= period.end => period.end = period.end.max(end),
+            _ => {
+                stats.cooldown_periods.push(CooldownPeriod { provider: provider_name.to_string(), start, end });
+                GatewayStats::trim_cooldown_periods(&mut stats.cooldown_periods);
+            }
+        }
+    }
+    
+    /// 供应商提前恢复 (请求成功或冷却被重置)：截断尚未结束的冷却期
+    pub fn end_cooldown(&self, provider_name: &str, now: u64) {
+        let mut stats = self.stats.lock().unwrap();
+        if let Some(period) = stats.cooldown_periods.iter_mut().rev().find(|p| p.provider == provider_name) {
+            if period.end > now {
+                period.end = now.max(period.start);
+            }
+        }
+    }
+    
+    /// 计算供应商在 [now - period_secs, now] 内的 SLA
+    /// 周期不超过小时统计保留范围时按小时桶统计，否则按天桶统计
+    pub fn provider_sla(&self, provider_id: &str, provider_name: &str, period_secs: u64, now: u64) -> ProviderSla {
+        let (hourly_retention, _) = self.retention();
+        let stats = self.stats.lock().unwrap();
+        let period_start = now.saturating_sub(period_secs);
+        
+        let (buckets, bucket_secs) = if period_secs <= hourly_retention as u64 * 3600 {
+            (&stats.hourly_activity, 3600)
+        } else {
+            (&stats.daily_activity, 86400)
+        };
+        let first_bucket = (period_start / bucket_secs) * bucket_secs;
+        let mut total_requests = 0u64;
+        let mut failed_requests = 0u64;
+        let mut error_rate_trend = Vec::new();
+        for bucket in buckets.iter().filter(|b| b.timestamp >= first_bucket && b.timestamp <= now) {
+            let requests = bucket.provider_requests.get(provider_name).copied().unwrap_or(0);
+            let failures = bucket.provider_failures.get(provider_name).copied().unwrap_or(0);
+            total_requests += requests as u64;
+            failed_requests += failures as u64;
+            if requests > 0 {
+                error_rate_trend.push(ErrorRatePoint {
+                    timestamp: bucket.timestamp,
+                    requests,
+                    error_rate: failures as f64 / requests as f64,
+                });
+            }
+        }
+        
+        let cooldown_secs: u64 = stats.cooldown_periods.iter()
+            .filter(|p| p.provider == provider_name)
+            .map(|p| p.end.min(now).saturating_sub(p.start.max(period_start)))
+            .sum::<u64>()
+            .min(period_secs);
+        let availability = if period_secs == 0 {
+            1.0
+        } else {
+            1.0 - cooldown_secs as f64 / period_secs as f64
+        };
+        
+        ProviderSla {
+            provider_id: provider_id.to_string(),
+            provider_name: provider_name.to_string(),
+            period_start,
+            period_end: now,
+            total_requests,
+            failed_requests,
+            success_rate: (total_requests > 0)
+                .then(|| (total_requests - failed_requests) as f64 / total_requests as f64),
+            availability,
+            cooldown_secs,
+            error_rate_trend,
+        }
+    }
+    
+    /// 记录主动健康检查的结果 (不计入请求统计)：
+    /// 检查失败时标记为不健康；检查成功时恢复健康并清零连续失败次数
+    pub fn set_provider_health(&self, provider_name: &str, healthy: bool) {
+        let mut stats = self.stats.lock().unwrap();
+        let provider_stats = stats.provider_stats
+            .entry(provider_name.to_string())
+            .or_insert_with(|| ProviderStats::new(provider_name.to_string(), provider_name.to_string()));
+        provider_stats.is_healthy = healthy;
+        if healthy {
+            provider_stats.consecutive_failures = 0;
+        }
+    }
+    
     /// 重置供应商健康状态（当冷却解除时调用）
     pub fn reset_provider_health(&self, provider_name: &str) {
         let mut stats = self.stats.lock().unwrap();
         if let Some(provider_stats) = stats.provider_stats.get_mut(provider_name) {
             provider_stats.is_healthy = true;
             provider_stats.consecutive_failures = 0;
+            provider_stats.recent_outcomes.clear();
+            provider_stats.weight_factor = default_weight_factor();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn latency_percentiles_stay_in_range_for_small_sample_counts() {
+        for (n, p50, p95, p99) in [(1, 1, 1, 1), (2, 2, 2, 2), (20, 11, 20, 20), (100, 51, 96, 100)] {
+            let mut stats = ProviderStats::new("p".to_string(), "p".to_string());
+            for latency in 1..=n {
+                stats.record_request(true, latency, 0, 0, 0.0, 0, None);
+            }
+            assert_eq!(stats.min_latency_ms, 1);
+            assert_eq!(stats.max_latency_ms, n);
+            assert_eq!((stats.p50_latency_ms, stats.p95_latency_ms, stats.p99_latency_ms), (p50, p95, p99), "n = {}", n);
         }
     }
+
+    
+    #[test]
+    fn demotion_restarts_the_rolling_window() {
+        let dir = std::env::temp_dir().join(format!("vibehub-stats-test-{}", uuid::Uuid::new_v4().simple()));
+        let manager = StatsManager::new(dir.clone(), 24, 30, false);
+        {
+            let mut stats = manager.stats.lock().unwrap();
+            let mut provider = ProviderStats::new("p".to_string(), "p".to_string());
+            for i in 0..4 {
+                provider.record_request(i == 0, 10, 0, 0, 0.0, 0, None);
+            }
+            stats.provider_stats.insert("p".to_string(), provider);
+        }
+        assert!(manager.check_demotion("p", 4, 0.5));
+        
+        // 冷却结束后的探测成功：窗口已重新开始，不会因为旧的失败记录立即再次降级
+        manager.stats.lock().unwrap().provider_stats.get_mut("p").unwrap().record_request(true, 10, 0, 0, 0.0, 0, None);
+        assert!(!manager.check_demotion("p", 4, 0.5));
+        assert!(!manager.check_demotion("p", 0, 0.5));
+        
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,38 @@
+// 精确的 token 计数：基于 tiktoken BPE 编码 (需要开启 tiktoken feature)
+//
+// 供应商的 tokenizer 配置可以是编码名称 (如 "cl100k_base")，或 "auto" 按模型名推断编码。
+// 未开启 feature、或模型没有已知编码时返回 None，由调用方按字符比例估算。
+
+/// 支持的 tokenizer 配置值
+pub const TOKENIZER_NAMES: &[&str] = &["auto", "o200k_base", "cl100k_base", "p50k_base", "p50k_edit", "r50k_base"];
+
+/// 按 tokenizer 配置计算文本的 token 数
+#[cfg(feature = "tiktoken")]
+pub fn count_tokens(text: &str, tokenizer: &str, model: &str) -> Option<u32> {
+    use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+    
+    let encoding = match tokenizer {
+        "auto" => get_tokenizer(model)?,
+        "o200k_base" => Tokenizer::O200kBase,
+        "cl100k_base" => Tokenizer::Cl100kBase,
+        "p50k_base" => Tokenizer::P50kBase,
+        "p50k_edit" => Tokenizer::P50kEdit,
+        "r50k_base" => Tokenizer::R50kBase,
+        _ => return None,
+    };
+    // 编码表加载开销较大，使用 tiktoken-rs 内置的单例
+    let bpe = match encoding {
+        Tokenizer::O200kBase => tiktoken_rs::o200k_base_singleton(),
+        Tokenizer::Cl100kBase => tiktoken_rs::cl100k_base_singleton(),
+        Tokenizer::P50kBase => tiktoken_rs::p50k_base_singleton(),
+        Tokenizer::P50kEdit => tiktoken_rs::p50k_edit_singleton(),
+        Tokenizer::R50kBase | Tokenizer::Gpt2 => tiktoken_rs::r50k_base_singleton(),
+    };
+    Some(bpe.encode_with_special_tokens(text).len().min(u32::MAX as usize) as u32)
+}
+
+/// 未开启 tiktoken feature：总是回退到字符比例估算
+#[cfg(not(feature = "tiktoken"))]
+pub fn count_tokens(_text: &str, _tokenizer: &str, _model: &str) -> Option<u32> {
+    None
+}
@@ -1,37 +1,91 @@
 use crate::models::{Project, TagConfig, TagCategory};
 use anyhow::{anyhow, Result};
-use std::process::Command;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::{Child, Command};
+
+/// The command a launch would execute, as shown by `preview_launch`
+#[derive(Debug, Clone, Serialize)]
+pub struct LaunchPreview {
+    pub program: String,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+    pub cwd: Option<String>,
+}
+
+/// Whether spawned children are the launched tools themselves. On Windows (`cmd /C start`) and
+/// macOS (`open`) the child is a short-lived wrapper that exits right away, so tracking and killing
+/// only work on Linux and other platforms where the executable is run directly.
+const TRACKS_LAUNCHED_PROCESSES: bool = cfg!(not(any(target_os = "windows", target_os = "macos")));
+
+/// A process spawned by `launch`, kept in `AppState` so it can be listed and terminated later
+pub struct LaunchedProcess {
+    pub project_id: String,
+    pub config_name: String,
+    pub child: Child,
+}
+
+/// A launched process as returned by `list_launched_processes`
+#[derive(Debug, Clone, Serialize)]
+pub struct LaunchedProcessInfo {
+    pub project_id: String,
+    pub config_name: String,
+    pub pid: u32,
+    pub alive: bool,
+}
+
+impl LaunchedProcess {
+    /// Whether the process is still running (reaps it if it has exited)
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+    
+    pub fn info(&mut self) -> LaunchedProcessInfo {
+        LaunchedProcessInfo {
+            project_id: self.project_id.clone(),
+            config_name: self.config_name.clone(),
+            pid: self.child.id(),
+            alive: self.is_alive(),
+        }
+    }
+}
 
 pub struct Launcher;
 
 impl Launcher {
+    /// Spawn the configured tools. Returns the processes that can be tracked
+    /// (always empty on Windows and macOS, see `TRACKS_LAUNCHED_PROCESSES`).
     pub fn launch(
         project: &Project,
         configs: &[(TagConfig, TagCategory)],
-    ) -> Result<()> {
-        let mut success = false;
+    ) -> Result<Vec<LaunchedProcess>> {
+        let mut launched = Vec::new();
+        let mut spawned = 0;
 
         for (config, category) in configs {
             if let Some(executable) = &config.executable {
-                #[cfg(target_os = "windows")]
-                if Self::launch_windows(executable, config, category, &project.path)? {
-                    success = true;
-                }
+                let mut cmd = Self::build_command(executable, config, category, &project.path);
                 
-                #[cfg(target_os = "macos")]
-                if Self::launch_macos(executable, config, category, &project.path)? {
-                    success = true;
-                }
-                
-                #[cfg(target_os = "linux")]
-                if Self::launch_linux(executable, config, category, &project.path)? {
-                    success = true;
+                let child = cmd.spawn()?;
+                spawned += 1;
+                if TRACKS_LAUNCHED_PROCESSES {
+                    // Name the process after the executable's file name (e.g. "code" for a full path to code)
+                    let config_name = Path::new(executable)
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_else(|| executable.clone());
+                    launched.push(LaunchedProcess {
+                        project_id: project.id.clone(),
+                        config_name,
+                        child,
+                    });
                 }
             }
         }
 
-        if success {
-            Ok(())
+        if spawned > 0 {
+            Ok(launched)
         } else if configs.is_empty() {
             Err(anyhow!("No launch configuration found for the selected tags. Please configure the tags or use custom launch."))
         } else {
@@ -39,10 +93,48 @@ impl Launcher {
         }
     }
 
-    #[cfg(target_os = "windows")]
-    fn launch_windows(executable: &str, config: &TagConfig, category: &TagCategory, project_path: &str) -> Result<bool> {
-        println!("Launching on Windows: exe={}, path={}, category={:?}", executable, project_path, category);
+    /// Build the commands `launch` would run, without spawning anything
+    pub fn preview(
+        project: &Project,
+        configs: &[(TagConfig, TagCategory)],
+    ) -> Vec<LaunchPreview> {
+        configs
+            .iter()
+            .filter_map(|(config, category)| {
+                let executable = config.executable.as_ref()?;
+                let cmd = Self::build_command(executable, config, category, &project.path);
+                Some(LaunchPreview {
+                    program: cmd.get_program().to_string_lossy().to_string(),
+                    args: cmd.get_args().map(|a| a.to_string_lossy().to_string()).collect(),
+                    env: cmd
+                        .get_envs()
+                        .filter_map(|(k, v)| Some((k.to_string_lossy().to_string(), v?.to_string_lossy().to_string())))
+                        .collect(),
+                    cwd: cmd.get_current_dir().map(|d| d.to_string_lossy().to_string()),
+                })
+            })
+            .collect()
+    }
+
+    fn build_command(executable: &str, config: &TagConfig, category: &TagCategory, project_path: &str) -> Command {
+        #[cfg(target_os = "windows")]
+        let cmd = Self::build_windows_command(executable, config, category, project_path);
+        
+        #[cfg(target_os = "macos")]
+        let cmd = Self::build_macos_command(executable, config, category, project_path);
         
+        #[cfg(target_os = "linux")]
+        let cmd = Self::build_linux_command(executable, config, category, project_path);
+        
+        // Other platforms (BSDs etc.) have no desktop-specific launcher; run the executable directly like Linux
+        #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+        let cmd = Self::build_linux_command(executable, config, category, project_path);
+        
+        cmd
+    }
+
+    #[cfg(target_os = "windows")]
+    fn build_windows_command(executable: &str, config: &TagConfig, category: &TagCategory, project_path: &str) -> Command {
         // Unified launch strategy using `cmd /C start`
         // This ensures:
         // 1. Environment variables are correctly inherited
@@ -80,14 +172,11 @@ impl Launcher {
             }
         }
         
-        println!("Executing command: {:?}", cmd);
-        
-        let child = cmd.spawn()?;
-        Ok(child.id() > 0)
+        cmd
     }
 
     #[cfg(target_os = "macos")]
-    fn launch_macos(executable: &str, config: &TagConfig, category: &TagCategory, project_path: &str) -> Result<bool> {
+    fn build_macos_command(executable: &str, config: &TagConfig, category: &TagCategory, project_path: &str) -> Command {
         // MacOS implementation (simplified for now, focusing on Windows as requested)
         let mut cmd = if executable.ends_with(".app") {
             let mut c = Command::new("open");
@@ -123,12 +212,11 @@ impl Launcher {
         
         cmd.current_dir(project_path);
         
-        let child = cmd.spawn()?;
-        Ok(child.id() > 0)
+        cmd
     }
 
-    #[cfg(target_os = "linux")]
-    fn launch_linux(executable: &str, config: &TagConfig, category: &TagCategory, project_path: &str) -> Result<bool> {
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    fn build_linux_command(executable: &str, config: &TagConfig, category: &TagCategory, project_path: &str) -> Command {
         // Linux implementation
         let mut cmd = Command::new(executable);
         
@@ -156,7 +244,6 @@ impl Launcher {
         
         cmd.current_dir(project_path);
         
-        let child = cmd.spawn()?;
-        Ok(child.id() > 0)
+        cmd
     }
 }
@@ -26,24 +26,37 @@ fn main() {
         })
         .manage(AppState {
             storage: Mutex::new(storage),
+            processes: Mutex::new(Vec::new()),
         })
         .invoke_handler(tauri::generate_handler![
             commands::load_config,
             commands::save_config,
             commands::scan_workspace,
+            commands::import_projects_from_paths,
             commands::add_workspace,
+            commands::update_workspace,
             commands::remove_workspace,
             commands::update_project,
             commands::refresh_project,
             commands::delete_project,
+            commands::merge_projects,
             commands::add_tag,
             commands::update_tag,
+            commands::rename_tag,
             commands::delete_tag,
+            commands::get_tag_tree,
+            commands::get_tag_usage,
             commands::launch_tool,
+            commands::open_project_default,
             commands::launch_custom,
+            commands::list_launched_processes,
+            commands::kill_launched_process,
+            commands::preview_launch,
             commands::open_in_explorer,
             commands::open_terminal,
             commands::record_project_open,
+            commands::clear_recent_projects,
+            commands::clear_project_history,
             commands::toggle_project_star,
             commands::initialize_default_configs,
             commands::set_theme,
@@ -51,8 +64,38 @@ fn main() {
             commands::check_for_updates,
             gateway::get_gateway_config,
             gateway::save_gateway_config,
+            gateway::get_gateway_config_json,
+            gateway::set_gateway_config_json,
+            gateway::get_gateway_status,
             gateway::get_gateway_stats,
+            gateway::query_recent_requests,
+            gateway::get_provider_health,
+            gateway::get_provider_sla,
+            gateway::get_effective_provider,
+            gateway::export_providers,
+            gateway::import_providers,
+            gateway::drain_provider,
+            gateway::undrain_provider,
+            gateway::clear_provider_cooldown,
+            gateway::request_as_curl,
+            gateway::replay_request,
+            gateway::project_monthly_cost,
+            gateway::compact_stats,
+            gateway::export_gateway_stats,
+            gateway::import_gateway_stats,
+            gateway::preview_conversion,
+            gateway::estimate_request_cost,
+            gateway::benchmark_providers,
+            gateway::get_cache_stats,
+            gateway::clear_gateway_cache,
+            gateway::simulate_provider_status,
+            gateway::simulate_request_log,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                gateway::shutdown(app);
+            }
+        });
 }
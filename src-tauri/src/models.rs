@@ -9,6 +9,12 @@ pub struct AppConfig {
     pub projects: Vec<Project>,
     pub theme: String,
     pub recent_projects: Vec<String>,
+    /// Global fallback for `open_project_default` (a tag id whose launch config is used)
+    #[serde(default)]
+    pub default_launch_config: Option<String>,
+    /// Persist config.json as compact JSON instead of pretty-printed (both forms load the same)
+    #[serde(default)]
+    pub compact_json: bool,
 }
 
 impl Default for AppConfig {
@@ -19,6 +25,8 @@ impl Default for AppConfig {
             projects: Vec::new(),
             theme: "auto".to_string(),
             recent_projects: Vec::new(),
+            default_launch_config: None,
+            compact_json: false,
         }
     }
 }
@@ -39,6 +47,26 @@ pub struct Project {
     #[serde(default)]
     pub tech_stack: Vec<String>,
     pub metadata: ProjectMetadata,
+    /// Tag id whose launch config is used when the project is opened directly
+    #[serde(default)]
+    pub default_launch_config: Option<String>,
+}
+
+/// Projects found by a workspace scan, plus per-path problems that were skipped
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ScanResult {
+    pub projects: Vec<Project>,
+    pub warnings: Vec<String>,
+}
+
+/// Outcome of `import_projects_from_paths`
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ImportSummary {
+    pub added: Vec<Project>,
+    /// Paths that were already known projects
+    pub skipped: Vec<String>,
+    /// Paths that couldn't be imported, with the reason
+    pub errors: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,6 +99,15 @@ pub struct Workspace {
     pub path: String,
     pub auto_scan: bool,
     pub created_at: DateTime<Utc>,
+    /// Default launch config for projects in this workspace without their own
+    #[serde(default)]
+    pub default_launch_config: Option<String>,
+    /// Scan depth used when none is passed explicitly (1 = immediate children)
+    #[serde(default)]
+    pub scan_max_depth: Option<usize>,
+    /// Directory names to skip when scanning this workspace
+    #[serde(default)]
+    pub scan_ignore: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,9 +117,62 @@ pub struct Tag {
     pub color: String,
     pub category: TagCategory,
     pub config: Option<TagConfig>,
+    /// Parent tag id for nested tags ("Work > ClientA"); `None` for top-level tags
+    #[serde(default)]
+    pub parent_id: Option<String>,
+}
+
+/// How many projects reference a tag, as returned by `get_tag_usage`
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TagUsage {
+    pub count: usize,
+    /// Only filled in when the caller asks for project ids
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_ids: Option<Vec<String>>,
+}
+
+/// A tag with its children, as returned by `get_tag_tree`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagNode {
+    #[serde(flatten)]
+    pub tag: Tag,
+    pub children: Vec<TagNode>,
 }
 
 impl Tag {
+    /// Build the nested tag tree; tags whose parent is missing are treated as roots
+    pub fn build_tree(tags: &[Tag]) -> Vec<TagNode> {
+        fn children_of(tags: &[Tag], parent_id: &str) -> Vec<TagNode> {
+            tags.iter()
+                .filter(|t| t.parent_id.as_deref() == Some(parent_id))
+                .map(|t| TagNode { tag: t.clone(), children: children_of(tags, &t.id) })
+                .collect()
+        }
+        
+        tags.iter()
+            .filter(|t| match &t.parent_id {
+                Some(parent_id) => !tags.iter().any(|p| &p.id == parent_id),
+                None => true,
+            })
+            .map(|t| TagNode { tag: t.clone(), children: children_of(tags, &t.id) })
+            .collect()
+    }
+    
+    /// Ids of all descendants of `tag_id` (children, grandchildren, ...)
+    pub fn descendant_ids(tags: &[Tag], tag_id: &str) -> Vec<String> {
+        let mut result = Vec::new();
+        let mut pending = vec![tag_id.to_string()];
+        while let Some(current) = pending.pop() {
+            for child in tags.iter().filter(|t| t.parent_id.as_deref() == Some(current.as_str())) {
+                if !result.contains(&child.id) && child.id != tag_id {
+                    result.push(child.id.clone());
+                    pending.push(child.id.clone());
+                }
+            }
+        }
+        result
+    }
+    
     pub fn default_tags() -> Vec<Tag> {
         vec![
             Tag {
@@ -91,6 +181,7 @@ impl Tag {
                 color: "#2EAADC".to_string(),
                 category: TagCategory::Custom,
                 config: None,
+                parent_id: None,
             },
             Tag {
                 id: uuid::Uuid::new_v4().to_string(),
@@ -98,6 +189,7 @@ impl Tag {
                 color: "#448361".to_string(),
                 category: TagCategory::Custom,
                 config: None,
+                parent_id: None,
             },
             Tag {
                 id: uuid::Uuid::new_v4().to_string(),
@@ -105,6 +197,7 @@ impl Tag {
                 color: "#D44C47".to_string(),
                 category: TagCategory::Custom,
                 config: None,
+                parent_id: None,
             },
         ]
     }
@@ -1,5 +1,5 @@
-use crate::models::{Project, ProjectMetadata, ProjectType};
-use anyhow::Result;
+use crate::models::{Project, ProjectMetadata, ProjectType, ScanResult};
+use anyhow::{anyhow, Result};
 use std::fs;
 use std::path::Path;
 
@@ -7,14 +7,39 @@ use std::path::Path;
 pub struct Scanner;
 
 impl Scanner {
-    pub fn scan_directory(path: &str, _max_depth: usize) -> Result<Vec<Project>> {
-        let mut projects = Vec::new();
+    /// Scan `path` for projects, descending up to `max_depth` levels (1 = immediate children only).
+    /// Directories named in `ignore` are skipped along with hidden folders and build artifacts.
+    /// Only an unreadable root is an error; entries that can't be read are skipped
+    /// and reported in `warnings` so one locked folder doesn't lose the rest of the scan.
+    pub fn scan_directory(path: &str, max_depth: usize, ignore: &[String]) -> Result<ScanResult> {
+        let mut result = ScanResult::default();
         let abs_path = fs::canonicalize(path)?;
+        fs::read_dir(&abs_path)?;
         
-        // User requested to just take all directories under the scanned directory
-        // So we iterate immediate children only
-        for entry in fs::read_dir(abs_path)? {
-            let entry = entry?;
+        Self::scan_level(&abs_path, max_depth.max(1), ignore, &mut result);
+        Ok(result)
+    }
+
+    /// Scan the children of `dir`. Every child directory is a project, except that while depth
+    /// remains, directories without a recognised project type are treated as containers and
+    /// searched instead (falling back to the directory itself if nothing is found inside).
+    fn scan_level(dir: &Path, depth: usize, ignore: &[String], result: &mut ScanResult) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                result.warnings.push(format!("{}: {}", dir.display(), e));
+                return;
+            }
+        };
+        
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    result.warnings.push(format!("{}: {}", dir.display(), e));
+                    continue;
+                }
+            };
             let path = entry.path();
             
             if path.is_dir() {
@@ -28,17 +53,30 @@ impl Scanner {
                    name == "build" ||
                    name == "venv" ||
                    name == "bin" ||
-                   name == "obj" {
+                   name == "obj" ||
+                   ignore.iter().any(|i| i.as_str() == name) {
+                    continue;
+                }
+
+                // Skip directories we can't list (e.g. permission denied)
+                if let Err(e) = fs::read_dir(&path) {
+                    result.warnings.push(format!("{}: {}", path.display(), e));
                     continue;
                 }
 
+                if depth > 1 && Self::detect_project_type(&path).is_none() {
+                    let found_before = result.projects.len();
+                    Self::scan_level(&path, depth - 1, ignore, result);
+                    if result.projects.len() > found_before {
+                        continue;
+                    }
+                }
+
                 if let Some(project) = Self::detect_project(&path) {
-                    projects.push(project);
+                    result.projects.push(project);
                 }
             }
         }
-        
-        Ok(projects)
     }
 
     pub fn refresh_project(project: &mut Project) {
@@ -54,6 +92,15 @@ impl Scanner {
         }
     }
 
+    /// Build a project from a single directory (used when importing paths directly)
+    pub fn detect_path(path: &str) -> Result<Project> {
+        let abs_path = fs::canonicalize(path)?;
+        if !abs_path.is_dir() {
+            return Err(anyhow!("Not a directory"));
+        }
+        Self::detect_project(&abs_path).ok_or_else(|| anyhow!("Unable to determine project name"))
+    }
+
     fn detect_project(path: &Path) -> Option<Project> {
         // We now accept any directory as a project, defaulting to "Other" if no specific type detected
         let project_type = Self::detect_project_type(path).unwrap_or(ProjectType::Other);
@@ -87,6 +134,7 @@ impl Scanner {
             theme_color: None,
             tech_stack: Vec::new(),
             metadata,
+            default_launch_config: None,
         })
     }
 
@@ -41,8 +41,12 @@ impl Storage {
     }
 
     pub fn save_config(&self, config: &AppConfig) -> Result<()> {
-        let content = serde_json::to_string_pretty(config)
-            .context("Failed to serialize config")?;
+        let content = if config.compact_json {
+            serde_json::to_string(config)
+        } else {
+            serde_json::to_string_pretty(config)
+        }
+        .context("Failed to serialize config")?;
         
         fs::write(&self.config_path, content)
             .context("Failed to write config file")?;
@@ -1,5 +1,5 @@
 import { invoke } from '@tauri-apps/api/core';
-import { AppConfig, Project, Workspace, Tag } from '../types';
+import { AppConfig, LaunchedProcess, Project, ScanResult, Workspace, Tag, TagUsage } from '../types';
 
 export const tauriApi = {
     loadConfig: async (): Promise<AppConfig> => {
@@ -10,7 +10,7 @@ export const tauriApi = {
         return await invoke('save_config', { config });
     },
 
-    scanWorkspace: async (path: string, maxDepth: number = 1): Promise<Project[]> => {
+    scanWorkspace: async (path: string, maxDepth?: number): Promise<ScanResult> => {
         return await invoke('scan_workspace', { path, maxDepth });
     },
 
@@ -30,18 +30,30 @@ export const tauriApi = {
         return await invoke('delete_project', { projectId });
     },
 
+    mergeProjects: async (keepId: string, mergeId: string): Promise<Project> => {
+        return await invoke('merge_projects', { keepId, mergeId });
+    },
+
     addTag: async (tag: Tag): Promise<void> => {
         return await invoke('add_tag', { tag });
     },
 
-    updateTag: async (tag: Tag): Promise<void> => {
+    updateTag: async (tag: Tag): Promise<Tag[]> => {
         return await invoke('update_tag', { tag });
     },
 
-    deleteTag: async (tagId: string): Promise<void> => {
+    renameTag: async (tagId: string, name: string): Promise<Tag[]> => {
+        return await invoke('rename_tag', { tagId, name });
+    },
+
+    deleteTag: async (tagId: string): Promise<Tag[]> => {
         return await invoke('delete_tag', { tagId });
     },
 
+    getTagUsage: async (includeProjects = false): Promise<Record<string, TagUsage>> => {
+        return await invoke('get_tag_usage', { includeProjects });
+    },
+
     launchTool: async (projectId: string): Promise<void> => {
         return await invoke('launch_tool', { projectId });
     },
@@ -50,6 +62,14 @@ export const tauriApi = {
         return await invoke('launch_custom', { projectId, config });
     },
 
+    listLaunchedProcesses: async (): Promise<LaunchedProcess[]> => {
+        return await invoke('list_launched_processes');
+    },
+
+    killLaunchedProcess: async (pid: number): Promise<void> => {
+        return await invoke('kill_launched_process', { pid });
+    },
+
     openInExplorer: async (path: string): Promise<void> => {
         return await invoke('open_in_explorer', { path });
     },
@@ -1,6 +1,6 @@
 import { create } from 'zustand';
 import { tauriApi } from '@/services/tauri';
-import { AppConfig, Project, Tag, Theme } from '@/types';
+import { AppConfig, Project, ScanResult, Tag, Theme } from '@/types';
 
 interface AppState {
     config: AppConfig | null;
@@ -15,7 +15,7 @@ interface AppState {
 
     addWorkspace: (name: string, path: string, autoScan: boolean) => Promise<void>;
     removeWorkspace: (id: string) => Promise<void>;
-    scanWorkspace: (path: string) => Promise<Project[]>;
+    scanWorkspace: (path: string) => Promise<ScanResult>;
 
     updateProject: (project: Project) => Promise<void>;
     deleteProject: (id: string) => Promise<void>;
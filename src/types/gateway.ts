@@ -118,3 +118,9 @@ export interface GatewayStats {
     recent_requests: RequestLog[];
     hourly_activity: HourlyStat[];
 }
+
+// 网关命令返回的结构化错误
+export interface GatewayError {
+    kind: 'bind' | 'server' | 'config' | 'not_found' | 'upstream' | 'conversion' | 'timeout' | 'invalid_request' | 'io';
+    message: string;
+}
@@ -51,12 +51,26 @@ export interface Project {
     metadata: ProjectMetadata;
 }
 
+export interface ScanResult {
+    projects: Project[];
+    warnings: string[];
+}
+
+export interface LaunchedProcess {
+    project_id: string;
+    config_name: string;
+    pid: number;
+    alive: boolean;
+}
+
 export interface Workspace {
     id: string;
     name: string;
     path: string;
     auto_scan: boolean;
     created_at: string;
+    scan_max_depth?: number;
+    scan_ignore?: string[];
 }
 
 export interface Tag {
@@ -67,6 +81,11 @@ export interface Tag {
     config?: TagConfig;
 }
 
+export interface TagUsage {
+    count: number;
+    project_ids?: string[];
+}
+
 export interface AppConfig {
     workspaces: Workspace[];
     tags: Tag[];